@@ -1,28 +1,130 @@
 use clap::{Parser, Subcommand};
-use luxnulla::{
-    CommandRequest, CommandResponse, ErrorCommandResponse, OkCommandResponse, SOCKET_NAME,
-};
-use std::{path::PathBuf, str::FromStr};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::UnixStream,
-};
+use luxnulla::config::RoutingPreset;
+use luxnulla::{CommandRequest, CommandResponse, LogLevel, TproxyAction};
+use output::Output;
+use std::io;
+use std::process::ExitCode;
+use std::str::FromStr;
 
+mod output;
+mod socket;
 mod ui;
 
+/// The daemon is not running or its socket cannot be reached.
+const EXIT_UNREACHABLE: u8 = 3;
+/// The daemon answered at least one response with an error.
+const EXIT_REQUEST_ERROR: u8 = 4;
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit status: 0 on success, 3 if the daemon cannot be reached, 4 if it reports an error."
+)]
 struct Args {
+    /// How to print responses: table, plain (tab-separated) or json (the daemon's responses as sent, one per line)
+    #[arg(short, long, global = true, default_value = "table")]
+    output: Output,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    Edit { target: EditTarget },
+    Edit {
+        target: EditTarget,
+    },
     Start,
     Status,
     Restart,
+    /// Re-download all subscriptions from luxnulla.kdl
+    Refresh,
+    Logs {
+        /// Keep the connection open and print new lines as they arrive
+        #[arg(short, long)]
+        follow: bool,
+        /// Number of recent lines to print first
+        #[arg(short = 'n', long, default_value_t = 100)]
+        lines: usize,
+        /// Minimum level to show: debug, info, warning or error
+        #[arg(short, long)]
+        level: Option<LogLevel>,
+    },
+    /// List nodes from the last subscription refresh
+    Nodes,
+    /// Show every parsed field, the share link and the latency history of a node
+    Show {
+        id: String,
+    },
+    /// Switch xray to the node with the given id
+    Select {
+        id: String,
+    },
+    /// Measure TCP connect and TLS handshake time of nodes (all by default)
+    Probe {
+        ids: Vec<String>,
+    },
+    /// Request the test URL through nodes via a temporary xray (all by default);
+    /// chain:<name> tests a whole chain
+    UrlTest {
+        ids: Vec<String>,
+    },
+    /// Print daemon events (automatic switches, failed health checks) as they happen
+    Events,
+    /// Mark a node as favourite
+    Favourite {
+        id: String,
+        /// Remove the node from favourites instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Add a tag to a node, for use in luxnulla.kdl group definitions
+    Tag {
+        id: String,
+        tag: String,
+        /// Remove the tag instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Balance traffic over the nodes of a group from luxnulla.kdl
+    SelectGroup {
+        name: String,
+    },
+    /// Route with presets instead of those in luxnulla.kdl: global, bypass-lan,
+    /// bypass-country:<code>, block-ads
+    Preset {
+        #[arg(required_unless_present = "reset")]
+        presets: Vec<RoutingPreset>,
+        /// Go back to the presets in luxnulla.kdl
+        #[arg(long, conflicts_with = "presets")]
+        reset: bool,
+    },
+    /// Send proxied traffic through a chain from luxnulla.kdl
+    SelectChain {
+        name: String,
+    },
+    /// List subscriptions with their traffic quota, expiry and last refresh
+    Subscriptions,
+    /// Add a subscription to luxnulla.kdl; fetch it with refresh
+    AddSubscription {
+        url: String,
+        /// Defaults to the host of the URL
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Remove a subscription from luxnulla.kdl together with the nodes only it listed
+    RemoveSubscription {
+        name: String,
+    },
+    /// Print the routing rules of the running xray config, in match order
+    Routing,
+    /// Traffic totals and current rates of the proxy and every inbound
+    Traffic,
+    /// Transparent proxy from the tproxy section of luxnulla.kdl: on, off or dry-run
+    Tproxy {
+        action: TproxyAction,
+    },
     Tui,
 }
 
@@ -48,30 +150,63 @@ impl FromStr for EditTarget {
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let args = Args::parse();
 
-    let mut sock = UnixStream::connect(PathBuf::from("/tmp").join(SOCKET_NAME)).await?;
-
-    let tui = match args.command {
-        Commands::Tui => {
-            ui::table::init();
-        }
-        _ => {}
-    };
+    if let Commands::Tui = args.command {
+        ui::init().await?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
+    let format = args.output;
     let cmd: CommandRequest = request_action(args);
 
-    let out = serde_json::to_vec(&cmd)?;
-    sock.write_all(&out).await?;
+    let mut lines = match socket::send(&cmd).await {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return Ok(ExitCode::from(EXIT_UNREACHABLE));
+        }
+    };
+    let mut answered = false;
+    let mut failed = false;
+    while let Some(line) = lines.next_line().await? {
+        let resp: CommandResponse = serde_json::from_str(&line)?;
+        answered = true;
+        failed |= matches!(resp, CommandResponse::Err(_));
+        match format {
+            // The daemon already speaks JSON, one response per line.
+            Output::Json => println!("{}", line),
+            Output::Plain => output::plain(resp, &mut io::stdout(), &mut io::stderr())?,
+            Output::Table => output::table(resp, &mut io::stdout(), &mut io::stderr())?,
+        }
+    }
 
-    let mut buf = vec![0u8; 1024];
-    let n = sock.read(&mut buf).await?;
+    if !answered && !is_stream(&cmd) {
+        eprintln!("Error: the daemon closed the connection without answering");
+    }
+    Ok(ExitCode::from(exit_status(&cmd, answered, failed)))
+}
 
-    let resp: CommandResponse = serde_json::from_slice(&buf[..n])?;
-    response_action(resp);
+/// The exit status once the daemon has closed the connection: an error if
+/// any response was one, or if a request that always gets an answer got none.
+fn exit_status(cmd: &CommandRequest, answered: bool, failed: bool) -> u8 {
+    if failed || (!answered && !is_stream(cmd)) {
+        EXIT_REQUEST_ERROR
+    } else {
+        0
+    }
+}
 
-    Ok(())
+/// Requests answered with any number of responses, possibly none.
+fn is_stream(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd,
+        CommandRequest::Logs { .. }
+            | CommandRequest::Events
+            | CommandRequest::Probe { .. }
+            | CommandRequest::UrlTest { .. }
+    )
 }
 
 fn request_action(args: Args) -> CommandRequest {
@@ -84,31 +219,73 @@ fn request_action(args: Args) -> CommandRequest {
         Commands::Start => CommandRequest::Start,
         Commands::Status => CommandRequest::Status,
         Commands::Restart => CommandRequest::Restart,
-        _ => {
-            eprintln!("Usage: client status|restart");
-            std::process::exit(1);
-        }
+        Commands::Refresh => CommandRequest::Refresh,
+        Commands::Logs {
+            follow,
+            lines,
+            level,
+        } => CommandRequest::Logs {
+            follow,
+            lines,
+            level,
+        },
+        Commands::Nodes => CommandRequest::ListNodes,
+        Commands::Show { id } => CommandRequest::NodeDetails { id },
+        Commands::Select { id } => CommandRequest::SelectNode { id },
+        Commands::Probe { ids } => CommandRequest::Probe { ids },
+        Commands::UrlTest { ids } => CommandRequest::UrlTest { ids },
+        Commands::Events => CommandRequest::Events,
+        Commands::Favourite { id, remove } => CommandRequest::SetFavourite {
+            id,
+            favourite: !remove,
+        },
+        Commands::Tag { id, tag, remove } => CommandRequest::SetTag {
+            id,
+            tag,
+            present: !remove,
+        },
+        Commands::SelectGroup { name } => CommandRequest::SelectGroup { name },
+        Commands::SelectChain { name } => CommandRequest::SelectChain { name },
+        Commands::Tproxy { action } => CommandRequest::Tproxy { action },
+        Commands::Subscriptions => CommandRequest::ListSubscriptions,
+        Commands::AddSubscription { url, name } => CommandRequest::AddSubscription { url, name },
+        Commands::RemoveSubscription { name } => CommandRequest::RemoveSubscription { name },
+        Commands::Routing => CommandRequest::Routing,
+        Commands::Traffic => CommandRequest::Traffic,
+        Commands::Preset { presets, reset } => CommandRequest::SetRoutingPreset {
+            presets: (!reset).then_some(presets),
+        },
+        Commands::Tui => unreachable!("the tui is not a single request"),
     }
 }
 
-fn response_action(res: CommandResponse) {
-    match res {
-        CommandResponse::Ok(res) => match res {
-            OkCommandResponse::Message(msg) => {
-                println!("Ok: {}", msg);
-            }
-            OkCommandResponse::GetSubs(msg) => {
-                // println!("Error: {}", msg);
-            }
-        },
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
 
-        CommandResponse::Err(res) => match res {
-            ErrorCommandResponse::Message(err) => {
-                println!("Error: {}", err);
-            }
-            ErrorCommandResponse::GetSubs(msg) => {
-                println!("Error: {}", msg);
-            }
-        },
+    #[test]
+    fn exit_status_of_answers() {
+        let status = CommandRequest::Status;
+        assert_eq!(exit_status(&status, true, false), 0);
+        assert_eq!(exit_status(&status, true, true), EXIT_REQUEST_ERROR);
+        assert_eq!(exit_status(&status, false, false), EXIT_REQUEST_ERROR);
+
+        // Streams may end without a response, but not with an error.
+        let test = CommandRequest::UrlTest { ids: Vec::new() };
+        assert_eq!(exit_status(&test, false, false), 0);
+        assert_eq!(exit_status(&test, true, false), 0);
+        assert_eq!(exit_status(&test, true, true), EXIT_REQUEST_ERROR);
+        assert_eq!(exit_status(&CommandRequest::Events, false, false), 0);
+    }
+
+    #[test]
+    fn help_lists_the_exit_codes() {
+        let help = Args::command().get_after_help().unwrap().to_string();
+        assert!(help.contains(&format!(
+            "{} if the daemon cannot be reached",
+            EXIT_UNREACHABLE
+        )));
+        assert!(help.contains(&format!("{} if it reports an error", EXIT_REQUEST_ERROR)));
     }
 }
@@ -0,0 +1,567 @@
+//! How the client prints daemon responses, chosen with `--output`.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use luxnulla::{CommandResponse, ErrorCommandResponse, OkCommandResponse, format_bytes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Aligned text for people.
+    Table,
+    /// One tab-separated record per line, for `cut` and `awk`.
+    Plain,
+    /// Every daemon response as one JSON object per line, exactly as the
+    /// daemon sends it: `{"Ok":{"Nodes":[...]}}` or `{"Err":{"Message":"..."}}`.
+    /// The variant names are part of the protocol and kept stable.
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Output::Table),
+            "plain" => Ok(Output::Plain),
+            "json" => Ok(Output::Json),
+            _ => Err(format!(
+                "unknown output format: {}. Expected table, plain or json.",
+                s
+            )),
+        }
+    }
+}
+
+/// Prints `res` as aligned text; errors go to `err`.
+pub fn table(res: CommandResponse, out: &mut impl Write, err: &mut impl Write) -> io::Result<()> {
+    let res = match res {
+        CommandResponse::Ok(res) => res,
+        CommandResponse::Err(ErrorCommandResponse::Message(e))
+        | CommandResponse::Err(ErrorCommandResponse::GetSubs(e)) => {
+            return writeln!(err, "Error: {}", e);
+        }
+    };
+
+    match res {
+        OkCommandResponse::Message(msg) => writeln!(out, "Ok: {}", msg)?,
+        // Only the TUI asks for its config.
+        OkCommandResponse::GetSubs(_) | OkCommandResponse::TuiConfig(_) => {}
+        OkCommandResponse::Log(log) => writeln!(out, "{}", log.line)?,
+        OkCommandResponse::Refreshed(report) => writeln!(out, "Ok: {}", report)?,
+        OkCommandResponse::Probe { id, result } => match result.error {
+            Some(e) => writeln!(out, "{} failed: {}", id, e)?,
+            None => writeln!(
+                out,
+                "{} tcp {}ms{}",
+                id,
+                result.tcp_ms.unwrap_or_default(),
+                result
+                    .tls_ms
+                    .map(|ms| format!(" tls {}ms", ms))
+                    .unwrap_or_default()
+            )?,
+        },
+        OkCommandResponse::UrlTest { id, result } => match result.latency_ms {
+            Some(ms) => writeln!(
+                out,
+                "{} {}ms (HTTP {})",
+                id,
+                ms,
+                result.status.unwrap_or_default()
+            )?,
+            None => writeln!(
+                out,
+                "{} failed: {}",
+                id,
+                result
+                    .error
+                    .unwrap_or_else(|| String::from("unknown error"))
+            )?,
+        },
+        OkCommandResponse::Event(event) => writeln!(out, "{}", event)?,
+        OkCommandResponse::NodeDetails(details) => {
+            writeln!(out, "{} ({})", details.info.name, details.info.id)?;
+            let width = details
+                .fields
+                .iter()
+                .map(|(label, _)| label.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (label, value) in &details.fields {
+                writeln!(out, "  {:<width$}  {}", label, value)?;
+            }
+            writeln!(
+                out,
+                "subscriptions: {}",
+                details.info.subscriptions.join(", ")
+            )?;
+            writeln!(out, "uri: {}", details.uri)?;
+            if !details.latency_history.is_empty() {
+                writeln!(out, "latency history:")?;
+            }
+            for sample in &details.latency_history {
+                writeln!(
+                    out,
+                    "  {} {:<7} {}",
+                    sample.at.format("%Y-%m-%d %H:%M:%S"),
+                    sample.kind,
+                    sample
+                        .latency_ms
+                        .map(|ms| format!("{}ms", ms))
+                        .unwrap_or_else(|| String::from("failed"))
+                )?;
+            }
+        }
+        OkCommandResponse::Status(status) => {
+            writeln!(out, "Ok: Luxnulla-core is running")?;
+            match status.xray_pid {
+                Some(pid) => writeln!(out, "xray: running (pid {})", pid)?,
+                None => writeln!(out, "xray: not running")?,
+            }
+            match (
+                &status.selected_node,
+                &status.selected_group,
+                &status.selected_chain,
+            ) {
+                (Some(node), _, _) => writeln!(out, "active: node {} ({})", node.name, node.id)?,
+                (_, Some(group), _) => writeln!(out, "active: group {}", group)?,
+                (_, _, Some(chain)) => writeln!(out, "active: chain {}", chain)?,
+                _ => writeln!(out, "active: none")?,
+            }
+            writeln!(
+                out,
+                "nodes: {} from {} subscriptions",
+                status.nodes, status.subscriptions
+            )?;
+            writeln!(
+                out,
+                "routing presets: {}",
+                if status.routing_presets.is_empty() {
+                    String::from("none")
+                } else {
+                    status
+                        .routing_presets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            )?;
+            writeln!(out, "tproxy: {}", if status.tproxy { "on" } else { "off" })?;
+        }
+        OkCommandResponse::Subscriptions(subs) => {
+            for sub in subs {
+                writeln!(
+                    out,
+                    "{} {} nodes, {}, expires {}, updated {}",
+                    sub.name,
+                    sub.nodes,
+                    sub.usage
+                        .as_ref()
+                        .map(|usage| format!(
+                            "{} of {} used",
+                            format_bytes(usage.used()),
+                            if usage.total == 0 {
+                                String::from("unlimited")
+                            } else {
+                                format_bytes(usage.total)
+                            }
+                        ))
+                        .unwrap_or_else(|| String::from("no quota reported")),
+                    sub.usage
+                        .and_then(|usage| usage.expire)
+                        .map(|at| at.format("%Y-%m-%d").to_string())
+                        .unwrap_or_else(|| String::from("never")),
+                    sub.updated
+                        .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| String::from("never")),
+                )?;
+                writeln!(out, "  {}", sub.url)?;
+                if let Some(error) = sub.error {
+                    writeln!(out, "  last refresh failed: {}", error)?;
+                }
+            }
+        }
+        OkCommandResponse::Routing(routing) => {
+            if let Some(strategy) = routing.domain_strategy {
+                writeln!(out, "domain strategy: {}", strategy)?;
+            }
+            for (i, rule) in routing.rules.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{:>3}. -> {}{}",
+                    i + 1,
+                    rule.target,
+                    rule.tag
+                        .as_ref()
+                        .map(|tag| format!(" ({})", tag))
+                        .unwrap_or_default()
+                )?;
+                for condition in &rule.conditions {
+                    writeln!(out, "       {}", condition)?;
+                }
+            }
+        }
+        OkCommandResponse::Traffic(stats) => {
+            for series in std::iter::once(&stats.proxy).chain(&stats.inbounds) {
+                let rate = match series.rate() {
+                    Some((up, down)) => {
+                        format!("↑ {}/s ↓ {}/s", format_bytes(up), format_bytes(down))
+                    }
+                    None => String::from("rate pending"),
+                };
+                writeln!(
+                    out,
+                    "{:<16} ↑ {:>10} ↓ {:>10}   {}",
+                    series.tag,
+                    format_bytes(series.uplink),
+                    format_bytes(series.downlink),
+                    rate
+                )?;
+            }
+        }
+        OkCommandResponse::Nodes(nodes) => {
+            for node in nodes {
+                writeln!(
+                    out,
+                    "{}{} {} {:<12} {:>6} {}:{} {} [{}]{}",
+                    if node.active { "*" } else { " " },
+                    if node.favourite { "★" } else { " " },
+                    node.id,
+                    node.protocol,
+                    node.latency_ms
+                        .map(|ms| format!("{}ms", ms))
+                        .unwrap_or_else(|| String::from("-")),
+                    node.address,
+                    node.port,
+                    node.name,
+                    node.subscriptions.join(", "),
+                    node.tags
+                        .iter()
+                        .map(|t| format!(" #{}", t))
+                        .collect::<String>()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints `res` as tab-separated records; missing values are empty fields.
+pub fn plain(res: CommandResponse, out: &mut impl Write, err: &mut impl Write) -> io::Result<()> {
+    let res = match res {
+        CommandResponse::Ok(res) => res,
+        CommandResponse::Err(ErrorCommandResponse::Message(e))
+        | CommandResponse::Err(ErrorCommandResponse::GetSubs(e)) => {
+            return writeln!(err, "{}", e);
+        }
+    };
+
+    match res {
+        OkCommandResponse::Message(msg) => writeln!(out, "{}", msg)?,
+        OkCommandResponse::GetSubs(_) | OkCommandResponse::TuiConfig(_) => {}
+        OkCommandResponse::Log(log) => writeln!(out, "{}", log.line)?,
+        OkCommandResponse::Event(event) => writeln!(out, "{}", event)?,
+        OkCommandResponse::Refreshed(report) => {
+            for sub in report.subscriptions {
+                record(out, [sub.name, sub.nodes.to_string(), or_empty(sub.error)])?;
+            }
+        }
+        OkCommandResponse::Probe { id, result } => record(
+            out,
+            [
+                id,
+                or_empty(result.tcp_ms),
+                or_empty(result.tls_ms),
+                or_empty(result.error),
+            ],
+        )?,
+        OkCommandResponse::UrlTest { id, result } => record(
+            out,
+            [
+                id,
+                or_empty(result.latency_ms),
+                or_empty(result.status),
+                or_empty(result.error),
+            ],
+        )?,
+        OkCommandResponse::NodeDetails(details) => {
+            record(out, ["id".to_string(), details.info.id])?;
+            record(out, ["name".to_string(), details.info.name])?;
+            for (label, value) in details.fields {
+                record(out, [label, value])?;
+            }
+            record(
+                out,
+                [
+                    "subscriptions".to_string(),
+                    details.info.subscriptions.join(","),
+                ],
+            )?;
+            record(out, ["uri".to_string(), details.uri])?;
+            for sample in details.latency_history {
+                record(
+                    out,
+                    [
+                        "latency".to_string(),
+                        sample.at.to_rfc3339(),
+                        sample.kind.to_string(),
+                        or_empty(sample.latency_ms),
+                    ],
+                )?;
+            }
+        }
+        OkCommandResponse::Status(status) => {
+            let active = match (
+                status.selected_node,
+                status.selected_group,
+                status.selected_chain,
+            ) {
+                (Some(node), _, _) => format!("node:{}", node.id),
+                (_, Some(group), _) => format!("group:{}", group),
+                (_, _, Some(chain)) => format!("chain:{}", chain),
+                _ => String::new(),
+            };
+            record(out, ["xray-pid".to_string(), or_empty(status.xray_pid)])?;
+            record(out, ["active".to_string(), active])?;
+            record(out, ["nodes".to_string(), status.nodes.to_string()])?;
+            record(
+                out,
+                [
+                    "subscriptions".to_string(),
+                    status.subscriptions.to_string(),
+                ],
+            )?;
+            record(
+                out,
+                [
+                    "routing-presets".to_string(),
+                    status
+                        .routing_presets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ],
+            )?;
+            record(
+                out,
+                [
+                    "tproxy".to_string(),
+                    if status.tproxy { "on" } else { "off" }.to_string(),
+                ],
+            )?;
+        }
+        OkCommandResponse::Subscriptions(subs) => {
+            for sub in subs {
+                record(
+                    out,
+                    [
+                        sub.name,
+                        sub.nodes.to_string(),
+                        or_empty(sub.usage.as_ref().map(|usage| usage.used())),
+                        or_empty(sub.usage.as_ref().map(|usage| usage.total)),
+                        or_empty(
+                            sub.usage
+                                .and_then(|usage| usage.expire)
+                                .map(|at| at.to_rfc3339()),
+                        ),
+                        or_empty(sub.updated.map(|at| at.to_rfc3339())),
+                        sub.url,
+                        or_empty(sub.error),
+                    ],
+                )?;
+            }
+        }
+        OkCommandResponse::Routing(routing) => {
+            for (i, rule) in routing.rules.into_iter().enumerate() {
+                record(
+                    out,
+                    [
+                        (i + 1).to_string(),
+                        rule.target,
+                        or_empty(rule.tag),
+                        rule.conditions.join("; "),
+                    ],
+                )?;
+            }
+        }
+        OkCommandResponse::Traffic(stats) => {
+            for series in std::iter::once(&stats.proxy).chain(&stats.inbounds) {
+                let rate = series.rate();
+                record(
+                    out,
+                    [
+                        series.tag.clone(),
+                        series.uplink.to_string(),
+                        series.downlink.to_string(),
+                        or_empty(rate.map(|(up, _)| up)),
+                        or_empty(rate.map(|(_, down)| down)),
+                    ],
+                )?;
+            }
+        }
+        OkCommandResponse::Nodes(nodes) => {
+            for node in nodes {
+                record(
+                    out,
+                    [
+                        node.id,
+                        node.name,
+                        node.protocol,
+                        node.address,
+                        node.port.to_string(),
+                        or_empty(node.latency_ms),
+                        flag(node.active),
+                        flag(node.favourite),
+                        node.subscriptions.join(","),
+                        node.tags.join(","),
+                    ],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn record<const N: usize>(out: &mut impl Write, fields: [String; N]) -> io::Result<()> {
+    writeln!(out, "{}", fields.join("\t"))
+}
+
+fn or_empty(value: Option<impl ToString>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn flag(value: bool) -> String {
+    String::from(if value { "1" } else { "0" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use luxnulla::UrlTestResult;
+
+    type Render = fn(CommandResponse, &mut Vec<u8>, &mut Vec<u8>) -> io::Result<()>;
+
+    /// What `render` prints for the response `json` on stdout and stderr.
+    fn render(render: Render, json: &str) -> (String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        render(serde_json::from_str(json).unwrap(), &mut out, &mut err).unwrap();
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    const NODES: &str = r#"{"Ok":{"Nodes":[
+        {"id":"ab12","protocol":"vless","address":"example.com","port":443,"name":"Frankfurt",
+         "active":true,"favourite":false,"tags":["work"],"subscriptions":["main","backup"],
+         "latency_ms":120},
+        {"id":"cd34","protocol":"trojan","address":"10.0.0.2","port":8443,"name":"Backup",
+         "active":false,"favourite":true,"subscriptions":["main"],"latency_ms":null}
+    ]}}"#;
+
+    const STATUS: &str = r#"{"Ok":{"Status":{"xray_pid":4242,"selected_node":null,
+        "selected_group":"europe","selected_chain":null,"nodes":12,"subscriptions":2,
+        "routing_presets":["BypassLan",{"BypassCountry":"ru"}],"tproxy":false}}}"#;
+
+    const URL_TESTS: [&str; 2] = [
+        r#"{"Ok":{"UrlTest":{"id":"ab12","result":{"latency_ms":250,"status":204,"error":null}}}}"#,
+        r#"{"Ok":{"UrlTest":{"id":"cd34","result":{"latency_ms":null,"status":null,"error":"timed out"}}}}"#,
+    ];
+
+    const TRAFFIC: &str = r#"{"Ok":{"Traffic":{"interval_ms":1000,
+        "proxy":{"tag":"proxy","uplink":2048,"downlink":5242880,
+                 "uplink_rates":[10,512],"downlink_rates":[20,4096]},
+        "inbounds":[{"tag":"in:socks","uplink":100,"downlink":0,
+                     "uplink_rates":[],"downlink_rates":[]}]}}}"#;
+
+    const ERROR: &str = r#"{"Err":{"Message":"unknown node id: ef56"}}"#;
+
+    #[test]
+    fn json_is_the_wire_format() {
+        let response = CommandResponse::Ok(OkCommandResponse::UrlTest {
+            id: String::from("ab12"),
+            result: UrlTestResult {
+                latency_ms: Some(250),
+                status: Some(204),
+                error: None,
+            },
+        });
+        assert_eq!(serde_json::to_string(&response).unwrap(), URL_TESTS[0]);
+
+        let response = CommandResponse::Err(ErrorCommandResponse::Message(String::from(
+            "unknown node id: ef56",
+        )));
+        assert_eq!(serde_json::to_string(&response).unwrap(), ERROR);
+    }
+
+    #[test]
+    fn plain_records() {
+        let (out, err) = render(plain, NODES);
+        assert_eq!(
+            out,
+            "ab12\tFrankfurt\tvless\texample.com\t443\t120\t1\t0\tmain,backup\twork\n\
+             cd34\tBackup\ttrojan\t10.0.0.2\t8443\t\t0\t1\tmain\t\n"
+        );
+        assert!(err.is_empty());
+
+        let (out, _) = render(plain, STATUS);
+        assert_eq!(
+            out,
+            "xray-pid\t4242\nactive\tgroup:europe\nnodes\t12\nsubscriptions\t2\n\
+             routing-presets\tbypass-lan,bypass-country:ru\ntproxy\toff\n"
+        );
+
+        assert_eq!(render(plain, URL_TESTS[0]).0, "ab12\t250\t204\t\n");
+        assert_eq!(render(plain, URL_TESTS[1]).0, "cd34\t\t\ttimed out\n");
+
+        let (out, _) = render(plain, TRAFFIC);
+        assert_eq!(
+            out,
+            "proxy\t2048\t5242880\t512\t4096\nin:socks\t100\t0\t\t\n"
+        );
+
+        // Errors carry no prefix and stay off stdout.
+        let (out, err) = render(plain, ERROR);
+        assert!(out.is_empty());
+        assert_eq!(err, "unknown node id: ef56\n");
+    }
+
+    #[test]
+    fn table_text() {
+        let (out, err) = render(table, NODES);
+        assert_eq!(
+            out,
+            "*  ab12 vless         120ms example.com:443 Frankfurt [main, backup] #work\n \
+             ★ cd34 trojan            - 10.0.0.2:8443 Backup [main]\n"
+        );
+        assert!(err.is_empty());
+
+        let (out, _) = render(table, STATUS);
+        assert_eq!(
+            out,
+            "Ok: Luxnulla-core is running\n\
+             xray: running (pid 4242)\n\
+             active: group europe\n\
+             nodes: 12 from 2 subscriptions\n\
+             routing presets: bypass-lan, bypass-country:ru\n\
+             tproxy: off\n"
+        );
+
+        assert_eq!(render(table, URL_TESTS[0]).0, "ab12 250ms (HTTP 204)\n");
+        assert_eq!(render(table, URL_TESTS[1]).0, "cd34 failed: timed out\n");
+
+        let (out, _) = render(table, TRAFFIC);
+        assert_eq!(
+            out,
+            "proxy            ↑    2.0 KiB ↓    5.0 MiB   ↑ 512 B/s ↓ 4.0 KiB/s\n\
+             in:socks         ↑      100 B ↓        0 B   rate pending\n"
+        );
+
+        let (out, err) = render(table, ERROR);
+        assert!(out.is_empty());
+        assert_eq!(err, "Error: unknown node id: ef56\n");
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::{Context, anyhow};
+use luxnulla::{CommandRequest, CommandResponse, SOCKET_NAME};
+use std::path::PathBuf;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::UnixStream,
+};
+
+/// Sends `cmd` to the daemon and returns its response lines; streaming
+/// requests keep producing lines until the daemon closes the connection.
+pub async fn send(cmd: &CommandRequest) -> anyhow::Result<Lines<BufReader<UnixStream>>> {
+    let path = PathBuf::from("/tmp").join(SOCKET_NAME);
+    let mut sock = UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("cannot reach the daemon at {}", path.display()))?;
+
+    let mut out = serde_json::to_vec(cmd)?;
+    out.push(b'\n');
+    sock.write_all(&out).await?;
+
+    Ok(BufReader::new(sock).lines())
+}
+
+/// Sends a request that is answered with a single response.
+pub async fn request(cmd: &CommandRequest) -> anyhow::Result<CommandResponse> {
+    let line = send(cmd)
+        .await?
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("the daemon closed the connection without answering"))?;
+
+    Ok(serde_json::from_str(&line)?)
+}
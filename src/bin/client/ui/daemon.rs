@@ -0,0 +1,311 @@
+//! Runs daemon requests on the tokio runtime for the blocking terminal loop,
+//! which drains their results from a channel between frames.
+
+use std::sync::mpsc::Sender;
+
+use anyhow::anyhow;
+use luxnulla::config::TuiConfig;
+use luxnulla::{
+    CommandRequest, CommandResponse, DaemonEvent, DaemonStatus, ErrorCommandResponse, LogLine,
+    NodeDetails, NodeInfo, OkCommandResponse, RefreshReport, RoutingInfo, SubscriptionInfo,
+    TrafficStats, UrlTestResult,
+};
+use tokio::{runtime::Handle, task::AbortHandle};
+
+use crate::socket;
+
+pub enum Update {
+    Nodes(Vec<NodeInfo>),
+    Event(DaemonEvent),
+    /// Answer to `Daemon::select_node`.
+    Selected {
+        id: String,
+        result: Result<String, String>,
+    },
+    /// Answer to `Daemon::set_favourite`.
+    Favourite {
+        id: String,
+        favourite: bool,
+        result: Result<String, String>,
+    },
+    /// Answer to `Daemon::node_details`.
+    Details {
+        id: String,
+        result: Result<NodeDetails, String>,
+    },
+    /// One result of `Daemon::url_test`.
+    Tested {
+        id: String,
+        result: UrlTestResult,
+    },
+    /// `Daemon::url_test` has no more results.
+    TestFinished(Result<(), String>),
+    Subscriptions(Result<Vec<SubscriptionInfo>, String>),
+    /// Answer to `Daemon::add_subscription` or `Daemon::remove_subscription`.
+    SubscriptionChanged(Result<String, String>),
+    Refreshed(Result<RefreshReport, String>),
+    Status(Result<DaemonStatus, String>),
+    Routing(Result<RoutingInfo, String>),
+    Traffic(Result<TrafficStats, String>),
+    /// One line of `Daemon::follow_logs`.
+    Log(LogLine),
+    /// The log stream ended, with the reason.
+    LogsClosed(String),
+    /// The daemon could not be reached or answered with an error.
+    Error(String),
+}
+
+#[derive(Clone)]
+pub struct Daemon {
+    runtime: Handle,
+    updates: Sender<Update>,
+}
+
+impl Daemon {
+    /// Must be called from within the tokio runtime.
+    pub fn new(updates: Sender<Update>) -> Self {
+        Self {
+            runtime: Handle::current(),
+            updates,
+        }
+    }
+
+    /// Reloads the node list in the background.
+    pub fn load_nodes(&self) {
+        let updates = self.updates.clone();
+        self.runtime.spawn(async move {
+            let update = match list_nodes().await {
+                Ok(nodes) => Update::Nodes(nodes),
+                Err(e) => Update::Error(e.to_string()),
+            };
+            let _ = updates.send(update);
+        });
+    }
+
+    /// Asks the daemon to switch xray to node `id`.
+    pub fn select_node(&self, id: String) {
+        let request = CommandRequest::SelectNode { id: id.clone() };
+        self.request(request, move |result| Update::Selected {
+            id,
+            result: result.and_then(message),
+        });
+    }
+
+    /// Adds node `id` to the favourites or removes it.
+    pub fn set_favourite(&self, id: String, favourite: bool) {
+        let request = CommandRequest::SetFavourite {
+            id: id.clone(),
+            favourite,
+        };
+        self.request(request, move |result| Update::Favourite {
+            id,
+            favourite,
+            result: result.and_then(message),
+        });
+    }
+
+    pub fn node_details(&self, id: String) {
+        let request = CommandRequest::NodeDetails { id: id.clone() };
+        self.request(request, move |result| Update::Details {
+            id,
+            result: result.and_then(|response| match response {
+                OkCommandResponse::NodeDetails(details) => Ok(details),
+                _ => Err(unexpected()),
+            }),
+        });
+    }
+
+    pub fn list_subscriptions(&self) {
+        self.request(CommandRequest::ListSubscriptions, |result| {
+            Update::Subscriptions(result.and_then(|response| match response {
+                OkCommandResponse::Subscriptions(subs) => Ok(subs),
+                _ => Err(unexpected()),
+            }))
+        });
+    }
+
+    pub fn add_subscription(&self, url: String, name: Option<String>) {
+        self.request(CommandRequest::AddSubscription { url, name }, |result| {
+            Update::SubscriptionChanged(result.and_then(message))
+        });
+    }
+
+    pub fn remove_subscription(&self, name: String) {
+        self.request(CommandRequest::RemoveSubscription { name }, |result| {
+            Update::SubscriptionChanged(result.and_then(message))
+        });
+    }
+
+    /// Re-downloads every subscription.
+    pub fn refresh(&self) {
+        self.request(CommandRequest::Refresh, |result| {
+            Update::Refreshed(result.and_then(|response| match response {
+                OkCommandResponse::Refreshed(report) => Ok(report),
+                _ => Err(unexpected()),
+            }))
+        });
+    }
+
+    pub fn status(&self) {
+        self.request(CommandRequest::Status, |result| {
+            Update::Status(result.and_then(|response| match response {
+                OkCommandResponse::Status(status) => Ok(*status),
+                _ => Err(unexpected()),
+            }))
+        });
+    }
+
+    pub fn routing(&self) {
+        self.request(CommandRequest::Routing, |result| {
+            Update::Routing(result.and_then(|response| match response {
+                OkCommandResponse::Routing(routing) => Ok(routing),
+                _ => Err(unexpected()),
+            }))
+        });
+    }
+
+    pub fn traffic(&self) {
+        self.request(CommandRequest::Traffic, |result| {
+            Update::Traffic(result.and_then(|response| match response {
+                OkCommandResponse::Traffic(stats) => Ok(stats),
+                _ => Err(unexpected()),
+            }))
+        });
+    }
+
+    /// Sends the last `lines` lines of the xray log, then new ones as xray
+    /// writes them.
+    pub fn follow_logs(&self, lines: usize) {
+        let updates = self.updates.clone();
+        self.runtime.spawn(async move {
+            let result = async {
+                let request = CommandRequest::Logs {
+                    follow: true,
+                    lines,
+                    level: None,
+                };
+                let mut lines = socket::send(&request).await?;
+                while let Some(line) = lines.next_line().await? {
+                    match serde_json::from_str(&line)? {
+                        // Fails once the terminal loop is gone.
+                        CommandResponse::Ok(OkCommandResponse::Log(line)) => {
+                            updates.send(Update::Log(line))?
+                        }
+                        CommandResponse::Err(ErrorCommandResponse::Message(e)) => {
+                            return Err(anyhow!(e));
+                        }
+                        _ => {}
+                    }
+                }
+                anyhow::Ok(())
+            }
+            .await;
+
+            let _ = updates.send(Update::LogsClosed(match result {
+                Ok(()) => String::from("the daemon closed the log stream"),
+                Err(e) => format!("log stream: {}", e),
+            }));
+        });
+    }
+
+    /// Streams URL test results of `ids` (every node if empty). Aborting the
+    /// returned handle closes the connection, which stops the test.
+    pub fn url_test(&self, ids: Vec<String>) -> AbortHandle {
+        let updates = self.updates.clone();
+        self.runtime
+            .spawn(async move {
+                let result = async {
+                    let mut lines = socket::send(&CommandRequest::UrlTest { ids }).await?;
+                    while let Some(line) = lines.next_line().await? {
+                        match serde_json::from_str(&line)? {
+                            CommandResponse::Ok(OkCommandResponse::UrlTest { id, result }) => {
+                                let _ = updates.send(Update::Tested { id, result });
+                            }
+                            CommandResponse::Err(ErrorCommandResponse::Message(e)) => {
+                                return Err(anyhow!(e));
+                            }
+                            _ => {}
+                        }
+                    }
+                    anyhow::Ok(())
+                }
+                .await;
+
+                let _ = updates.send(Update::TestFinished(result.map_err(|e| e.to_string())));
+            })
+            .abort_handle()
+    }
+
+    /// Forwards daemon events until the daemon closes the stream.
+    pub fn watch_events(&self) {
+        let updates = self.updates.clone();
+        self.runtime.spawn(async move {
+            let result = async {
+                let mut lines = socket::send(&CommandRequest::Events).await?;
+                while let Some(line) = lines.next_line().await? {
+                    if let CommandResponse::Ok(OkCommandResponse::Event(event)) =
+                        serde_json::from_str(&line)?
+                        && updates.send(Update::Event(event)).is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+                anyhow::Ok(())
+            }
+            .await;
+
+            let _ = updates.send(Update::Error(match result {
+                Ok(()) => String::from("the daemon closed the event stream"),
+                Err(e) => format!("event stream: {}", e),
+            }));
+        });
+    }
+
+    /// Sends a request answered with a single response in the background
+    /// and passes what `update` makes of it to the terminal loop.
+    fn request(
+        &self,
+        cmd: CommandRequest,
+        update: impl FnOnce(Result<OkCommandResponse, String>) -> Update + Send + 'static,
+    ) {
+        let updates = self.updates.clone();
+        self.runtime.spawn(async move {
+            let result = match socket::request(&cmd).await {
+                Ok(CommandResponse::Ok(response)) => Ok(response),
+                Ok(CommandResponse::Err(
+                    ErrorCommandResponse::Message(e) | ErrorCommandResponse::GetSubs(e),
+                )) => Err(e),
+                Err(e) => Err(e.to_string()),
+            };
+            let _ = updates.send(update(result));
+        });
+    }
+}
+
+pub async fn list_nodes() -> anyhow::Result<Vec<NodeInfo>> {
+    match socket::request(&CommandRequest::ListNodes).await? {
+        CommandResponse::Ok(OkCommandResponse::Nodes(nodes)) => Ok(nodes),
+        CommandResponse::Err(ErrorCommandResponse::Message(e)) => Err(anyhow!(e)),
+        _ => Err(anyhow!("unexpected response to ListNodes")),
+    }
+}
+
+pub async fn tui_config() -> anyhow::Result<TuiConfig> {
+    match socket::request(&CommandRequest::TuiConfig).await? {
+        CommandResponse::Ok(OkCommandResponse::TuiConfig(config)) => Ok(config),
+        CommandResponse::Err(ErrorCommandResponse::Message(e)) => Err(anyhow!(e)),
+        _ => Err(anyhow!("unexpected response to TuiConfig")),
+    }
+}
+
+/// Unpacks a response that is a plain message.
+fn message(response: OkCommandResponse) -> Result<String, String> {
+    match response {
+        OkCommandResponse::Message(msg) => Ok(msg),
+        _ => Err(unexpected()),
+    }
+}
+
+fn unexpected() -> String {
+    String::from("unexpected response from the daemon")
+}
@@ -0,0 +1,87 @@
+//! Keys of the dashboard, from the `tui` section of luxnulla.kdl.
+
+use std::sync::OnceLock;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use luxnulla::config::{Key, KeyBinding, TuiAction, TuiConfig};
+
+/// Actions with a description, as one entry of the help bar.
+pub type Help = [(&'static [TuiAction], &'static str)];
+
+pub struct Keymap {
+    keys: Vec<(TuiAction, Vec<KeyBinding>)>,
+}
+
+static KEYMAP: OnceLock<Keymap> = OnceLock::new();
+
+/// The keymap set by `init`, or the default one before that.
+pub fn keymap() -> &'static Keymap {
+    KEYMAP.get_or_init(|| Keymap::new(&TuiConfig::default()))
+}
+
+/// Sets the keymap for the rest of the session; only the first call counts.
+pub fn init(config: &TuiConfig) {
+    let _ = KEYMAP.set(Keymap::new(config));
+}
+
+impl Keymap {
+    fn new(config: &TuiConfig) -> Self {
+        Self {
+            keys: config.keys.clone(),
+        }
+    }
+
+    /// Every action bound to `key`. Tabs take the first one they know.
+    pub fn actions(&self, key: KeyEvent) -> Vec<TuiAction> {
+        let Some(binding) = binding(key) else {
+            return Vec::new();
+        };
+        self.keys
+            .iter()
+            .filter(|(_, keys)| keys.contains(&binding))
+            .map(|(action, _)| *action)
+            .collect()
+    }
+
+    /// The first key of each action joined with `/`, such as `↑/↓`.
+    pub fn label(&self, actions: &[TuiAction]) -> String {
+        actions
+            .iter()
+            .filter_map(|action| {
+                self.keys
+                    .iter()
+                    .find(|(a, _)| a == action)
+                    .and_then(|(_, keys)| keys.first())
+                    .map(ToString::to_string)
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// The binding a key press matches. Shift only changes the character.
+fn binding(event: KeyEvent) -> Option<KeyBinding> {
+    let key = match event.code {
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::BackTab => Key::BackTab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::F(n) => Key::F(n),
+        _ => return None,
+    };
+    Some(KeyBinding {
+        key,
+        ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+    })
+}
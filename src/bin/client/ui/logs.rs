@@ -0,0 +1,201 @@
+//! The Logs tab: xray output as the daemon captures it.
+
+use std::collections::VecDeque;
+
+use crossterm::event::{MouseEvent, MouseEventKind};
+use ratatui::{
+    Frame,
+    layout::{Alignment, Rect},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+};
+
+use luxnulla::config::TuiAction;
+use luxnulla::{LogLevel, LogLine};
+
+use super::daemon::{Daemon, Update};
+use super::keymap::{Help, keymap};
+use super::theme::theme;
+use super::{WHEEL_ROWS, message};
+
+pub const HELP: &Help = &[
+    (&[TuiAction::Up, TuiAction::Down], "scroll"),
+    (&[TuiAction::PageUp, TuiAction::PageDown], "page"),
+    (&[TuiAction::First, TuiAction::Last], "oldest/follow"),
+    (&[TuiAction::Level], "level"),
+    (&[TuiAction::Reload], "reconnect"),
+];
+
+/// Lines kept in memory; older ones are dropped.
+const MAX_LINES: usize = 5000;
+/// Recent lines asked for when the stream starts.
+const BACKLOG: usize = 500;
+
+pub struct LogView {
+    lines: VecDeque<LogLine>,
+    daemon: Daemon,
+    /// The log stream is open.
+    streaming: bool,
+    /// Lines below this level are hidden.
+    level: LogLevel,
+    /// Index into the shown lines of the top row, `None` to follow new lines.
+    top: Option<usize>,
+    /// Rows of the last render.
+    height: usize,
+    status: Option<Line<'static>>,
+}
+
+impl LogView {
+    pub fn new(daemon: Daemon) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            daemon,
+            streaming: false,
+            level: LogLevel::Debug,
+            top: None,
+            height: 10,
+            status: None,
+        }
+    }
+
+    /// Opens the log stream unless it already is.
+    pub fn start(&mut self) {
+        if self.streaming {
+            return;
+        }
+        self.streaming = true;
+        self.lines.clear();
+        self.top = None;
+        self.status = Some(message("following the xray log", theme().info));
+        self.daemon.follow_logs(BACKLOG);
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Log(line) => {
+                if self.lines.len() == MAX_LINES {
+                    self.lines.pop_front();
+                    if line.level >= self.level {
+                        self.top = self.top.map(|top| top.saturating_sub(1));
+                    }
+                }
+                self.lines.push_back(line);
+            }
+            Update::LogsClosed(reason) => {
+                self.streaming = false;
+                self.status = Some(message(
+                    format!(
+                        "{}, press {} to reconnect",
+                        reason,
+                        keymap().label(&[TuiAction::Reload])
+                    ),
+                    theme().error,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    fn shown(&self) -> impl Iterator<Item = &LogLine> {
+        self.lines.iter().filter(|line| line.level >= self.level)
+    }
+
+    /// Top row when following: the last screenful.
+    fn bottom(&self) -> usize {
+        self.shown().count().saturating_sub(self.height)
+    }
+
+    /// Scrolls by `delta` rows; reaching the end follows new lines again.
+    fn scroll(&mut self, delta: isize) {
+        let bottom = self.bottom();
+        let top = self
+            .top
+            .unwrap_or(bottom)
+            .saturating_add_signed(delta)
+            .min(bottom);
+        self.top = (top < bottom).then_some(top);
+    }
+
+    pub fn handle_key(&mut self, actions: &[TuiAction]) {
+        let page = self.height.max(1) as isize;
+        // Первое действие, которое знает вкладка
+        for action in actions {
+            match action {
+                TuiAction::Up => self.scroll(-1),
+                TuiAction::Down => self.scroll(1),
+                TuiAction::PageUp => self.scroll(-page),
+                TuiAction::PageDown => self.scroll(page),
+                TuiAction::First => self.top = (self.bottom() > 0).then_some(0),
+                TuiAction::Last => self.top = None,
+                TuiAction::Level => {
+                    self.level = match self.level {
+                        LogLevel::Debug => LogLevel::Info,
+                        LogLevel::Info => LogLevel::Warning,
+                        LogLevel::Warning => LogLevel::Error,
+                        LogLevel::Error => LogLevel::Debug,
+                    };
+                    self.top = None;
+                }
+                TuiAction::Reload => self.start(),
+                _ => continue,
+            }
+            return;
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.scroll(-WHEEL_ROWS),
+            MouseEventKind::ScrollDown => self.scroll(WHEEL_ROWS),
+            _ => {}
+        }
+    }
+
+    pub fn status_line(&self) -> Option<Line<'static>> {
+        self.status.clone()
+    }
+
+    pub fn render(&mut self, f: &mut Frame, area: Rect) {
+        self.height = area.height.saturating_sub(2) as usize;
+        let top = self.top.unwrap_or_else(|| self.bottom());
+
+        let lines: Vec<Line> = self
+            .shown()
+            .skip(top)
+            .take(self.height)
+            .map(|line| {
+                Line::from(Span::styled(
+                    line.line.clone(),
+                    Style::default().fg(level_color(line.level)),
+                ))
+            })
+            .collect();
+
+        let title = format!(
+            "📜 xray log · {} and above · {}",
+            self.level,
+            if self.top.is_none() {
+                "following".to_string()
+            } else {
+                format!("paused, {} to follow", keymap().label(&[TuiAction::Last]))
+            }
+        );
+        let log = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .title_alignment(Alignment::Center),
+        );
+        f.render_widget(log, area);
+    }
+}
+
+fn level_color(level: LogLevel) -> Color {
+    match level {
+        LogLevel::Debug => theme().muted,
+        LogLevel::Info => theme().text,
+        LogLevel::Warning => theme().warning,
+        LogLevel::Error => theme().error,
+    }
+}
@@ -1 +1,511 @@
-pub mod table;
+//! The terminal dashboard: one tab per area of the daemon, all fed through
+//! its socket API.
+
+mod daemon;
+mod keymap;
+mod logs;
+mod routing;
+mod status;
+mod subscriptions;
+mod table;
+mod theme;
+
+use std::io::{self, Result};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use crossterm::{
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+};
+use ratatui::{
+    Frame, Terminal,
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Tabs, Wrap},
+};
+
+use luxnulla::config::{TuiAction, TuiConfig};
+
+use daemon::{Daemon, Update};
+use keymap::{Help, keymap};
+use logs::LogView;
+use routing::RoutingView;
+use status::StatusView;
+use subscriptions::SubscriptionList;
+use table::NodeTable;
+use theme::theme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Nodes,
+    Subscriptions,
+    Logs,
+    Status,
+    Routing,
+}
+
+impl Tab {
+    const ALL: [Tab; 5] = [
+        Tab::Nodes,
+        Tab::Subscriptions,
+        Tab::Logs,
+        Tab::Status,
+        Tab::Routing,
+    ];
+
+    /// The tab bar entry, with the number key that opens the tab.
+    fn label(self) -> String {
+        format!("{} {}", self.index() + 1, self.title())
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Tab::Nodes => "Nodes",
+            Tab::Subscriptions => "Subscriptions",
+            Tab::Logs => "Logs",
+            Tab::Status => "Status",
+            Tab::Routing => "Routing",
+        }
+    }
+
+    fn index(self) -> usize {
+        Tab::ALL.iter().position(|&tab| tab == self).unwrap_or(0)
+    }
+}
+
+/// Keys that work on every tab, besides 1-5 for the tabs themselves.
+const GLOBAL_HELP: &Help = &[
+    (&[TuiAction::Quit], "quit"),
+    (&[TuiAction::NextTab, TuiAction::PreviousTab], "switch tab"),
+];
+
+/// Rows moved by one step of the mouse wheel.
+const WHEEL_ROWS: isize = 3;
+
+struct Dashboard {
+    tab: Tab,
+    nodes: NodeTable,
+    subscriptions: SubscriptionList,
+    logs: LogView,
+    status: StatusView,
+    routing: RoutingView,
+    daemon: Daemon,
+    updates: Receiver<Update>,
+    /// Where the tab bar was drawn, for clicks on it.
+    tabs_area: Rect,
+}
+
+impl Dashboard {
+    fn apply_updates(&mut self) {
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                Update::Subscriptions(_) => self.subscriptions.apply(update),
+                // Both change which nodes there are.
+                Update::SubscriptionChanged(_) | Update::Refreshed(_) => {
+                    self.daemon.load_nodes();
+                    self.subscriptions.apply(update);
+                }
+                Update::Log(_) | Update::LogsClosed(_) => self.logs.apply(update),
+                Update::Status(_) | Update::Traffic(_) => self.status.apply(update),
+                Update::Routing(_) => self.routing.apply(update),
+                Update::Event(_) => {
+                    // The active node may have changed.
+                    if self.tab == Tab::Status {
+                        self.status.reload();
+                    }
+                    self.nodes.apply(update);
+                }
+                _ => self.nodes.apply(update),
+            }
+        }
+    }
+
+    fn switch_to(&mut self, tab: Tab) {
+        if tab == self.tab {
+            return;
+        }
+        self.tab = tab;
+        match tab {
+            Tab::Nodes => {}
+            Tab::Subscriptions => self.subscriptions.reload(),
+            Tab::Logs => self.logs.start(),
+            Tab::Status => self.status.reload(),
+            Tab::Routing => self.routing.reload(),
+        }
+    }
+
+    fn cycle_tab(&mut self, forward: bool) {
+        let count = Tab::ALL.len();
+        let index = self.tab.index();
+        let next = if forward {
+            (index + 1) % count
+        } else {
+            (index + count - 1) % count
+        };
+        self.switch_to(Tab::ALL[next]);
+    }
+
+    /// Whether the active tab takes text input, so `q` and digits are typed
+    /// instead of acted on.
+    fn captures_keys(&self) -> bool {
+        match self.tab {
+            Tab::Nodes => self.nodes.captures_keys(),
+            Tab::Subscriptions => self.subscriptions.captures_keys(),
+            Tab::Logs | Tab::Status | Tab::Routing => false,
+        }
+    }
+
+    /// Handles the keys that work on every tab, then passes the key with
+    /// the actions bound to it to the active tab.
+    fn handle_key(&mut self, key: KeyEvent) {
+        let actions = keymap().actions(key);
+        if !self.captures_keys() {
+            for action in &actions {
+                match action {
+                    TuiAction::NextTab => return self.cycle_tab(true),
+                    TuiAction::PreviousTab => return self.cycle_tab(false),
+                    _ => {}
+                }
+            }
+            if let KeyCode::Char(c @ '1'..='5') = key.code
+                && !key.modifiers.contains(KeyModifiers::CONTROL)
+            {
+                return self.switch_to(Tab::ALL[c as usize - '1' as usize]);
+            }
+        }
+
+        match self.tab {
+            Tab::Nodes => self.nodes.handle_key(key.code, &actions),
+            Tab::Subscriptions => self.subscriptions.handle_key(key.code, &actions),
+            Tab::Logs => self.logs.handle_key(&actions),
+            Tab::Status => self.status.handle_key(&actions),
+            Tab::Routing => self.routing.handle_key(&actions),
+        }
+    }
+
+    /// Whether `key` quits; never while the tab takes text input.
+    fn quits(&self, key: KeyEvent) -> bool {
+        !self.captures_keys() && keymap().actions(key).contains(&TuiAction::Quit)
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if mouse.kind == MouseEventKind::Down(MouseButton::Left)
+            && self
+                .tabs_area
+                .contains(Position::new(mouse.column, mouse.row))
+        {
+            if let Some(tab) = self.tab_at(mouse.column)
+                && !self.captures_keys()
+            {
+                self.switch_to(tab);
+            }
+            return;
+        }
+
+        match self.tab {
+            Tab::Nodes => self.nodes.handle_mouse(mouse),
+            Tab::Subscriptions => self.subscriptions.handle_mouse(mouse),
+            Tab::Logs => self.logs.handle_mouse(mouse),
+            Tab::Routing => self.routing.handle_mouse(mouse),
+            Tab::Status => {}
+        }
+    }
+
+    /// The tab whose title is at `column` of the tab bar.
+    fn tab_at(&self, column: u16) -> Option<Tab> {
+        // Каждый заголовок окружён пробелами и отделён символом «│»
+        let mut x = self.tabs_area.x;
+        for tab in Tab::ALL {
+            let width = tab.label().chars().count() as u16 + 2;
+            if (x..x + width).contains(&column) {
+                return Some(tab);
+            }
+            x += width + 1;
+        }
+        None
+    }
+
+    fn help(&self) -> &'static Help {
+        match self.tab {
+            Tab::Nodes => table::HELP,
+            Tab::Subscriptions => subscriptions::HELP,
+            Tab::Logs => logs::HELP,
+            Tab::Status => status::HELP,
+            Tab::Routing => routing::HELP,
+        }
+    }
+}
+
+/// Loads the nodes from the daemon and runs the dashboard until the user
+/// quits.
+pub async fn init() -> anyhow::Result<()> {
+    let nodes = daemon::list_nodes().await?;
+
+    let (tx, rx) = mpsc::channel();
+    let config = daemon::tui_config().await.unwrap_or_else(|e| {
+        let _ = tx.send(Update::Error(format!(
+            "using the default keys and colours: {}",
+            e
+        )));
+        TuiConfig::default()
+    });
+    theme::init(&config);
+    keymap::init(&config);
+    let daemon = Daemon::new(tx);
+    daemon.watch_events();
+
+    let mut app = Dashboard {
+        tab: Tab::Nodes,
+        nodes: NodeTable::new(nodes, daemon.clone()),
+        subscriptions: SubscriptionList::new(daemon.clone()),
+        logs: LogView::new(daemon.clone()),
+        status: StatusView::new(daemon.clone()),
+        routing: RoutingView::new(daemon.clone()),
+        daemon,
+        updates: rx,
+        tabs_area: Rect::default(),
+    };
+    tokio::task::spawn_blocking(move || run(&mut app)).await??;
+
+    Ok(())
+}
+
+fn run(app: &mut Dashboard) -> Result<()> {
+    // Настройка терминала
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // Основной цикл
+    let res = run_app(&mut terminal, app);
+
+    // Восстанавливаем терминал
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+
+    if let Err(err) = res {
+        println!("{err:?}");
+    }
+
+    Ok(())
+}
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut Dashboard) -> Result<()> {
+    loop {
+        app.apply_updates();
+        if app.tab == Tab::Status {
+            app.status.tick();
+        }
+        terminal.draw(|f| ui(f, app))?;
+
+        // Обрабатываем события
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                if app.quits(key) {
+                    return Ok(());
+                }
+                app.handle_key(key);
+            }
+            Event::Mouse(mouse) => app.handle_mouse(mouse),
+            _ => {}
+        }
+    }
+}
+
+fn ui(f: &mut Frame, app: &mut Dashboard) {
+    let main_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(5),
+            Constraint::Length(1),
+            Constraint::Length(4),
+        ])
+        .split(f.area());
+
+    let titles = Tab::ALL.iter().map(|tab| tab.label());
+    app.tabs_area = main_layout[0];
+    let tabs = Tabs::new(titles)
+        .select(app.tab.index())
+        .style(Style::default().fg(theme().text))
+        .highlight_style(
+            Style::default()
+                .fg(theme().accent)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+        );
+    f.render_widget(tabs, main_layout[0]);
+
+    match app.tab {
+        Tab::Nodes => {
+            app.nodes.render(f, main_layout[1]);
+            app.nodes.render_status(f, main_layout[2]);
+        }
+        Tab::Subscriptions => {
+            app.subscriptions.render(f, main_layout[1]);
+            render_status(f, app.subscriptions.status_line(), main_layout[2]);
+        }
+        Tab::Logs => {
+            app.logs.render(f, main_layout[1]);
+            render_status(f, app.logs.status_line(), main_layout[2]);
+        }
+        Tab::Status => {
+            app.status.render(f, main_layout[1]);
+            render_status(f, app.status.status_line(), main_layout[2]);
+        }
+        Tab::Routing => {
+            app.routing.render(f, main_layout[1]);
+            render_status(f, app.routing.status_line(), main_layout[2]);
+        }
+    }
+
+    // Подсказки строим из текущей раскладки клавиш
+    let entries = GLOBAL_HELP
+        .iter()
+        .map(|(actions, text)| (keymap().label(actions), *text))
+        .chain(std::iter::once((String::from("1-5"), "go to tab")))
+        .chain(
+            app.help()
+                .iter()
+                .map(|(actions, text)| (keymap().label(actions), *text)),
+        )
+        .filter(|(keys, _)| !keys.is_empty());
+    let help_message = Line::from(
+        entries
+            .enumerate()
+            .flat_map(|(i, (keys, text))| {
+                [
+                    Span::raw(if i == 0 { "" } else { "  " }),
+                    Span::styled(
+                        keys,
+                        Style::default()
+                            .fg(theme().accent)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(format!(" {}", text), Style::default().fg(theme().text)),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let help = Paragraph::new(help_message)
+        .block(Block::default().borders(Borders::ALL).title("Controls"))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
+
+    f.render_widget(help, main_layout[3]);
+
+    // Всплывающие окна рисуем поверх всего остального
+    match app.tab {
+        Tab::Nodes => app.nodes.render_popup(f),
+        Tab::Subscriptions => app.subscriptions.render_popup(f),
+        Tab::Logs | Tab::Status | Tab::Routing => {}
+    }
+}
+
+fn render_status(f: &mut Frame, status: Option<Line<'static>>, area: Rect) {
+    if let Some(status) = status {
+        f.render_widget(Paragraph::new(status), area);
+    }
+}
+
+/// A status line in `color`.
+fn message(text: impl Into<String>, color: Color) -> Line<'static> {
+    Line::from(Span::styled(text.into(), Style::default().fg(color)))
+}
+
+/// Moves a list selection by `delta` rows, stopping at either end; `None`
+/// for an empty list.
+fn step(selected: Option<usize>, len: usize, delta: isize) -> Option<usize> {
+    let last = len.checked_sub(1)?;
+    Some(
+        selected
+            .map_or(0, |i| i.saturating_add_signed(delta))
+            .min(last),
+    )
+}
+
+/// Index of the list row under `mouse` in a bordered table with a one-line
+/// header, given the index of its first shown row. May be past the end of
+/// the list.
+fn table_row(area: Rect, offset: usize, mouse: MouseEvent) -> Option<usize> {
+    let inner = area.inner(Margin::new(1, 1));
+    if !inner.contains(Position::new(mouse.column, mouse.row)) || mouse.row == inner.y {
+        return None;
+    }
+    Some(offset + (mouse.row - inner.y - 1) as usize)
+}
+
+/// A rectangle of `percent_x` by `percent_y` of `area`, centered in it.
+fn centered(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = area.height * percent_y / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn step_stops_at_the_ends() {
+        assert_eq!(step(None, 0, 0), None);
+        assert_eq!(step(Some(3), 0, 1), None);
+        assert_eq!(step(None, 1, 5), Some(0));
+        assert_eq!(step(Some(0), 1, -5), Some(0));
+        assert_eq!(step(Some(0), 1, 5), Some(0));
+        assert_eq!(step(None, 10, 3), Some(0));
+        assert_eq!(step(Some(2), 10, 3), Some(5));
+        assert_eq!(step(Some(2), 10, -3), Some(0));
+        assert_eq!(step(Some(8), 10, 10), Some(9));
+        // A selection left behind by a shorter list comes back into it.
+        assert_eq!(step(Some(7), 3, 0), Some(2));
+    }
+
+    #[test]
+    fn table_row_under_the_mouse() {
+        // Borders at rows 5 and 14 and columns 2 and 21, the header at row 6.
+        let area = Rect::new(2, 5, 20, 10);
+        assert_eq!(table_row(area, 0, click(10, 7)), Some(0));
+        assert_eq!(table_row(area, 0, click(10, 13)), Some(6));
+        assert_eq!(table_row(area, 40, click(3, 7)), Some(40));
+        for (column, row) in [(10, 5), (10, 6), (10, 14), (2, 8), (21, 8), (30, 8)] {
+            assert_eq!(table_row(area, 0, click(column, row)), None);
+        }
+
+        // Too small for anything but borders and the header.
+        assert_eq!(table_row(Rect::new(0, 0, 10, 3), 0, click(5, 1)), None);
+        assert_eq!(table_row(Rect::new(0, 0, 10, 2), 0, click(5, 1)), None);
+        assert_eq!(table_row(Rect::default(), 0, click(0, 0)), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! The Routing tab: the rules of the running xray config in match order.
+
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{
+    Frame,
+    layout::{Alignment, Constraint, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, Borders, Cell, Row, Table, TableState},
+};
+
+use luxnulla::RoutingInfo;
+use luxnulla::config::TuiAction;
+
+use super::daemon::{Daemon, Update};
+use super::keymap::Help;
+use super::theme::theme;
+use super::{WHEEL_ROWS, message, step, table_row};
+
+pub const HELP: &Help = &[
+    (&[TuiAction::Up, TuiAction::Down], "navigate"),
+    (&[TuiAction::PageUp, TuiAction::PageDown], "page"),
+    (&[TuiAction::Reload], "reload"),
+];
+
+pub struct RoutingView {
+    daemon: Daemon,
+    data: Option<RoutingInfo>,
+    state: TableState,
+    /// Rows of the last render.
+    height: usize,
+    /// Where the table was drawn, for clicks on its rows.
+    area: Rect,
+    status: Option<Line<'static>>,
+}
+
+impl RoutingView {
+    pub fn new(daemon: Daemon) -> Self {
+        Self {
+            daemon,
+            data: None,
+            state: TableState::default(),
+            height: 10,
+            area: Rect::default(),
+            status: None,
+        }
+    }
+
+    pub fn reload(&self) {
+        self.daemon.routing();
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Routing(Ok(data)) => {
+                let len = data.rules.len();
+                self.data = Some(data);
+                self.state.select(step(self.state.selected(), len, 0));
+                self.status = None;
+            }
+            Update::Routing(Err(e)) => self.status = Some(message(e, theme().error)),
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let len = self.data.as_ref().map_or(0, |data| data.rules.len());
+        self.state.select(step(self.state.selected(), len, delta));
+    }
+
+    pub fn handle_key(&mut self, actions: &[TuiAction]) {
+        let page = self.height.max(1) as isize;
+        for action in actions {
+            match action {
+                TuiAction::Down => self.move_selection(1),
+                TuiAction::Up => self.move_selection(-1),
+                TuiAction::PageDown => self.move_selection(page),
+                TuiAction::PageUp => self.move_selection(-page),
+                TuiAction::Reload => self.reload(),
+                _ => continue,
+            }
+            return;
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollDown => self.move_selection(WHEEL_ROWS),
+            MouseEventKind::ScrollUp => self.move_selection(-WHEEL_ROWS),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let len = self.data.as_ref().map_or(0, |data| data.rules.len());
+                if let Some(index) =
+                    table_row(self.area, self.state.offset(), mouse).filter(|&index| index < len)
+                {
+                    self.state.select(Some(index));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn status_line(&self) -> Option<Line<'static>> {
+        self.status.clone()
+    }
+
+    pub fn render(&mut self, f: &mut Frame, area: Rect) {
+        self.height = area.height.saturating_sub(3) as usize;
+        self.area = area;
+
+        let Some(data) = &self.data else {
+            let block = Block::default().borders(Borders::ALL).title("🧭 Routing");
+            f.render_widget(block, area);
+            return;
+        };
+
+        let header = Row::new(
+            ["#", "Target", "Conditions", "Tag"].map(|h| Cell::from(h).style(theme().header())),
+        )
+        .style(theme().header_row());
+
+        let rows = data.rules.iter().enumerate().map(|(i, rule)| {
+            let target_color = match rule.target.as_str() {
+                "direct" => theme().good,
+                "block" => theme().error,
+                _ => theme().info,
+            };
+            Row::new([
+                Cell::from((i + 1).to_string()),
+                Cell::from(rule.target.clone()).style(Style::default().fg(target_color)),
+                Cell::from(rule.conditions.join("; ")),
+                Cell::from(rule.tag.clone().unwrap_or_default())
+                    .style(Style::default().fg(theme().muted)),
+            ])
+        });
+
+        let title = format!(
+            "🧭 Routing ({} rules, domain strategy {})",
+            data.rules.len(),
+            data.domain_strategy.as_deref().unwrap_or("AsIs")
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(4),
+                Constraint::Length(20),
+                Constraint::Fill(1),
+                Constraint::Length(24),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .title_alignment(Alignment::Center),
+        )
+        .row_highlight_style(theme().highlighted());
+        f.render_stateful_widget(table, area, &mut self.state);
+    }
+}
@@ -0,0 +1,290 @@
+//! The Status tab: whether xray runs, what carries proxied traffic and how
+//! much of it flows.
+
+use std::time::{Duration, Instant};
+
+use ratatui::{
+    Frame,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Sparkline, Wrap},
+};
+
+use luxnulla::config::TuiAction;
+use luxnulla::{DaemonStatus, TrafficSeries, TrafficStats, format_bytes};
+
+use super::daemon::{Daemon, Update};
+use super::keymap::Help;
+use super::message;
+use super::theme::theme;
+
+pub const HELP: &Help = &[(&[TuiAction::Reload], "reload")];
+
+/// How often the tab asks the daemon again while shown.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Rows of one uplink/downlink sparkline pair.
+const GRAPH_HEIGHT: u16 = 4;
+
+pub struct StatusView {
+    daemon: Daemon,
+    data: Option<DaemonStatus>,
+    status: Option<Line<'static>>,
+    /// When the last request was sent.
+    polled: Option<Instant>,
+    traffic: Option<TrafficStats>,
+    /// Why there are no traffic numbers, e.g. xray is not running.
+    traffic_error: Option<String>,
+    /// When traffic was last asked for; it is polled at the daemon's
+    /// sampling interval.
+    traffic_polled: Option<Instant>,
+}
+
+impl StatusView {
+    pub fn new(daemon: Daemon) -> Self {
+        Self {
+            daemon,
+            data: None,
+            status: None,
+            polled: None,
+            traffic: None,
+            traffic_error: None,
+            traffic_polled: None,
+        }
+    }
+
+    pub fn reload(&mut self) {
+        self.polled = Some(Instant::now());
+        self.daemon.status();
+        self.reload_traffic();
+    }
+
+    fn reload_traffic(&mut self) {
+        self.traffic_polled = Some(Instant::now());
+        self.daemon.traffic();
+    }
+
+    /// Reloads when the last answer is older than `POLL_INTERVAL`, traffic
+    /// once the daemon has a new sample.
+    pub fn tick(&mut self) {
+        if self.polled.is_none_or(|at| at.elapsed() >= POLL_INTERVAL) {
+            self.reload();
+        }
+        let interval = self
+            .traffic
+            .as_ref()
+            .map_or(Duration::from_secs(1), |stats| {
+                Duration::from_millis(stats.interval_ms.max(100))
+            });
+        if self
+            .traffic_polled
+            .is_none_or(|at| at.elapsed() >= interval)
+        {
+            self.reload_traffic();
+        }
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Status(Ok(data)) => {
+                self.data = Some(data);
+                self.status = None;
+            }
+            Update::Status(Err(e)) => self.status = Some(message(e, theme().error)),
+            Update::Traffic(Ok(stats)) => {
+                self.traffic = Some(stats);
+                self.traffic_error = None;
+            }
+            Update::Traffic(Err(e)) => {
+                self.traffic = None;
+                self.traffic_error = Some(e);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_key(&mut self, actions: &[TuiAction]) {
+        if actions.contains(&TuiAction::Reload) {
+            self.reload();
+        }
+    }
+
+    pub fn status_line(&self) -> Option<Line<'static>> {
+        self.status.clone()
+    }
+
+    pub fn render(&self, f: &mut Frame, area: Rect) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("📊 Status")
+            .title_alignment(Alignment::Center);
+
+        let Some(data) = &self.data else {
+            f.render_widget(Paragraph::new("loading…").block(block), area);
+            return;
+        };
+
+        let label = Style::default()
+            .fg(theme().info)
+            .add_modifier(Modifier::BOLD);
+        let row = |name: &str, value: Span<'static>| {
+            Line::from(vec![Span::styled(format!("{:<16}", name), label), value])
+        };
+
+        let xray = match data.xray_pid {
+            Some(pid) => Span::styled(
+                format!("running (pid {})", pid),
+                Style::default().fg(theme().good),
+            ),
+            None => Span::styled("not running", Style::default().fg(theme().error)),
+        };
+
+        let active = match (
+            &data.selected_node,
+            &data.selected_group,
+            &data.selected_chain,
+        ) {
+            (Some(node), _, _) => format!("node {}", node.name),
+            (_, Some(group), _) => format!("group {}", group),
+            (_, _, Some(chain)) => format!("chain {}", chain),
+            _ => String::from("none"),
+        };
+
+        let mut lines = vec![row("xray", xray), row("active", Span::raw(active))];
+        if let Some(node) = &data.selected_node {
+            lines.push(row(
+                "  address",
+                Span::raw(format!("{} {}:{}", node.protocol, node.address, node.port)),
+            ));
+            lines.push(row(
+                "  latency",
+                match node.latency_ms {
+                    Some(ms) => Span::raw(format!("{}ms", ms)),
+                    None => Span::styled("untested", Style::default().fg(theme().muted)),
+                },
+            ));
+            lines.push(row("  id", Span::raw(node.id.clone())));
+        }
+        lines.extend([
+            row(
+                "nodes",
+                Span::raw(format!(
+                    "{} from {} subscriptions",
+                    data.nodes, data.subscriptions
+                )),
+            ),
+            row(
+                "routing presets",
+                Span::raw(if data.routing_presets.is_empty() {
+                    String::from("none")
+                } else {
+                    data.routing_presets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }),
+            ),
+            row("tproxy", Span::raw(if data.tproxy { "on" } else { "off" })),
+        ]);
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(lines.len() as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .split(area);
+
+        let text = Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false });
+        f.render_widget(text, layout[0]);
+
+        self.render_traffic(f, layout[1]);
+    }
+
+    /// Rate graphs of the proxy and then of every inbound, as many as fit.
+    fn render_traffic(&self, f: &mut Frame, area: Rect) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("📈 Traffic")
+            .title_alignment(Alignment::Center);
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        let Some(stats) = &self.traffic else {
+            let text = match &self.traffic_error {
+                Some(e) => Span::styled(e.clone(), Style::default().fg(theme().warning)),
+                None => Span::styled("loading…", Style::default().fg(theme().muted)),
+            };
+            f.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), inner);
+            return;
+        };
+
+        let series: Vec<&TrafficSeries> = std::iter::once(&stats.proxy)
+            .chain(&stats.inbounds)
+            .take((inner.height / GRAPH_HEIGHT).max(1) as usize)
+            .collect();
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(series.iter().map(|_| Constraint::Length(GRAPH_HEIGHT)))
+            .split(inner);
+
+        for (series, row) in series.into_iter().zip(rows.iter()) {
+            let halves = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(*row);
+            let rate = series.rate();
+            render_graph(
+                f,
+                halves[0],
+                format!("{} ↑", series.tag),
+                rate.map(|r| r.0),
+                series.uplink,
+                &series.uplink_rates,
+                theme().uplink,
+            );
+            render_graph(
+                f,
+                halves[1],
+                format!("{} ↓", series.tag),
+                rate.map(|r| r.1),
+                series.downlink,
+                &series.downlink_rates,
+                theme().downlink,
+            );
+        }
+    }
+}
+
+/// One direction of a series: the latest rate and total in the title, the
+/// most recent rates that fit the width below it.
+fn render_graph(
+    f: &mut Frame,
+    area: Rect,
+    name: String,
+    rate: Option<u64>,
+    total: u64,
+    rates: &[u64],
+    color: Color,
+) {
+    let title = format!(
+        " {} {} · {} total ",
+        name,
+        rate.map_or_else(|| String::from("-"), |r| format!("{}/s", format_bytes(r))),
+        format_bytes(total)
+    );
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .title(title)
+        .border_style(Style::default().fg(theme().muted));
+    let width = block.inner(area).width as usize;
+    let sparkline = Sparkline::default()
+        .block(block)
+        .data(&rates[rates.len().saturating_sub(width)..])
+        .style(Style::default().fg(color));
+    f.render_widget(sparkline, area);
+}
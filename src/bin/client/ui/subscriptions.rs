@@ -0,0 +1,413 @@
+//! The Subscriptions tab: quota and expiry of every subscription, adding,
+//! removing and refreshing them.
+
+use chrono::Utc;
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{
+    Frame,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap},
+};
+
+use luxnulla::config::TuiAction;
+use luxnulla::{SubscriptionInfo, SubscriptionUsage, format_bytes};
+
+use super::daemon::{Daemon, Update};
+use super::keymap::{Help, keymap};
+use super::theme::theme;
+use super::{WHEEL_ROWS, centered, message, step, table_row};
+
+pub const HELP: &Help = &[
+    (&[TuiAction::Up, TuiAction::Down], "navigate"),
+    (&[TuiAction::Add], "add"),
+    (&[TuiAction::Remove], "remove"),
+    (&[TuiAction::Reload], "refresh all"),
+];
+
+pub struct SubscriptionList {
+    state: TableState,
+    items: Vec<SubscriptionInfo>,
+    daemon: Daemon,
+    status: Option<Line<'static>>,
+    /// A refresh is running; it can take a while for slow providers.
+    refreshing: bool,
+    /// Refresh once the daemon confirms the subscription being added.
+    fetch_added: bool,
+    /// `d` was pressed once on this subscription.
+    confirm_remove: Option<String>,
+    form: Option<AddForm>,
+    /// Where the table was drawn, for clicks on its rows.
+    table_area: Rect,
+}
+
+/// Popup asking for the URL and optional name of a new subscription.
+#[derive(Default)]
+struct AddForm {
+    url: String,
+    name: String,
+    /// The name field has the cursor.
+    on_name: bool,
+}
+
+impl SubscriptionList {
+    pub fn new(daemon: Daemon) -> Self {
+        Self {
+            state: TableState::default(),
+            items: Vec::new(),
+            daemon,
+            status: None,
+            refreshing: false,
+            fetch_added: false,
+            confirm_remove: None,
+            form: None,
+            table_area: Rect::default(),
+        }
+    }
+
+    pub fn reload(&self) {
+        self.daemon.list_subscriptions();
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Subscriptions(Ok(items)) => {
+                let selected = self.selected().map(|sub| sub.name.clone());
+                self.items = items;
+                let index = selected
+                    .and_then(|name| self.items.iter().position(|sub| sub.name == name))
+                    .or(self.state.selected());
+                self.state.select(step(index, self.items.len(), 0));
+            }
+            Update::SubscriptionChanged(Ok(msg)) => {
+                self.status = Some(message(msg, theme().good));
+                self.reload();
+                if std::mem::take(&mut self.fetch_added) {
+                    self.refresh();
+                }
+            }
+            Update::Refreshed(Ok(report)) => {
+                self.refreshing = false;
+                let failed = report
+                    .subscriptions
+                    .iter()
+                    .filter(|sub| sub.error.is_some())
+                    .count();
+                self.status = Some(if failed == 0 {
+                    message(
+                        format!("refreshed, {} unique nodes", report.nodes),
+                        theme().good,
+                    )
+                } else {
+                    message(
+                        format!(
+                            "refreshed, {} unique nodes; {} subscriptions failed",
+                            report.nodes, failed
+                        ),
+                        theme().warning,
+                    )
+                });
+                self.reload();
+            }
+            Update::Subscriptions(Err(e))
+            | Update::SubscriptionChanged(Err(e))
+            | Update::Refreshed(Err(e)) => {
+                self.refreshing = false;
+                self.fetch_added = false;
+                self.status = Some(message(e, theme().error));
+            }
+            _ => {}
+        }
+    }
+
+    fn selected(&self) -> Option<&SubscriptionInfo> {
+        self.state.selected().and_then(|i| self.items.get(i))
+    }
+
+    /// Whether the add form has the keyboard.
+    pub fn captures_keys(&self) -> bool {
+        self.form.is_some()
+    }
+
+    pub fn handle_key(&mut self, code: KeyCode, actions: &[TuiAction]) {
+        if self.form.is_some() {
+            self.form_input(code);
+            return;
+        }
+
+        let confirm = self.confirm_remove.take();
+        for action in actions {
+            match action {
+                TuiAction::Down => self.move_selection(1),
+                TuiAction::Up => self.move_selection(-1),
+                TuiAction::Add => self.form = Some(AddForm::default()),
+                TuiAction::Reload => self.refresh(),
+                TuiAction::Remove => self.remove(confirm),
+                _ => continue,
+            }
+            return;
+        }
+    }
+
+    /// Removes the selected subscription on the second press in a row.
+    fn remove(&mut self, confirm: Option<String>) {
+        let Some(name) = self.selected().map(|sub| sub.name.clone()) else {
+            return;
+        };
+        if confirm.as_ref() == Some(&name) {
+            self.status = Some(message(format!("removing {}…", name), theme().warning));
+            self.daemon.remove_subscription(name);
+        } else {
+            self.status = Some(message(
+                format!(
+                    "press {} again to remove {} and its nodes",
+                    keymap().label(&[TuiAction::Remove]),
+                    name
+                ),
+                theme().warning,
+            ));
+            self.confirm_remove = Some(name);
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if self.form.is_some() {
+            return;
+        }
+        match mouse.kind {
+            MouseEventKind::ScrollDown => self.move_selection(WHEEL_ROWS),
+            MouseEventKind::ScrollUp => self.move_selection(-WHEEL_ROWS),
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(index) = table_row(self.table_area, self.state.offset(), mouse)
+                    .filter(|&index| index < self.items.len())
+                {
+                    self.state.select(Some(index));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn refresh(&mut self) {
+        if self.refreshing {
+            return;
+        }
+        self.refreshing = true;
+        self.status = Some(message("refreshing subscriptions…", theme().warning));
+        self.daemon.refresh();
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let index = step(self.state.selected(), self.items.len(), delta);
+        self.state.select(index);
+    }
+
+    fn form_input(&mut self, code: KeyCode) {
+        let Some(form) = &mut self.form else {
+            return;
+        };
+        let field = if form.on_name {
+            &mut form.name
+        } else {
+            &mut form.url
+        };
+
+        match code {
+            KeyCode::Esc => self.form = None,
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Up | KeyCode::Down => {
+                form.on_name = !form.on_name
+            }
+            KeyCode::Enter if !form.on_name => form.on_name = true,
+            KeyCode::Enter => {
+                let url = form.url.trim().to_string();
+                if url.is_empty() {
+                    form.on_name = false;
+                    return;
+                }
+                let name = Some(form.name.trim().to_string()).filter(|name| !name.is_empty());
+                self.form = None;
+                self.fetch_added = true;
+                self.status = Some(message(format!("adding {}…", url), theme().warning));
+                self.daemon.add_subscription(url, name);
+            }
+            KeyCode::Backspace => {
+                field.pop();
+            }
+            KeyCode::Char(c) => field.push(c),
+            _ => {}
+        }
+    }
+
+    pub fn status_line(&self) -> Option<Line<'static>> {
+        self.status.clone()
+    }
+
+    pub fn render(&mut self, f: &mut Frame, area: Rect) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(4)])
+            .split(area);
+        self.table_area = layout[0];
+
+        let header = Row::new(
+            ["Name", "Nodes", "Traffic", "Expires", "Updated"]
+                .map(|h| Cell::from(h).style(theme().header())),
+        )
+        .style(theme().header_row());
+
+        let rows = self.items.iter().map(|sub| {
+            let name = match &sub.error {
+                Some(_) => {
+                    Cell::from(format!("✗ {}", sub.name)).style(Style::default().fg(theme().error))
+                }
+                None => Cell::from(sub.name.clone()),
+            };
+            Row::new([
+                name,
+                Cell::from(sub.nodes.to_string()),
+                traffic_cell(sub.usage.as_ref()),
+                expiry_cell(sub.usage.as_ref()),
+                Cell::from(
+                    sub.updated
+                        .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| String::from("never")),
+                ),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(6),
+                Constraint::Length(30),
+                Constraint::Length(12),
+                Constraint::Length(17),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("📡 Subscriptions ({})", self.items.len()))
+                .title_alignment(Alignment::Center),
+        )
+        .row_highlight_style(theme().highlighted())
+        .highlight_symbol(">> ");
+        f.render_stateful_widget(table, layout[0], &mut self.state);
+
+        let mut lines = Vec::new();
+        if let Some(sub) = self.selected() {
+            lines.push(Line::from(vec![
+                Span::styled("url  ", Style::default().fg(theme().info)),
+                Span::raw(sub.url.clone()),
+            ]));
+            if let Some(error) = &sub.error {
+                lines.push(Line::from(Span::styled(
+                    format!("last refresh failed: {}", error),
+                    Style::default().fg(theme().error),
+                )));
+            }
+        } else {
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "no subscriptions, press {} to add one",
+                    keymap().label(&[TuiAction::Add])
+                ),
+                Style::default().fg(theme().muted),
+            )));
+        }
+        let details = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL))
+            .wrap(Wrap { trim: false });
+        f.render_widget(details, layout[1]);
+    }
+
+    /// The add form, if open, drawn over the whole screen.
+    pub fn render_popup(&self, f: &mut Frame) {
+        let Some(form) = &self.form else {
+            return;
+        };
+
+        let mut area = centered(f.area(), 70, 100);
+        area.y += area.height.saturating_sub(6) / 2;
+        area.height = area.height.min(6);
+        f.render_widget(Clear, area);
+
+        let field = |label: &str, value: &str, active: bool| {
+            let style = if active {
+                Style::default().fg(theme().accent)
+            } else {
+                Style::default().fg(theme().text)
+            };
+            Line::from(vec![
+                Span::styled(format!("{:<6}", label), style.add_modifier(Modifier::BOLD)),
+                Span::raw(value.to_string()),
+                Span::styled(if active { "▏" } else { "" }, style),
+            ])
+        };
+        let lines = vec![
+            field("url", &form.url, !form.on_name),
+            field("name", &form.name, form.on_name),
+            Line::from(Span::styled(
+                "empty name: use the URL host",
+                Style::default().fg(theme().muted),
+            )),
+        ];
+
+        let popup = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Add subscription ")
+                .title_alignment(Alignment::Center)
+                .title_bottom(
+                    Line::from(" Enter next/add · Tab switch field · Esc cancel ").centered(),
+                )
+                .style(Style::default().fg(theme().text)),
+        );
+        f.render_widget(popup, area);
+    }
+}
+
+/// Used of total traffic, red when nearly exhausted.
+fn traffic_cell(usage: Option<&SubscriptionUsage>) -> Cell<'static> {
+    let Some(usage) = usage else {
+        return Cell::from("-").style(Style::default().fg(theme().muted));
+    };
+    if usage.total == 0 {
+        return Cell::from(format!("{} / unlimited", format_bytes(usage.used())));
+    }
+
+    let ratio = usage.used() as f64 / usage.total as f64;
+    let color = if ratio >= 0.9 {
+        theme().error
+    } else if ratio >= 0.7 {
+        theme().warning
+    } else {
+        theme().good
+    };
+    Cell::from(format!(
+        "{} / {} ({:.0}%)",
+        format_bytes(usage.used()),
+        format_bytes(usage.total),
+        ratio * 100.0
+    ))
+    .style(Style::default().fg(color))
+}
+
+/// Expiry date, yellow within a week and red once passed.
+fn expiry_cell(usage: Option<&SubscriptionUsage>) -> Cell<'static> {
+    let Some(expire) = usage.and_then(|usage| usage.expire) else {
+        return Cell::from("-").style(Style::default().fg(theme().muted));
+    };
+    let left = expire - Utc::now();
+    let color = if left.num_seconds() <= 0 {
+        theme().error
+    } else if left.num_days() < 7 {
+        theme().warning
+    } else {
+        theme().text
+    };
+    Cell::from(expire.format("%Y-%m-%d").to_string()).style(Style::default().fg(color))
+}
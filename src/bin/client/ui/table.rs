@@ -1,182 +1,738 @@
-use std::io::{self, Result};
-use std::time::{Duration, Instant};
+//! The Nodes tab: every node of the last refresh with sorting, filters,
+//! URL tests and a detail popup.
 
-use crossterm::{
-    event::{
-        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
-    },
-    execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
-};
-use rand::Rng;
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use base64::{Engine as _, engine::general_purpose};
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
 use ratatui::{
-    Frame, Terminal,
-    backend::{Backend, CrosstermBackend},
-    layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    Frame,
+    layout::{Alignment, Constraint, Direction, Layout, Position, Rect},
+    style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Cell, Gauge, Paragraph, Row, Table, TableState},
+    widgets::{
+        Block, Borders, Cell, Clear, Gauge, Paragraph, Row, Sparkline, Table, TableState, Wrap,
+    },
 };
+use tokio::task::AbortHandle;
+
+use luxnulla::config::TuiAction;
+use luxnulla::{DaemonEvent, NodeDetails, NodeInfo};
+
+use super::daemon::{Daemon, Update};
+use super::keymap::{Help, keymap};
+use super::theme::theme;
+use super::{WHEEL_ROWS, centered, step, table_row};
 
 #[derive(Debug, Clone)]
 struct SubData {
-    id: u32,
+    id: String,
     protocol: String,
     address: String,
     name: String,
-    test_result: String,
+    subscription: String,
+    test_result: TestResult,
+    /// The node xray currently sends proxied traffic through.
+    active: bool,
+    favourite: bool,
 }
 
-impl SubData {
-    fn new(id: u32) -> Self {
+impl From<NodeInfo> for SubData {
+    fn from(node: NodeInfo) -> Self {
         Self {
-            id,
-            protocol: String::from(""),
-            address: String::from(""),
-            name: String::from(""),
-            test_result: String::from(""),
+            protocol: node.protocol,
+            address: format!("{}:{}", node.address, node.port),
+            name: node.name,
+            subscription: node.subscriptions.join(", "),
+            test_result: node
+                .latency_ms
+                .map_or(TestResult::Untested, TestResult::Latency),
+            active: node.active,
+            favourite: node.favourite,
+            id: node.id,
         }
     }
 }
 
-struct App {
+#[derive(Debug, Clone)]
+enum TestResult {
+    /// Never tested, or the last test result is not known to the daemon.
+    Untested,
+    Latency(u32),
+    Failed,
+}
+
+impl TestResult {
+    fn text(&self) -> String {
+        match self {
+            TestResult::Untested => String::from("-"),
+            TestResult::Latency(ms) => format!("{}ms", ms),
+            TestResult::Failed => String::from("failed"),
+        }
+    }
+
+    fn latency_ms(&self) -> Option<u32> {
+        match self {
+            TestResult::Latency(ms) => Some(*ms),
+            TestResult::Untested | TestResult::Failed => None,
+        }
+    }
+
+    fn style(&self) -> Style {
+        let color = match self {
+            TestResult::Untested => theme().muted,
+            TestResult::Latency(ms) if *ms < 300 => theme().good,
+            TestResult::Latency(ms) if *ms < 800 => theme().warning,
+            TestResult::Latency(_) | TestResult::Failed => theme().error,
+        };
+        Style::default().fg(color)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Latency,
+    Name,
+    Protocol,
+    Subscription,
+}
+
+impl SortKey {
+    /// The column `s` switches to after `key`; `None` is the daemon's order.
+    fn cycle(key: Option<SortKey>) -> Option<SortKey> {
+        match key {
+            None => Some(SortKey::Latency),
+            Some(SortKey::Latency) => Some(SortKey::Name),
+            Some(SortKey::Name) => Some(SortKey::Protocol),
+            Some(SortKey::Protocol) => Some(SortKey::Subscription),
+            Some(SortKey::Subscription) => None,
+        }
+    }
+
+    fn column(self) -> usize {
+        match self {
+            SortKey::Name => 0,
+            SortKey::Protocol => 1,
+            SortKey::Subscription => 3,
+            SortKey::Latency => 4,
+        }
+    }
+
+    /// Untested and failed nodes sort after every measured latency.
+    fn compare(self, a: &SubData, b: &SubData) -> Ordering {
+        let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+        match self {
+            SortKey::Latency => a
+                .test_result
+                .latency_ms()
+                .unwrap_or(u32::MAX)
+                .cmp(&b.test_result.latency_ms().unwrap_or(u32::MAX)),
+            SortKey::Name => Ordering::Equal,
+            SortKey::Protocol => a.protocol.cmp(&b.protocol),
+            SortKey::Subscription => a.subscription.cmp(&b.subscription),
+        }
+        .then_with(by_name)
+    }
+}
+
+/// Which nodes the table shows.
+#[derive(Debug, Default)]
+struct Filter {
+    /// Only nodes with a measured latency.
+    alive: bool,
+    protocol: Option<String>,
+    favourites: bool,
+    /// Case-insensitive substring of the name or address.
+    search: String,
+}
+
+impl Filter {
+    fn matches(&self, item: &SubData) -> bool {
+        let search = self.search.to_lowercase();
+        (!self.alive || item.test_result.latency_ms().is_some())
+            && self.protocol.as_ref().is_none_or(|p| item.protocol == *p)
+            && (!self.favourites || item.favourite)
+            && (search.is_empty()
+                || item.name.to_lowercase().contains(&search)
+                || item.address.to_lowercase().contains(&search))
+    }
+
+    fn is_active(&self) -> bool {
+        self.alive || self.protocol.is_some() || self.favourites || !self.search.is_empty()
+    }
+
+    /// Short description for the table title.
+    fn describe(&self) -> Vec<String> {
+        let mut parts = Vec::new();
+        if self.alive {
+            parts.push(String::from("alive"));
+        }
+        if let Some(protocol) = &self.protocol {
+            parts.push(protocol.clone());
+        }
+        if self.favourites {
+            parts.push(String::from("★"));
+        }
+        if !self.search.is_empty() {
+            parts.push(format!("/{}", self.search));
+        }
+        parts
+    }
+}
+
+pub struct NodeTable {
     state: TableState,
     items: Vec<SubData>,
-    last_update: Instant,
+    /// Indices into `items` of the rows shown, after filtering and sorting.
+    /// `state` and `scroll_offset` refer to positions in this list.
+    view: Vec<usize>,
+    sort: Option<SortKey>,
+    descending: bool,
+    filter: Filter,
+    /// `/` was pressed and keys go to `filter.search`.
+    searching: bool,
     scroll_offset: usize,
     visible_rows: usize,
+    /// Where the table and its scrollbar were drawn, for the mouse.
+    table_area: Rect,
+    scrollbar_area: Rect,
+    /// The scrollbar thumb is held with the mouse.
+    dragging: bool,
+    daemon: Daemon,
+    /// Latest daemon event or error, shown above the controls.
+    status: Option<Line<'static>>,
+    /// Node switch waiting for the daemon, shown with a spinner.
+    switching: Option<Switching>,
+    /// URL test streaming results into the table.
+    testing: Option<Testing>,
+    /// Popup with the full configuration of a node.
+    details: Option<DetailPane>,
 }
 
-impl App {
-    fn new() -> Self {
+struct DetailPane {
+    id: String,
+    /// `None` until the daemon answers.
+    data: Option<std::result::Result<NodeDetails, String>>,
+    scroll: u16,
+}
+
+struct Testing {
+    total: usize,
+    done: usize,
+    handle: AbortHandle,
+}
+
+struct Switching {
+    name: String,
+    started: Instant,
+}
+
+pub const HELP: &Help = &[
+    (&[TuiAction::Up, TuiAction::Down], "navigate"),
+    (&[TuiAction::PageUp, TuiAction::PageDown], "scroll"),
+    (&[TuiAction::First, TuiAction::Last], "jump"),
+    (&[TuiAction::Select], "select"),
+    (
+        &[TuiAction::Test, TuiAction::TestFiltered, TuiAction::TestAll],
+        "test selected/filtered/all",
+    ),
+    (&[TuiAction::Cancel], "cancel test"),
+    (&[TuiAction::Sort, TuiAction::ReverseSort], "sort/reverse"),
+    (&[TuiAction::AliveOnly], "alive only"),
+    (&[TuiAction::Protocol], "protocol"),
+    (&[TuiAction::Favourite], "favourite"),
+    (&[TuiAction::Favourites], "favourites only"),
+    (&[TuiAction::Search], "search"),
+    (&[TuiAction::Details], "details"),
+    (&[TuiAction::Reload], "reload nodes"),
+];
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+impl NodeTable {
+    pub fn new(nodes: Vec<NodeInfo>, daemon: Daemon) -> Self {
         let mut app = Self {
             state: TableState::default(),
             items: Vec::new(),
-            last_update: Instant::now(),
+            view: Vec::new(),
+            sort: None,
+            descending: false,
+            filter: Filter::default(),
+            searching: false,
             scroll_offset: 0,
             visible_rows: 10,
+            table_area: Rect::default(),
+            scrollbar_area: Rect::default(),
+            dragging: false,
+            daemon,
+            status: None,
+            switching: None,
+            testing: None,
+            details: None,
         };
 
-        app.state.select(Some(0));
+        app.set_items(nodes);
         app
     }
 
-    // fn generate_data(&mut self) {
-    //     self.items.clear();
-    //     // Генерируем больше данных, чтобы показать скролл
-    //     for i in 0..150 {
-    //         self.items.push(SubData::new(i + 1));
-    //     }
-    // }
+    /// Replaces the table contents, keeping the selection on the same node
+    /// if it is still listed.
+    fn set_items(&mut self, nodes: Vec<NodeInfo>) {
+        let selected_id = self.selected_item().map(|item| item.id.clone());
+        self.items = nodes.into_iter().map(SubData::from).collect();
+        self.rebuild_view(selected_id);
+    }
 
-    fn next(&mut self) {
-        let selected = self.state.selected().unwrap_or(0);
-        let next_index = if selected >= self.items.len() - 1 {
-            0
-        } else {
-            selected + 1
-        };
+    fn selected_item(&self) -> Option<&SubData> {
+        self.state
+            .selected()
+            .and_then(|i| self.view.get(i))
+            .map(|&i| &self.items[i])
+    }
 
-        self.state.select(Some(next_index));
+    /// Recomputes `view` from the filter and sort order and selects the node
+    /// `keep` if it is still shown, otherwise the row at the same position.
+    fn rebuild_view(&mut self, keep: Option<String>) {
+        let mut view: Vec<usize> = (0..self.items.len())
+            .filter(|&i| self.filter.matches(&self.items[i]))
+            .collect();
+        if let Some(key) = self.sort {
+            view.sort_by(|&a, &b| {
+                let ordering = key.compare(&self.items[a], &self.items[b]);
+                if self.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        self.view = view;
 
-        // Обновляем скролл если необходимо
-        if next_index >= self.scroll_offset + self.visible_rows {
-            self.scroll_offset = next_index - self.visible_rows + 1;
-        } else if next_index < self.scroll_offset {
-            self.scroll_offset = next_index;
+        let selected = keep
+            .and_then(|id| self.view.iter().position(|&i| self.items[i].id == id))
+            .or_else(|| self.state.selected());
+        self.select(step(selected, self.view.len(), 0));
+    }
+
+    /// Applies `change` to the view options, keeping the selected node.
+    fn change_view(&mut self, change: impl FnOnce(&mut Self)) {
+        let selected_id = self.selected_item().map(|item| item.id.clone());
+        change(self);
+        self.rebuild_view(selected_id);
+    }
+
+    fn cycle_sort(&mut self) {
+        self.change_view(|app| {
+            app.sort = SortKey::cycle(app.sort);
+            app.descending = false;
+        });
+    }
+
+    fn reverse_sort(&mut self) {
+        if self.sort.is_some() {
+            self.change_view(|app| app.descending = !app.descending);
         }
     }
 
-    fn previous(&mut self) {
-        let selected = self.state.selected().unwrap_or(0);
-        let prev_index = if selected == 0 {
-            self.items.len() - 1
-        } else {
-            selected - 1
+    /// Steps the protocol filter through the protocols in the list, then off.
+    fn cycle_protocol(&mut self) {
+        let mut protocols: Vec<&str> = self.items.iter().map(|i| i.protocol.as_str()).collect();
+        protocols.sort_unstable();
+        protocols.dedup();
+
+        let next = match &self.filter.protocol {
+            None => protocols.first(),
+            Some(current) => protocols
+                .iter()
+                .position(|p| p == current)
+                .and_then(|i| protocols.get(i + 1)),
+        }
+        .map(|p| p.to_string());
+
+        self.change_view(|app| app.filter.protocol = next);
+    }
+
+    fn search_input(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.change_view(|app| app.filter.search.clear());
+            }
+            KeyCode::Backspace => self.change_view(|app| {
+                app.filter.search.pop();
+            }),
+            KeyCode::Char(c) => self.change_view(|app| app.filter.search.push(c)),
+            KeyCode::Down => self.next(),
+            KeyCode::Up => self.previous(),
+            _ => {}
+        }
+    }
+
+    /// Selects row `index` of the view and scrolls it into sight.
+    fn select(&mut self, index: Option<usize>) {
+        self.state.select(index);
+        self.scroll_to_selected();
+    }
+
+    /// Keeps the selected row within the visible window and the window
+    /// within the list.
+    fn scroll_to_selected(&mut self) {
+        let max_offset = self.view.len().saturating_sub(self.visible_rows);
+        if let Some(selected) = self.state.selected() {
+            if selected < self.scroll_offset {
+                self.scroll_offset = selected;
+            } else if selected >= self.scroll_offset + self.visible_rows {
+                self.scroll_offset = selected + 1 - self.visible_rows;
+            }
+        }
+        self.scroll_offset = self.scroll_offset.min(max_offset);
+    }
+
+    /// Takes in an update meant for this tab.
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Nodes(nodes) => self.set_items(nodes),
+            Update::Event(event) => {
+                match &event {
+                    DaemonEvent::HealthCheckFailed { id, .. } => {
+                        self.set_test_result(id, TestResult::Failed)
+                    }
+                    DaemonEvent::NodeSwitched { .. } => self.daemon.load_nodes(),
+                }
+                self.status = Some(Line::from(Span::styled(
+                    event.to_string(),
+                    Style::default().fg(theme().info),
+                )));
+            }
+            Update::Selected { id, result } => {
+                self.switching = None;
+                match result {
+                    Ok(msg) => {
+                        for item in &mut self.items {
+                            item.active = item.id == id;
+                        }
+                        self.status = Some(Line::from(Span::styled(
+                            msg,
+                            Style::default().fg(theme().good),
+                        )));
+                    }
+                    Err(e) => {
+                        self.status = Some(Line::from(Span::styled(
+                            format!("cannot switch: {}", e),
+                            Style::default().fg(theme().error),
+                        )));
+                    }
+                }
+                self.daemon.load_nodes();
+            }
+            Update::Favourite {
+                id,
+                favourite,
+                result,
+            } => match result {
+                Ok(msg) => {
+                    if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+                        item.favourite = favourite;
+                        self.change_view(|_| {});
+                    }
+                    self.status = Some(Line::from(Span::styled(
+                        msg,
+                        Style::default().fg(theme().good),
+                    )));
+                }
+                Err(e) => {
+                    self.status = Some(Line::from(Span::styled(
+                        format!("cannot change favourites: {}", e),
+                        Style::default().fg(theme().error),
+                    )));
+                }
+            },
+            Update::Details { id, result } => {
+                if let Some(pane) = self.details.as_mut().filter(|pane| pane.id == id) {
+                    pane.data = Some(result);
+                }
+            }
+            Update::Tested { id, result } => {
+                if let Some(testing) = &mut self.testing {
+                    testing.done += 1;
+                }
+                self.set_test_result(
+                    &id,
+                    result
+                        .latency_ms
+                        .map_or(TestResult::Failed, TestResult::Latency),
+                );
+            }
+            Update::TestFinished(result) => {
+                let Some(testing) = self.testing.take() else {
+                    return;
+                };
+                self.status = Some(match result {
+                    Ok(()) => Line::from(Span::styled(
+                        format!("tested {} nodes", testing.done),
+                        Style::default().fg(theme().good),
+                    )),
+                    Err(e) => Line::from(Span::styled(
+                        format!("latency test failed: {}", e),
+                        Style::default().fg(theme().error),
+                    )),
+                });
+            }
+            Update::Error(e) => {
+                self.status = Some(Line::from(Span::styled(
+                    e,
+                    Style::default().fg(theme().error),
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    fn open_details(&mut self) {
+        if let Some(id) = self.selected_item().map(|item| item.id.clone()) {
+            self.daemon.node_details(id.clone());
+            self.details = Some(DetailPane {
+                id,
+                data: None,
+                scroll: 0,
+            });
+        }
+    }
+
+    fn details_input(&mut self, actions: &[TuiAction]) {
+        let Some(pane) = &mut self.details else {
+            return;
         };
 
-        self.state.select(Some(prev_index));
+        let Some(action) = actions.iter().find(|action| {
+            matches!(
+                action,
+                TuiAction::Cancel
+                    | TuiAction::Details
+                    | TuiAction::Down
+                    | TuiAction::Up
+                    | TuiAction::Copy
+            )
+        }) else {
+            return;
+        };
+        match action {
+            TuiAction::Cancel | TuiAction::Details => self.details = None,
+            TuiAction::Down => pane.scroll = pane.scroll.saturating_add(1),
+            TuiAction::Up => pane.scroll = pane.scroll.saturating_sub(1),
+            TuiAction::Copy => {
+                let Some(Ok(details)) = &pane.data else {
+                    return;
+                };
+                self.status = Some(match copy_to_clipboard(&details.uri) {
+                    Ok(()) => Line::from(Span::styled(
+                        format!("copied the share link of {}", details.info.name),
+                        Style::default().fg(theme().good),
+                    )),
+                    Err(e) => Line::from(Span::styled(
+                        format!("cannot copy: {}", e),
+                        Style::default().fg(theme().error),
+                    )),
+                });
+            }
+            _ => {}
+        }
+    }
 
-        // Обновляем скролл если необходимо
-        if prev_index < self.scroll_offset {
-            self.scroll_offset = prev_index;
-        } else if prev_index >= self.scroll_offset + self.visible_rows {
-            self.scroll_offset = prev_index - self.visible_rows + 1;
+    /// Updates the latency of node `id`, which may move or hide its row.
+    fn set_test_result(&mut self, id: &str, result: TestResult) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.test_result = result;
+            self.change_view(|_| {});
         }
     }
 
-    fn page_down(&mut self) {
-        let selected = self.state.selected().unwrap_or(0);
-        let new_index = (selected + self.visible_rows).min(self.items.len() - 1);
-        self.state.select(Some(new_index));
+    /// Switches xray to the selected node unless a switch is under way.
+    fn activate_selected(&mut self) {
+        if self.switching.is_some() {
+            return;
+        }
+        let Some((id, name)) = self
+            .selected_item()
+            .map(|item| (item.id.clone(), item.name.clone()))
+        else {
+            return;
+        };
+
+        self.switching = Some(Switching {
+            name,
+            started: Instant::now(),
+        });
+        self.daemon.select_node(id);
+    }
 
-        if new_index >= self.scroll_offset + self.visible_rows {
-            self.scroll_offset = new_index - self.visible_rows + 1;
+    /// Asks the daemon to flip the favourite mark of the selected node; the
+    /// row changes once it answers.
+    fn toggle_favourite(&mut self) {
+        if let Some(item) = self.selected_item() {
+            self.daemon.set_favourite(item.id.clone(), !item.favourite);
         }
     }
 
-    fn page_up(&mut self) {
-        let selected = self.state.selected().unwrap_or(0);
-        let new_index = if selected >= self.visible_rows {
-            selected - self.visible_rows
+    /// Starts a URL test of `ids`, all nodes if empty, unless one is running.
+    fn start_test(&mut self, ids: Vec<String>) {
+        if self.testing.is_some() || self.items.is_empty() {
+            return;
+        }
+
+        let total = if ids.is_empty() {
+            self.items.len()
         } else {
-            0
+            ids.len()
         };
-        self.state.select(Some(new_index));
+        self.testing = Some(Testing {
+            total,
+            done: 0,
+            handle: self.daemon.url_test(ids),
+        });
+    }
 
-        if new_index < self.scroll_offset {
-            self.scroll_offset = new_index;
+    fn test_selected(&mut self) {
+        if let Some(item) = self.selected_item() {
+            let ids = vec![item.id.clone()];
+            self.start_test(ids);
         }
     }
 
-    fn update_data(&mut self) {
-        if self.last_update.elapsed() >= Duration::from_secs(3) {
-            // Обновляем только значения и время, оставляем структуру
-            let mut rng = rand::thread_rng();
-            let statuses = [
-                "Active",
-                "Inactive",
-                "Pending",
-                "Complete",
-                "Error",
-                "Processing",
-                "Waiting",
-            ];
+    /// Tests every node that passes the filter, not only those on screen.
+    fn test_view(&mut self) {
+        let ids = self
+            .view
+            .iter()
+            .map(|&i| self.items[i].id.clone())
+            .collect();
+        self.start_test(ids);
+    }
 
-            // for item in &mut self.items {
-            //     item.value = rng.gen_range(0.0..1000.0);
-            //     item.timestamp = chrono::Utc::now().format("%H:%M:%S").to_string();
-            //     if rng.gen_bool(0.2) {
-            //         // 20% шанс изменить статус
-            //         item.status = statuses[rng.gen_range(0..statuses.len())].to_string();
-            //     }
-            // }
+    fn cancel_test(&mut self) {
+        if let Some(testing) = self.testing.take() {
+            testing.handle.abort();
+            self.status = Some(Line::from(Span::styled(
+                format!(
+                    "latency test cancelled after {}/{} nodes",
+                    testing.done, testing.total
+                ),
+                Style::default().fg(theme().warning),
+            )));
+        }
+    }
 
-            self.last_update = Instant::now();
+    fn status_line(&self) -> Option<Line<'static>> {
+        if self.searching {
+            return Some(Line::from(vec![
+                Span::styled("/", Style::default().fg(theme().accent)),
+                Span::raw(self.filter.search.clone()),
+                Span::styled("▏", Style::default().fg(theme().accent)),
+            ]));
         }
+
+        let Some(switching) = &self.switching else {
+            return self.status.clone();
+        };
+
+        let frame = switching.started.elapsed().as_millis() / 80;
+        Some(Line::from(Span::styled(
+            format!(
+                "{} switching to {}, reconfiguring xray…",
+                SPINNER[frame as usize % SPINNER.len()],
+                switching.name
+            ),
+            Style::default().fg(theme().warning),
+        )))
+    }
+
+    /// Selects the next row, wrapping from the last to the first.
+    fn next(&mut self) {
+        let next = match self.state.selected() {
+            Some(i) if i + 1 < self.view.len() => Some(i + 1),
+            _ => step(None, self.view.len(), 0),
+        };
+        self.select(next);
+    }
+
+    /// Selects the previous row, wrapping from the first to the last.
+    fn previous(&mut self) {
+        let previous = match self.state.selected() {
+            Some(i) if i > 0 && i < self.view.len() => Some(i - 1),
+            _ => self.view.len().checked_sub(1),
+        };
+        self.select(previous);
+    }
+
+    fn page_down(&mut self) {
+        let page = self.visible_rows as isize;
+        self.select(step(self.state.selected(), self.view.len(), page));
+    }
+
+    fn page_up(&mut self) {
+        let page = self.visible_rows as isize;
+        self.select(step(self.state.selected(), self.view.len(), -page));
+    }
+
+    /// Moves the window by `delta` rows, taking the selection along when it
+    /// would scroll out of sight.
+    fn scroll_by(&mut self, delta: isize) {
+        let offset = self.scroll_offset.saturating_add_signed(delta);
+        self.scroll_to(offset);
+    }
+
+    fn scroll_to(&mut self, offset: usize) {
+        let max_offset = self.view.len().saturating_sub(self.visible_rows);
+        self.scroll_offset = offset.min(max_offset);
+
+        let last_shown = (self.scroll_offset + self.visible_rows).min(self.view.len());
+        if let Some(selected) = self.state.selected()
+            && last_shown > 0
+        {
+            let selected = selected.clamp(self.scroll_offset, last_shown - 1);
+            self.state.select(Some(selected));
+        }
+    }
+
+    /// Scrolls so that the thumb sits at screen row `row` of the scrollbar.
+    fn drag_scrollbar(&mut self, row: u16) {
+        // Дорожка скролл-бара без рамок
+        let track = self.scrollbar_area.height.saturating_sub(2) as usize;
+        let Some(last) = track.checked_sub(1).filter(|&last| last > 0) else {
+            return;
+        };
+        let position = (row.saturating_sub(self.scrollbar_area.y + 1) as usize).min(last);
+        let max_offset = self.view.len().saturating_sub(self.visible_rows);
+        self.scroll_to((position * max_offset + last / 2) / last);
     }
 
     fn update_visible_rows(&mut self, height: usize) {
-        // Высота таблицы = общая высота - заголовок - рамки - панель помощи
-        // Вычитаем 6: 2 для рамок таблицы, 1 для заголовка, 3 для панели помощи
-        self.visible_rows = if height > 6 { height - 6 } else { 1 };
+        // Высота таблицы без рамок и заголовка
+        self.visible_rows = height.saturating_sub(3).max(1);
+        self.scroll_to_selected();
     }
 
     fn get_visible_items(&self) -> Vec<&SubData> {
-        let end = (self.scroll_offset + self.visible_rows).min(self.items.len());
-        self.items[self.scroll_offset..end].iter().collect()
+        let end = (self.scroll_offset + self.visible_rows).min(self.view.len());
+        self.view[self.scroll_offset.min(end)..end]
+            .iter()
+            .map(|&i| &self.items[i])
+            .collect()
     }
 
     fn get_scroll_progress(&self) -> f64 {
-        if self.items.len() <= self.visible_rows {
+        if self.view.len() <= self.visible_rows {
             1.0
         } else {
-            self.scroll_offset as f64 / (self.items.len() - self.visible_rows) as f64
+            self.scroll_offset as f64 / (self.view.len() - self.visible_rows) as f64
         }
     }
 
     fn get_scroll_thumb_position(&self, scrollbar_height: usize) -> usize {
-        if self.items.len() <= self.visible_rows {
+        if self.view.len() <= self.visible_rows {
             0
         } else {
             let progress = self.get_scroll_progress();
@@ -185,144 +741,193 @@ impl App {
     }
 }
 
-pub fn init() -> Result<()> {
-    // Настройка терминала
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
-    // Создаем приложение
-    let mut app = App::new();
-
-    // Основной цикл
-    let res = run_app(&mut terminal, &mut app);
-
-    // Восстанавливаем терминал
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+impl NodeTable {
+    /// Whether keys are typed into the search prompt.
+    pub fn captures_keys(&self) -> bool {
+        self.searching
+    }
 
-    if let Err(err) = res {
-        println!("{err:?}");
+    pub fn handle_key(&mut self, code: KeyCode, actions: &[TuiAction]) {
+        if self.searching {
+            self.search_input(code);
+            return;
+        }
+        if self.details.is_some() {
+            self.details_input(actions);
+            return;
+        }
+
+        for action in actions {
+            match action {
+                TuiAction::Down => self.next(),
+                TuiAction::Up => self.previous(),
+                TuiAction::PageDown => self.page_down(),
+                TuiAction::PageUp => self.page_up(),
+                TuiAction::Reload => self.daemon.load_nodes(),
+                TuiAction::Select => self.activate_selected(),
+                TuiAction::Test => self.test_selected(),
+                TuiAction::TestFiltered => self.test_view(),
+                TuiAction::TestAll => self.start_test(Vec::new()),
+                TuiAction::Cancel => self.cancel_test(),
+                TuiAction::Sort => self.cycle_sort(),
+                TuiAction::ReverseSort => self.reverse_sort(),
+                TuiAction::AliveOnly => {
+                    self.change_view(|app| app.filter.alive = !app.filter.alive)
+                }
+                TuiAction::Protocol => self.cycle_protocol(),
+                TuiAction::Favourites => {
+                    self.change_view(|app| app.filter.favourites = !app.filter.favourites)
+                }
+                TuiAction::Favourite => self.toggle_favourite(),
+                TuiAction::Search => self.searching = true,
+                TuiAction::Details => self.open_details(),
+                TuiAction::First => self.select(step(None, self.view.len(), 0)),
+                TuiAction::Last => self.select(self.view.len().checked_sub(1)),
+                _ => continue,
+            }
+            return;
+        }
     }
 
-    Ok(())
-}
+    pub fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if let Some(pane) = &mut self.details {
+            match mouse.kind {
+                MouseEventKind::ScrollDown => pane.scroll = pane.scroll.saturating_add(3),
+                MouseEventKind::ScrollUp => pane.scroll = pane.scroll.saturating_sub(3),
+                _ => {}
+            }
+            return;
+        }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
-    loop {
-        terminal.draw(|f| ui(f, app))?;
-
-        // Обновляем данные каждые 3 секунды
-        app.update_data();
-
-        // Обрабатываем события
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    match key.code {
-                        KeyCode::Char('q') => return Ok(()),
-                        KeyCode::Down => app.next(),
-                        KeyCode::Up => app.previous(),
-                        KeyCode::PageDown => app.page_down(),
-                        KeyCode::PageUp => app.page_up(),
-                        KeyCode::Home => {
-                            app.state.select(Some(0));
-                            app.scroll_offset = 0;
-                        }
-                        KeyCode::End => {
-                            let last_index = app.items.len() - 1;
-                            app.state.select(Some(last_index));
-                            app.scroll_offset = if last_index >= app.visible_rows {
-                                last_index - app.visible_rows + 1
-                            } else {
-                                0
-                            };
-                        }
-                        _ => {}
-                    }
+        let position = Position::new(mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::ScrollDown => self.scroll_by(WHEEL_ROWS),
+            MouseEventKind::ScrollUp => self.scroll_by(-WHEEL_ROWS),
+            MouseEventKind::Down(MouseButton::Left) if self.scrollbar_area.contains(position) => {
+                self.dragging = true;
+                self.drag_scrollbar(mouse.row);
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging => {
+                self.drag_scrollbar(mouse.row)
+            }
+            MouseEventKind::Up(MouseButton::Left) => self.dragging = false,
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(index) = table_row(self.table_area, self.scroll_offset, mouse)
+                    .filter(|&index| index < self.view.len())
+                {
+                    self.select(Some(index));
                 }
             }
+            _ => {}
         }
     }
-}
 
-fn ui(f: &mut Frame, app: &mut App) {
-    // Обновляем количество видимых строк на основе размера терминала
-    app.update_visible_rows(f.size().height as usize);
+    /// The test gauge, search prompt or latest message.
+    pub fn render_status(&self, f: &mut Frame, area: Rect) {
+        if let Some(testing) = self.testing.as_ref().filter(|_| !self.searching) {
+            let gauge = Gauge::default()
+                .gauge_style(Style::default().fg(theme().info).bg(theme().muted))
+                .ratio((testing.done as f64 / testing.total.max(1) as f64).min(1.0))
+                .label(format!(
+                    "testing {}/{}, {} to cancel",
+                    testing.done,
+                    testing.total,
+                    keymap().label(&[TuiAction::Cancel])
+                ));
+            f.render_widget(gauge, area);
+        } else if let Some(status) = self.status_line() {
+            f.render_widget(Paragraph::new(status), area);
+        }
+    }
 
-    let main_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Min(5), Constraint::Length(3)])
-        .split(f.size());
+    pub fn render(&mut self, f: &mut Frame, area: Rect) {
+        render_table(f, self, area);
+    }
 
-    // Разделяем верхнюю часть на таблицу и скролл-бар
+    /// The detail popup, if open, drawn over the whole screen.
+    pub fn render_popup(&self, f: &mut Frame) {
+        if let Some(pane) = &self.details {
+            render_details(f, pane);
+        }
+    }
+}
+
+fn render_table(f: &mut Frame, app: &mut NodeTable, area: Rect) {
+    // Обновляем количество видимых строк на основе размера таблицы
+    app.update_visible_rows(area.height as usize);
+
+    // Разделяем область на таблицу и скролл-бар
     let table_layout = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Min(10), Constraint::Length(3)])
-        .split(main_layout[0]);
+        .split(area);
+    app.table_area = table_layout[0];
+    app.scrollbar_area = table_layout[1];
 
-    let header_cells = ["ID", "Name", "Status", "Value", "Time"].iter().map(|h| {
-        Cell::from(*h).style(
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        )
-    });
+    let header_cells = ["Name", "Protocol", "Address", "Subscription", "Latency"]
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            let label = match app.sort {
+                Some(key) if key.column() == i => {
+                    format!("{} {}", h, if app.descending { "▼" } else { "▲" })
+                }
+                _ => h.to_string(),
+            };
+            Cell::from(label).style(theme().header())
+        });
 
-    let header = Row::new(header_cells)
-        .style(Style::default().bg(Color::Blue))
-        .height(1);
+    let header = Row::new(header_cells).style(theme().header_row()).height(1);
 
     // Получаем только видимые элементы
     let visible_items = app.get_visible_items();
-    let rows = visible_items.iter().enumerate().map(|(i, item)| {
-        let actual_index = app.scroll_offset + i;
+    let rows = visible_items.iter().map(|item| {
+        let name = format!(
+            "{}{}{}",
+            if item.active { "● " } else { "" },
+            if item.favourite { "★ " } else { "" },
+            item.name
+        );
         let cells = vec![
-            Cell::from(format!("{:03}", item.id)),
-            Cell::from(item.name.clone()),
-            // Cell::from(item.status.clone()).style(match item.status.as_str() {
-            //     "Active" => Style::default()
-            //         .fg(Color::Green)
-            //         .add_modifier(Modifier::BOLD),
-            //     "Error" => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-            //     "Pending" => Style::default()
-            //         .fg(Color::Yellow)
-            //         .add_modifier(Modifier::BOLD),
-            //     "Processing" => Style::default()
-            //         .fg(Color::Cyan)
-            //         .add_modifier(Modifier::BOLD),
-            //     "Complete" => Style::default()
-            //         .fg(Color::Magenta)
-            //         .add_modifier(Modifier::BOLD),
-            //     "Waiting" => Style::default()
-            //         .fg(Color::Blue)
-            //         .add_modifier(Modifier::BOLD),
-            //     _ => Style::default().fg(Color::Gray),
-            // }),
-            Cell::from(format!("{}", item.protocol)),
-            Cell::from(format!("{}", item.address)),
+            Cell::from(name),
+            Cell::from(item.protocol.clone()),
+            Cell::from(item.address.clone()),
+            Cell::from(item.subscription.clone()),
+            Cell::from(item.test_result.text()).style(item.test_result.style()),
         ];
-        Row::new(cells).height(1)
+        let row = Row::new(cells).height(1);
+        if item.active {
+            row.style(
+                Style::default()
+                    .fg(theme().good)
+                    .add_modifier(Modifier::BOLD),
+            )
+        } else {
+            row
+        }
     });
 
     // Создаем заголовок с информацией о скролле
-    let selected = app.state.selected().unwrap_or(0);
-    let title = format!("🚀 Demo Table ({}/{})", selected + 1, app.items.len());
+    let selected = app.state.selected().map_or(0, |i| i + 1);
+    let title = if app.filter.is_active() {
+        format!(
+            "🚀 Nodes ({}/{} of {}) {}",
+            selected,
+            app.view.len(),
+            app.items.len(),
+            app.filter.describe().join(" · ")
+        )
+    } else {
+        format!("🚀 Nodes ({}/{})", selected, app.items.len())
+    };
 
     let table = Table::new(
         rows,
         [
-            Constraint::Length(5),
+            Constraint::Fill(1),
             Constraint::Length(12),
-            Constraint::Length(12),
-            Constraint::Length(10),
+            Constraint::Length(28),
+            Constraint::Length(16),
             Constraint::Length(10),
         ],
     )
@@ -332,84 +937,166 @@ fn ui(f: &mut Frame, app: &mut App) {
             .borders(Borders::ALL)
             .title(title)
             .title_alignment(Alignment::Center)
-            .style(Style::default().fg(Color::White)),
-    )
-    .highlight_style(
-        Style::default()
-            .add_modifier(Modifier::REVERSED)
-            .fg(Color::Yellow),
+            .style(Style::default().fg(theme().text)),
     )
+    .row_highlight_style(theme().highlighted())
     .highlight_symbol(">> ");
 
     // Создаем состояние для отображения выделения относительно видимых элементов
     let mut display_state = TableState::default();
-    if let Some(selected_index) = app.state.selected() {
-        if selected_index >= app.scroll_offset
-            && selected_index < app.scroll_offset + app.visible_rows
-        {
-            display_state.select(Some(selected_index - app.scroll_offset));
-        }
+    if let Some(selected_index) = app.state.selected()
+        && selected_index >= app.scroll_offset
+        && selected_index < app.scroll_offset + app.visible_rows
+    {
+        display_state.select(Some(selected_index - app.scroll_offset));
     }
 
     f.render_stateful_widget(table, table_layout[0], &mut display_state);
 
     // Рендерим скролл-бар
     render_scrollbar(f, app, table_layout[1]);
+}
 
-    let help_message = Line::from(vec![
-        Span::styled("Press ", Style::default().fg(Color::Gray)),
-        Span::styled(
-            "q",
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(" to quit, ", Style::default().fg(Color::Gray)),
-        Span::styled(
-            "↑/↓",
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(" to navigate, ", Style::default().fg(Color::Gray)),
-        Span::styled(
-            "PgUp/PgDn",
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(" to scroll, ", Style::default().fg(Color::Gray)),
-        Span::styled(
-            "Home/End",
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(" to jump, ", Style::default().fg(Color::Gray)),
-        Span::styled(
-            "r",
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(" to refresh", Style::default().fg(Color::Gray)),
-    ]);
-
-    let help = ratatui::widgets::Paragraph::new(help_message)
-        .block(Block::default().borders(Borders::ALL).title("Controls"))
-        .alignment(Alignment::Center);
+fn render_details(f: &mut Frame, pane: &DetailPane) {
+    let area = centered(f.area(), 80, 80);
+    f.render_widget(Clear, area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title_alignment(Alignment::Center)
+        .title_bottom(
+            Line::from(format!(
+                " {} copy share link · {} scroll · {} close ",
+                keymap().label(&[TuiAction::Copy]),
+                keymap().label(&[TuiAction::Up, TuiAction::Down]),
+                keymap().label(&[TuiAction::Cancel]),
+            ))
+            .centered(),
+        )
+        .style(Style::default().fg(theme().text));
+
+    let details = match &pane.data {
+        None => {
+            f.render_widget(Paragraph::new("loading…").block(block), area);
+            return;
+        }
+        Some(Err(e)) => {
+            let text = Span::styled(e.clone(), Style::default().fg(theme().error));
+            f.render_widget(Paragraph::new(text).block(block), area);
+            return;
+        }
+        Some(Ok(details)) => details,
+    };
+
+    let block = block.title(format!(" {} ({}) ", details.info.name, details.info.id));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(4)])
+        .split(inner);
+
+    let label = Style::default()
+        .fg(theme().info)
+        .add_modifier(Modifier::BOLD);
+    let heading = Style::default()
+        .fg(theme().accent)
+        .add_modifier(Modifier::BOLD);
+    let width = details
+        .fields
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .chain([13])
+        .max()
+        .unwrap_or(0);
+
+    let mut lines: Vec<Line> = details
+        .fields
+        .iter()
+        .map(|(name, value)| {
+            Line::from(vec![
+                Span::styled(format!("{:<width$}  ", name), label),
+                Span::raw(value.clone()),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(vec![
+        Span::styled(format!("{:<width$}  ", "subscriptions"), label),
+        Span::raw(details.info.subscriptions.join(", ")),
+    ]));
+    if !details.info.tags.is_empty() {
+        lines.push(Line::from(vec![
+            Span::styled(format!("{:<width$}  ", "tags"), label),
+            Span::raw(details.info.tags.join(", ")),
+        ]));
+    }
+
+    lines.push(Line::default());
+    lines.push(Line::from(Span::styled("Share link", heading)));
+    lines.push(Line::from(details.uri.clone()));
 
-    f.render_widget(help, main_layout[1]);
+    lines.push(Line::default());
+    lines.push(Line::from(Span::styled("Latency history", heading)));
+    if details.latency_history.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "never tested",
+            Style::default().fg(theme().muted),
+        )));
+    }
+    for sample in details.latency_history.iter().rev() {
+        let result = sample
+            .latency_ms
+            .map_or(TestResult::Failed, TestResult::Latency);
+        lines.push(Line::from(vec![
+            Span::raw(format!(
+                "{} {:<7} ",
+                sample.at.format("%Y-%m-%d %H:%M:%S"),
+                sample.kind
+            )),
+            Span::styled(result.text(), result.style()),
+        ]));
+    }
+
+    let text = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((pane.scroll, 0));
+    f.render_widget(text, layout[0]);
+
+    let history: Vec<Option<u64>> = details
+        .latency_history
+        .iter()
+        .map(|sample| sample.latency_ms.map(u64::from))
+        .collect();
+    let sparkline = Sparkline::default()
+        .block(Block::default().borders(Borders::TOP).title("latency"))
+        .data(&history)
+        .style(Style::default().fg(theme().info))
+        .absent_value_style(Style::default().fg(theme().error))
+        .absent_value_symbol("×");
+    f.render_widget(sparkline, layout[1]);
 }
 
-fn render_scrollbar(f: &mut Frame, app: &App, area: Rect) {
-    if app.items.len() <= app.visible_rows {
+/// Puts `text` on the clipboard through the terminal with an OSC 52
+/// escape sequence, which also works over SSH.
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(
+        stdout,
+        "\x1b]52;c;{}\x07",
+        general_purpose::STANDARD.encode(text)
+    )?;
+    stdout.flush()
+}
+
+fn render_scrollbar(f: &mut Frame, app: &NodeTable, area: Rect) {
+    if app.view.len() <= app.visible_rows {
         // Если все элементы помещаются, показываем пустой скролл-бар
         let scrollbar = Block::default()
             .borders(Borders::ALL)
             .title("━")
             .title_alignment(Alignment::Center)
-            .style(Style::default().fg(Color::DarkGray));
+            .style(Style::default().fg(theme().muted));
         f.render_widget(scrollbar, area);
         return;
     }
@@ -417,24 +1104,28 @@ fn render_scrollbar(f: &mut Frame, app: &App, area: Rect) {
     // Вычисляем размеры скролл-бара
     let scrollbar_height = area.height.saturating_sub(2) as usize; // Вычитаем рамки
     let thumb_position = app.get_scroll_thumb_position(scrollbar_height);
-    let thumb_size = (scrollbar_height * app.visible_rows / app.items.len()).max(1);
+    let thumb_size = (scrollbar_height * app.visible_rows / app.view.len()).max(1);
 
     // Создаем содержимое скролл-бара
     let mut scrollbar_content = Vec::new();
     for i in 0..scrollbar_height {
         if i >= thumb_position && i < thumb_position + thumb_size {
-            // Ползунок скролла
+            // Ползунок скролла, подсвеченный пока его тянут мышью
             scrollbar_content.push(Line::from(Span::styled(
                 "█",
                 Style::default()
-                    .fg(Color::Yellow)
+                    .fg(if app.dragging {
+                        theme().info
+                    } else {
+                        theme().accent
+                    })
                     .add_modifier(Modifier::BOLD),
             )));
         } else {
             // Фон скролла
             scrollbar_content.push(Line::from(Span::styled(
                 "░",
-                Style::default().fg(Color::DarkGray),
+                Style::default().fg(theme().muted),
             )));
         }
     }
@@ -443,7 +1134,7 @@ fn render_scrollbar(f: &mut Frame, app: &App, area: Rect) {
     let position_info = format!(
         "{}-{}",
         app.scroll_offset + 1,
-        (app.scroll_offset + app.visible_rows).min(app.items.len())
+        (app.scroll_offset + app.visible_rows).min(app.view.len())
     );
 
     let scrollbar = Paragraph::new(scrollbar_content)
@@ -452,9 +1143,293 @@ fn render_scrollbar(f: &mut Frame, app: &App, area: Rect) {
                 .borders(Borders::ALL)
                 .title(position_info)
                 .title_alignment(Alignment::Center)
-                .style(Style::default().fg(Color::Gray)),
+                .style(Style::default().fg(theme().text)),
         )
         .alignment(Alignment::Center);
 
     f.render_widget(scrollbar, area);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, protocol: &str, latency_ms: Option<u32>, favourite: bool) -> NodeInfo {
+        NodeInfo {
+            id: id.to_string(),
+            protocol: protocol.to_string(),
+            address: format!("{}.example.com", id),
+            port: 443,
+            name: id.to_uppercase(),
+            active: false,
+            favourite,
+            tags: Vec::new(),
+            subscriptions: vec![String::from("main")],
+            latency_ms,
+        }
+    }
+
+    /// A table whose daemon is never asked anything.
+    fn table(nodes: Vec<NodeInfo>) -> NodeTable {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let (updates, _) = std::sync::mpsc::channel();
+        NodeTable::new(nodes, Daemon::new(updates))
+    }
+
+    fn nodes() -> Vec<NodeInfo> {
+        vec![
+            node("delta", "vless", Some(300), false),
+            node("alpha", "vmess", None, true),
+            node("charlie", "vless", Some(100), true),
+            node("bravo", "trojan", Some(200), false),
+        ]
+    }
+
+    fn shown(table: &NodeTable) -> Vec<&str> {
+        table
+            .view
+            .iter()
+            .map(|&i| table.items[i].id.as_str())
+            .collect()
+    }
+
+    fn selected(table: &NodeTable) -> Option<&str> {
+        table.selected_item().map(|item| item.id.as_str())
+    }
+
+    #[test]
+    fn filter_matches() {
+        let items: Vec<SubData> = nodes().into_iter().map(SubData::from).collect();
+        let passing = |filter: Filter| -> Vec<&str> {
+            items
+                .iter()
+                .filter(|item| filter.matches(item))
+                .map(|item| item.id.as_str())
+                .collect()
+        };
+
+        assert_eq!(
+            passing(Filter::default()),
+            ["delta", "alpha", "charlie", "bravo"]
+        );
+        let alive = Filter {
+            alive: true,
+            ..Filter::default()
+        };
+        assert_eq!(passing(alive), ["delta", "charlie", "bravo"]);
+        let vless = Filter {
+            protocol: Some(String::from("vless")),
+            ..Filter::default()
+        };
+        assert_eq!(passing(vless), ["delta", "charlie"]);
+        let favourites = Filter {
+            favourites: true,
+            ..Filter::default()
+        };
+        assert_eq!(passing(favourites), ["alpha", "charlie"]);
+        // Name and address, either case.
+        let search = Filter {
+            search: String::from("Bra"),
+            ..Filter::default()
+        };
+        assert_eq!(passing(search), ["bravo"]);
+        let search = Filter {
+            search: String::from("charlie.EXAMPLE"),
+            ..Filter::default()
+        };
+        assert_eq!(passing(search), ["charlie"]);
+        let combined = Filter {
+            alive: true,
+            favourites: true,
+            ..Filter::default()
+        };
+        assert!(combined.is_active());
+        assert_eq!(passing(combined), ["charlie"]);
+        assert!(!Filter::default().is_active());
+    }
+
+    #[test]
+    fn sort_keys() {
+        let items: Vec<SubData> = nodes().into_iter().map(SubData::from).collect();
+        let sorted = |key: SortKey| -> Vec<&str> {
+            let mut sorted: Vec<&SubData> = items.iter().collect();
+            sorted.sort_by(|a, b| key.compare(a, b));
+            sorted.iter().map(|item| item.id.as_str()).collect()
+        };
+
+        // Untested nodes go last.
+        assert_eq!(
+            sorted(SortKey::Latency),
+            ["charlie", "bravo", "delta", "alpha"]
+        );
+        assert_eq!(
+            sorted(SortKey::Name),
+            ["alpha", "bravo", "charlie", "delta"]
+        );
+        // Ties fall back to the name.
+        assert_eq!(
+            sorted(SortKey::Protocol),
+            ["bravo", "charlie", "delta", "alpha"]
+        );
+        assert_eq!(
+            sorted(SortKey::Subscription),
+            ["alpha", "bravo", "charlie", "delta"]
+        );
+
+        let mut key = None;
+        for _ in 0..5 {
+            key = SortKey::cycle(key);
+        }
+        assert_eq!(key, None);
+    }
+
+    #[test]
+    fn view_keeps_the_selected_node() {
+        let mut table = table(nodes());
+        assert_eq!(shown(&table), ["delta", "alpha", "charlie", "bravo"]);
+        assert_eq!(selected(&table), Some("delta"));
+
+        table.select(Some(2));
+        table.cycle_sort();
+        assert_eq!(table.sort, Some(SortKey::Latency));
+        assert_eq!(shown(&table), ["charlie", "bravo", "delta", "alpha"]);
+        assert_eq!(selected(&table), Some("charlie"));
+
+        table.reverse_sort();
+        assert_eq!(shown(&table), ["alpha", "delta", "bravo", "charlie"]);
+        assert_eq!(selected(&table), Some("charlie"));
+
+        table.change_view(|app| app.filter.favourites = true);
+        assert_eq!(shown(&table), ["alpha", "charlie"]);
+        assert_eq!(selected(&table), Some("charlie"));
+
+        // A hidden node leaves the selection at the same row.
+        table.select(Some(0));
+        table.change_view(|app| app.filter.alive = true);
+        assert_eq!(shown(&table), ["charlie"]);
+        assert_eq!(selected(&table), Some("charlie"));
+
+        table.change_view(|app| app.filter.search = String::from("nothing"));
+        assert!(shown(&table).is_empty());
+        assert_eq!(table.state.selected(), None);
+        assert!(table.selected_item().is_none());
+
+        table.change_view(|app| app.filter = Filter::default());
+        assert_eq!(selected(&table), Some("alpha"));
+    }
+
+    #[test]
+    fn new_nodes_keep_the_selection() {
+        let mut table = table(nodes());
+        table.select(Some(3));
+        assert_eq!(selected(&table), Some("bravo"));
+
+        let mut refreshed = nodes();
+        refreshed.remove(0);
+        table.set_items(refreshed);
+        assert_eq!(selected(&table), Some("bravo"));
+
+        table.set_items(Vec::new());
+        assert_eq!(table.state.selected(), None);
+    }
+
+    fn many(count: usize) -> Vec<NodeInfo> {
+        (0..count)
+            .map(|i| node(&format!("n{:02}", i), "vless", Some(100), i % 2 == 0))
+            .collect()
+    }
+
+    #[test]
+    fn scroll_to_keeps_the_selection_in_sight() {
+        let mut table = table(many(20));
+        table.visible_rows = 5;
+        table.select(Some(1));
+
+        table.scroll_to(3);
+        assert_eq!(table.scroll_offset, 3);
+        assert_eq!(table.state.selected(), Some(3));
+        table.scroll_to(100);
+        assert_eq!(table.scroll_offset, 15);
+        assert_eq!(table.state.selected(), Some(15));
+        table.select(Some(19));
+        table.scroll_to(0);
+        assert_eq!(table.state.selected(), Some(4));
+
+        // Ten favourites are left, so the window stops at five.
+        table.change_view(|app| app.filter.favourites = true);
+        table.scroll_to(100);
+        assert_eq!(table.scroll_offset, 5);
+        assert_eq!(table.get_visible_items().len(), 5);
+
+        let mut table = self::table(many(1));
+        table.visible_rows = 5;
+        table.scroll_to(4);
+        assert_eq!(table.scroll_offset, 0);
+        assert_eq!(table.state.selected(), Some(0));
+
+        let mut table = self::table(Vec::new());
+        table.scroll_to(4);
+        assert_eq!(table.scroll_offset, 0);
+        assert_eq!(table.state.selected(), None);
+        assert!(table.get_visible_items().is_empty());
+    }
+
+    #[test]
+    fn drag_scrollbar() {
+        let mut table = table(many(20));
+        table.visible_rows = 5;
+        // A track of ten rows, 11 to 20.
+        table.scrollbar_area = Rect::new(40, 10, 3, 12);
+
+        table.drag_scrollbar(11);
+        assert_eq!(table.scroll_offset, 0);
+        table.drag_scrollbar(20);
+        assert_eq!(table.scroll_offset, 15);
+        table.drag_scrollbar(15);
+        assert_eq!(table.scroll_offset, 7);
+        table.drag_scrollbar(0);
+        assert_eq!(table.scroll_offset, 0);
+        table.drag_scrollbar(100);
+        assert_eq!(table.scroll_offset, 15);
+
+        // Without a track of at least two rows the thumb cannot move.
+        for height in 0..=3 {
+            table.scrollbar_area = Rect::new(40, 10, 3, height);
+            table.drag_scrollbar(11);
+            assert_eq!(table.scroll_offset, 15);
+        }
+
+        // Nothing to scroll.
+        for count in [0, 1, 5] {
+            let mut table = self::table(many(count));
+            table.visible_rows = 5;
+            table.scrollbar_area = Rect::new(40, 10, 3, 12);
+            table.drag_scrollbar(20);
+            assert_eq!(table.scroll_offset, 0);
+        }
+    }
+
+    #[test]
+    fn favourite_answers_update_the_row() {
+        let mut table = table(nodes());
+        table.change_view(|app| app.filter.favourites = true);
+        assert_eq!(shown(&table), ["alpha", "charlie"]);
+
+        table.apply(Update::Favourite {
+            id: String::from("delta"),
+            favourite: true,
+            result: Ok(String::from("delta added to favourites")),
+        });
+        assert_eq!(shown(&table), ["delta", "alpha", "charlie"]);
+
+        table.apply(Update::Favourite {
+            id: String::from("alpha"),
+            favourite: false,
+            result: Err(String::from("unknown node id: alpha")),
+        });
+        assert_eq!(shown(&table), ["delta", "alpha", "charlie"]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Colours of the dashboard, from the `tui` section of luxnulla.kdl.
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use luxnulla::config::{ThemeName, ThemeSlot, TuiConfig};
+use ratatui::style::{Color, Modifier, Style};
+
+pub struct Theme {
+    pub text: Color,
+    pub muted: Color,
+    pub accent: Color,
+    pub header_fg: Color,
+    pub header_bg: Color,
+    pub highlight: Color,
+    pub good: Color,
+    pub warning: Color,
+    pub error: Color,
+    pub info: Color,
+    pub uplink: Color,
+    pub downlink: Color,
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// The theme set by `init`, or the dark one before that.
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(|| Theme::new(&TuiConfig::default()))
+}
+
+/// Sets the theme for the rest of the session; only the first call counts.
+pub fn init(config: &TuiConfig) {
+    let _ = THEME.set(Theme::new(config));
+}
+
+impl Theme {
+    fn new(config: &TuiConfig) -> Self {
+        let mut theme = match config.theme {
+            ThemeName::Dark => Self {
+                text: Color::Reset,
+                muted: Color::DarkGray,
+                accent: Color::Yellow,
+                header_fg: Color::Yellow,
+                header_bg: Color::Blue,
+                highlight: Color::Yellow,
+                good: Color::Green,
+                warning: Color::Yellow,
+                error: Color::Red,
+                info: Color::Cyan,
+                uplink: Color::Magenta,
+                downlink: Color::Green,
+            },
+            ThemeName::Light => Self {
+                text: Color::Black,
+                muted: Color::Gray,
+                accent: Color::Blue,
+                header_fg: Color::White,
+                header_bg: Color::Blue,
+                highlight: Color::Blue,
+                good: Color::Green,
+                warning: Color::Magenta,
+                error: Color::Red,
+                info: Color::Blue,
+                uplink: Color::Magenta,
+                downlink: Color::Green,
+            },
+            ThemeName::Mono => Self {
+                text: Color::Reset,
+                muted: Color::Reset,
+                accent: Color::Reset,
+                header_fg: Color::Reset,
+                header_bg: Color::Reset,
+                highlight: Color::Reset,
+                good: Color::Reset,
+                warning: Color::Reset,
+                error: Color::Reset,
+                info: Color::Reset,
+                uplink: Color::Reset,
+                downlink: Color::Reset,
+            },
+        };
+
+        // Значения уже проверены при разборе luxnulla.kdl
+        for (slot, value) in &config.colors {
+            if let Ok(color) = Color::from_str(value) {
+                *theme.slot(*slot) = color;
+            }
+        }
+        theme
+    }
+
+    fn slot(&mut self, slot: ThemeSlot) -> &mut Color {
+        match slot {
+            ThemeSlot::Text => &mut self.text,
+            ThemeSlot::Muted => &mut self.muted,
+            ThemeSlot::Accent => &mut self.accent,
+            ThemeSlot::HeaderFg => &mut self.header_fg,
+            ThemeSlot::HeaderBg => &mut self.header_bg,
+            ThemeSlot::Highlight => &mut self.highlight,
+            ThemeSlot::Good => &mut self.good,
+            ThemeSlot::Warning => &mut self.warning,
+            ThemeSlot::Error => &mut self.error,
+            ThemeSlot::Info => &mut self.info,
+            ThemeSlot::Uplink => &mut self.uplink,
+            ThemeSlot::Downlink => &mut self.downlink,
+        }
+    }
+
+    /// Column titles of a table.
+    pub fn header(&self) -> Style {
+        Style::default()
+            .fg(self.header_fg)
+            .add_modifier(Modifier::BOLD)
+    }
+
+    /// Background of the header row.
+    pub fn header_row(&self) -> Style {
+        Style::default().bg(self.header_bg)
+    }
+
+    /// The selected row.
+    pub fn highlighted(&self) -> Style {
+        Style::default()
+            .add_modifier(Modifier::REVERSED)
+            .fg(self.highlight)
+    }
+}
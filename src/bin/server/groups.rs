@@ -0,0 +1,114 @@
+use luxnulla::config::NodeGroup;
+
+use crate::{node_store::NodeStore, xray_parser::Node};
+
+/// Nodes of `nodes` that belong to `group`, in list order.
+pub fn members<'a>(group: &NodeGroup, nodes: &'a [Node], store: &NodeStore) -> Vec<&'a Node> {
+    nodes
+        .iter()
+        .filter(|node| matches(group, node, store))
+        .collect()
+}
+
+fn matches(group: &NodeGroup, node: &Node, store: &NodeStore) -> bool {
+    let name = node.config.name().unwrap_or_default();
+
+    if !group.subscriptions.is_empty()
+        && !node
+            .subscriptions
+            .iter()
+            .any(|s| group.subscriptions.contains(s))
+    {
+        return false;
+    }
+
+    if !group.protocols.is_empty()
+        && !group
+            .protocols
+            .iter()
+            .any(|p| p.eq_ignore_ascii_case(node.config.protocol()))
+    {
+        return false;
+    }
+
+    if !group.tags.is_empty() {
+        let tags = store
+            .get(&node.config.fingerprint())
+            .map(|s| s.tags.as_slice())
+            .unwrap_or_default();
+        if !tags.iter().any(|t| group.tags.contains(t)) {
+            return false;
+        }
+    }
+
+    if !group.countries.is_empty() && !country(name).is_some_and(|c| group.countries.contains(&c)) {
+        return false;
+    }
+
+    group.name_regex.as_ref().is_none_or(|re| re.is_match(name))
+}
+
+/// Guesses the country of a node from its name: a flag emoji first, then a
+/// standalone two-letter upper-case word such as `DE` in `"DE Frankfurt 2"`.
+pub fn country(name: &str) -> Option<String> {
+    let chars: Vec<char> = name.chars().collect();
+    for pair in chars.windows(2) {
+        if let (Some(a), Some(b)) = (regional_letter(pair[0]), regional_letter(pair[1])) {
+            return Some(format!("{}{}", a, b));
+        }
+    }
+
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|word| word.len() == 2 && word.chars().all(|c| c.is_ascii_uppercase()))
+        .map(String::from)
+}
+
+/// Maps a regional indicator symbol (the halves of a flag emoji) to its letter.
+fn regional_letter(c: char) -> Option<char> {
+    let offset = (c as u32).checked_sub(0x1F1E6)?;
+    if offset < 26 {
+        char::from_u32('A' as u32 + offset)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::country;
+    use crate::xray_parser::parse_line;
+
+    #[test]
+    fn flag_emoji() {
+        assert_eq!(country("🇩🇪 Frankfurt").as_deref(), Some("DE"));
+        assert_eq!(country("Tokyo 🇯🇵 2").as_deref(), Some("JP"));
+    }
+
+    #[test]
+    fn flag_wins_over_letters() {
+        assert_eq!(country("NL 🇩🇪 relay").as_deref(), Some("DE"));
+    }
+
+    #[test]
+    fn plain_code() {
+        assert_eq!(country("DE Frankfurt 2").as_deref(), Some("DE"));
+        assert_eq!(country("fast-US-east").as_deref(), Some("US"));
+    }
+
+    #[test]
+    fn no_country() {
+        assert_eq!(country("Frankfurt"), None);
+        assert_eq!(country("de frankfurt"), None);
+        assert_eq!(country("USA 1"), None);
+        assert_eq!(country(""), None);
+    }
+
+    #[test]
+    fn encoded_flag_in_share_link() {
+        let config =
+            parse_line("vless://id@example.com:443?type=tcp#%F0%9F%87%A9%F0%9F%87%AA%20Frankfurt")
+                .unwrap();
+        assert_eq!(config.name(), Some("🇩🇪 Frankfurt"));
+        assert_eq!(country(config.name().unwrap()).as_deref(), Some("DE"));
+    }
+}
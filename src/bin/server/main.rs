@@ -1,56 +1,114 @@
+use chrono::Utc;
 use dirs::config_dir;
 use eyre::OptionExt;
+use futures::StreamExt;
+use luxnulla::config::{
+    self, LuxnullaConfig, RoutingPreset, SelectionConfig, SelectionPolicy, StatsConfig,
+    Subscription, expand_chain,
+};
 use luxnulla::{
-    CONFIG_DIR, CommandRequest, CommandResponse, EDITOR_NAME, ErrorCommandResponse,
-    LUXNULLA_CONFIG_FILE, OkCommandResponse, SOCKET_NAME, XRAY_CONFIG_FILE,
+    CONFIG_DIR, CommandRequest, CommandResponse, DaemonEvent, DaemonStatus, EDITOR_NAME,
+    ErrorCommandResponse, LUXNULLA_CONFIG_FILE, LogLevel, LogStream, NODES_FILE, NodeDetails,
+    NodeInfo, OkCommandResponse, ProbeResult, RefreshReport, SOCKET_NAME, STATE_FILE, SampleKind,
+    SubscriptionInfo, SubscriptionReport, TproxyAction, TrafficStats, UrlTestResult,
+    XRAY_CONFIG_FILE, XRAY_LOG_FILE,
+};
+use node_store::NodeStore;
+use probe::ProbeTarget;
+use selection::Candidate;
+use state::DaemonState;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
 };
-use std::{fs, path::PathBuf, sync::Arc};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{UnixListener, UnixStream},
+    process::Child,
+    signal::unix::{SignalKind, signal},
+    sync::{
+        Mutex,
+        broadcast::{self, error::RecvError},
+    },
 };
+use traffic::TrafficMonitor;
+use url_test::{UrlTestCandidate, UrlTestSettings};
+use xray_logs::XrayLogs;
+use xray_parser::Node;
 
+mod groups;
+mod node_store;
+mod ports;
+mod probe;
+mod selection;
+mod state;
 mod subscribe_parse;
+mod tproxy;
+mod traffic;
+mod url_test;
+mod xray_check;
+mod xray_config;
+mod xray_logs;
 mod xray_parser;
 
+/// `UrlTest` ids with this prefix name a chain instead of a node.
+const CHAIN_ID_PREFIX: &str = "chain:";
+
+/// What `apply_config` puts behind the proxy target.
+enum Selection {
+    /// Whatever is selected now, rebuilt from the daemon state.
+    Current,
+    Node(serde_json::Value),
+    Group(String),
+    Chain(String),
+}
+
 struct Application {
     config_dir: PathBuf,
+    xray: Mutex<Option<Child>>,
+    logs: Arc<XrayLogs>,
+    nodes: Mutex<Vec<Node>>,
+    store: Mutex<NodeStore>,
+    state: Mutex<DaemonState>,
+    events: broadcast::Sender<DaemonEvent>,
+    /// Consecutive failed health checks of the active node.
+    health_failures: Mutex<u32>,
+    traffic: Mutex<TrafficMonitor>,
+    /// Held by `apply_config` from generating `xray.json` until xray runs
+    /// with it, so concurrent requests cannot interleave their writes.
+    applying: Mutex<()>,
 }
 
 impl Application {
     async fn handle_client(&self, mut sock: UnixStream) {
-        let mut buf = vec![0u8; 1024];
-        match sock.read(&mut buf).await {
+        let mut line = String::new();
+        match BufReader::new(&mut sock).read_line(&mut line).await {
             Ok(n) if n > 0 => {
-                let req: Result<CommandRequest, _> = serde_json::from_slice(&buf[..n]);
+                let req: Result<CommandRequest, _> = serde_json::from_str(&line);
 
                 let resp = match req {
-                    Ok(CommandRequest::Status) => CommandResponse::Ok(OkCommandResponse::Message(
-                        "Luxnulla-core is running".to_string(),
+                    Ok(CommandRequest::Status) => CommandResponse::Ok(OkCommandResponse::Status(
+                        Box::new(self.status().await),
                     )),
                     Ok(CommandRequest::Restart) => {
-                        let mock_plain_text_url = "https://raw.githubusercontent.com/Epodonios/v2ray-configs/refs/heads/main/Base64/Config%20list10_base64.txt";
-
-                        println!(
-                            "--- Fetching from plain text URL: {} ---",
-                            mock_plain_text_url
-                        );
-
-                        match subscribe_parse::fetch_and_parse_configs(mock_plain_text_url).await {
-                            Ok(configs) => {
-                                println!("Successfully parsed {} configs.", configs.len());
-                                xray_parser::work(configs);
-                            }
-                            Err(e) => {
-                                eprintln!("Error: {}", e);
-                            }
+                        match self.refresh_subscriptions().await {
+                            Ok(report) => println!("{}", report),
+                            Err(e) => eprintln!("Error: {}", e),
                         }
 
-                        CommandResponse::Ok(OkCommandResponse::Message(String::from("restart")))
+                        match self.start_xray().await {
+                            Ok(()) => CommandResponse::Ok(OkCommandResponse::Message(
+                                String::from("xray is restarted"),
+                            )),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
                     }
                     Ok(CommandRequest::EditXray) => {
                         tokio::process::Command::new(EDITOR_NAME)
-                            .arg(&self.config_dir.join(XRAY_CONFIG_FILE))
+                            .arg(self.config_dir.join(XRAY_CONFIG_FILE))
                             .spawn()
                             .unwrap();
 
@@ -60,7 +118,7 @@ impl Application {
                     }
                     Ok(CommandRequest::EditLuxnulla) => {
                         tokio::process::Command::new(EDITOR_NAME)
-                            .arg(&self.config_dir.join(LUXNULLA_CONFIG_FILE))
+                            .arg(self.config_dir.join(LUXNULLA_CONFIG_FILE))
                             .spawn()
                             .unwrap();
 
@@ -68,22 +126,177 @@ impl Application {
                             "zeditor is running",
                         )))
                     }
-                    Ok(CommandRequest::Start) => {
-                        let config_path_buf = self.config_dir.join(XRAY_CONFIG_FILE);
+                    Ok(CommandRequest::Start) => match self.start_xray().await {
+                        Ok(()) => CommandResponse::Ok(OkCommandResponse::Message(String::from(
+                            "xray is started",
+                        ))),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::Refresh) => match self.refresh_subscriptions().await {
+                        Ok(report) => CommandResponse::Ok(OkCommandResponse::Refreshed(report)),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::ListNodes) => {
+                        let selected = self.state.lock().await.selected_node.clone();
+                        let store = self.store.lock().await;
+                        let nodes = self.nodes.lock().await;
 
-                        let xray_config = match config_path_buf.to_str() {
-                            Some(s) => String::from(s),
-                            None => String::from("pizda pathbuffer"),
-                        };
+                        let infos = nodes
+                            .iter()
+                            .map(|node| node_info(node, &store, selected.as_deref()))
+                            .collect();
 
-                        tokio::process::Command::new("xray")
-                            .args(&["run", "-c", &xray_config])
-                            .spawn()
-                            .unwrap();
+                        CommandResponse::Ok(OkCommandResponse::Nodes(infos))
+                    }
+                    Ok(CommandRequest::NodeDetails { id }) => {
+                        let selected = self.state.lock().await.selected_node.clone();
+                        let store = self.store.lock().await;
+                        let nodes = self.nodes.lock().await;
 
-                        CommandResponse::Ok(OkCommandResponse::Message(String::from(
-                            "xray is started",
-                        )))
+                        match nodes.iter().find(|node| node.config.fingerprint() == id) {
+                            Some(node) => {
+                                CommandResponse::Ok(OkCommandResponse::NodeDetails(NodeDetails {
+                                    info: node_info(node, &store, selected.as_deref()),
+                                    fields: node.config.fields(),
+                                    uri: node.uri.clone(),
+                                    latency_history: store
+                                        .get(&id)
+                                        .map(|s| s.latency_history.clone())
+                                        .unwrap_or_default(),
+                                }))
+                            }
+                            None => CommandResponse::Err(ErrorCommandResponse::Message(format!(
+                                "unknown node id: {}",
+                                id
+                            ))),
+                        }
+                    }
+                    Ok(CommandRequest::ListSubscriptions) => {
+                        match self.list_subscriptions().await {
+                            Ok(subs) => CommandResponse::Ok(OkCommandResponse::Subscriptions(subs)),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::AddSubscription { url, name }) => {
+                        match self.add_subscription(&url, name.as_deref()).await {
+                            Ok(message) => CommandResponse::Ok(OkCommandResponse::Message(message)),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::RemoveSubscription { name }) => {
+                        match self.remove_subscription(&name).await {
+                            Ok(message) => CommandResponse::Ok(OkCommandResponse::Message(message)),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::Routing) => {
+                        let path = self.config_dir.join(XRAY_CONFIG_FILE);
+                        match std::fs::read_to_string(&path)
+                            .map_err(|e| format!("failed to read {:?}: {}", path, e))
+                            .and_then(|config| xray_config::routing_info(&config))
+                        {
+                            Ok(info) => CommandResponse::Ok(OkCommandResponse::Routing(info)),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::TuiConfig) => match self.load_config() {
+                        Ok(config) => CommandResponse::Ok(OkCommandResponse::TuiConfig(config.tui)),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::Traffic) => match self.traffic().await {
+                        Ok(stats) => CommandResponse::Ok(OkCommandResponse::Traffic(stats)),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::SetFavourite { id, favourite }) => {
+                        let mut store = self.store.lock().await;
+                        if store.set_favourite(&id, favourite) {
+                            self.save_store(&store);
+                            CommandResponse::Ok(OkCommandResponse::Message(format!(
+                                "{} {} favourites",
+                                id,
+                                if favourite {
+                                    "added to"
+                                } else {
+                                    "removed from"
+                                }
+                            )))
+                        } else {
+                            CommandResponse::Err(ErrorCommandResponse::Message(format!(
+                                "unknown node id: {}",
+                                id
+                            )))
+                        }
+                    }
+                    Ok(CommandRequest::SetTag { id, tag, present }) => {
+                        let mut store = self.store.lock().await;
+                        if store.set_tag(&id, &tag, present) {
+                            self.save_store(&store);
+                            CommandResponse::Ok(OkCommandResponse::Message(format!(
+                                "{} tag '{}' on {}",
+                                if present { "added" } else { "removed" },
+                                tag,
+                                id
+                            )))
+                        } else {
+                            CommandResponse::Err(ErrorCommandResponse::Message(format!(
+                                "unknown node id: {}",
+                                id
+                            )))
+                        }
+                    }
+                    Ok(CommandRequest::SelectGroup { name }) => {
+                        match self.select_group(&name).await {
+                            Ok(members) => CommandResponse::Ok(OkCommandResponse::Message(
+                                format!("balancing over {} nodes of group {}", members, name),
+                            )),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::SetRoutingPreset { presets }) => {
+                        match self.set_routing_presets(presets).await {
+                            Ok(message) => CommandResponse::Ok(OkCommandResponse::Message(message)),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::Tproxy { action }) => match self.tproxy(action).await {
+                        Ok(message) => CommandResponse::Ok(OkCommandResponse::Message(message)),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::SelectChain { name }) => {
+                        match self.select_chain(&name).await {
+                            Ok(hops) => CommandResponse::Ok(OkCommandResponse::Message(format!(
+                                "switched to chain {} ({} hops)",
+                                name, hops
+                            ))),
+                            Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                        }
+                    }
+                    Ok(CommandRequest::SelectNode { id }) => match self.select_node(id).await {
+                        Ok(name) => CommandResponse::Ok(OkCommandResponse::Message(format!(
+                            "switched to {}",
+                            name
+                        ))),
+                        Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(e)),
+                    },
+                    Ok(CommandRequest::Probe { ids }) => {
+                        self.probe_nodes(&mut sock, ids).await;
+                        return;
+                    }
+                    Ok(CommandRequest::UrlTest { ids }) => {
+                        self.url_test_nodes(&mut sock, ids).await;
+                        return;
+                    }
+                    Ok(CommandRequest::Events) => {
+                        self.stream_events(&mut sock).await;
+                        return;
+                    }
+                    Ok(CommandRequest::Logs {
+                        follow,
+                        lines,
+                        level,
+                    }) => {
+                        self.stream_logs(&mut sock, follow, lines, level).await;
+                        return;
                     }
 
                     Err(e) => CommandResponse::Err(ErrorCommandResponse::Message(format!(
@@ -91,20 +304,1118 @@ impl Application {
                         e
                     ))),
                 };
-                let out = serde_json::to_vec(&resp).unwrap();
-                let _ = sock.write_all(&out).await;
+                let _ = write_response(&mut sock, &resp).await;
             }
             _ => {}
         }
     }
+
+    /// Fetches every subscription in `luxnulla.kdl` and replaces the node list
+    /// with their deduplicated union. A subscription that fails to download
+    /// keeps the nodes it had before.
+    async fn refresh_subscriptions(&self) -> Result<RefreshReport, String> {
+        let config = self.load_config()?;
+
+        let fetched =
+            futures::future::join_all(config.subscriptions.iter().map(|sub| async move {
+                println!("--- Fetching subscription {}: {} ---", sub.name, sub.url);
+                (
+                    sub,
+                    subscribe_parse::fetch_and_parse_configs(&sub.url).await,
+                )
+            }))
+            .await;
+
+        // Failed subscriptions keep the nodes they had. The list in use stays
+        // as it is until the new one is complete.
+        let mut kept: HashMap<&str, Vec<Node>> = {
+            let previous = self.nodes.lock().await;
+            fetched
+                .iter()
+                .filter(|(_, result)| result.is_err())
+                .map(|(sub, _)| {
+                    let nodes = previous
+                        .iter()
+                        .filter(|node| node.subscriptions.contains(&sub.name))
+                        .filter_map(|node| {
+                            Some(Node {
+                                uri: node.uri.clone(),
+                                config: xray_parser::parse_line(&node.uri).ok()?,
+                                subscriptions: vec![sub.name.clone()],
+                            })
+                        })
+                        .collect();
+                    (sub.name.as_str(), nodes)
+                })
+                .collect()
+        };
+
+        let mut all = Vec::new();
+        let mut subscriptions = Vec::new();
+        let mut state = self.state.lock().await;
+
+        for (sub, result) in fetched {
+            let status = state.subscriptions.entry(sub.name.clone()).or_default();
+            match result {
+                Ok(fetched) => {
+                    status.usage = fetched.usage;
+                    status.updated = Some(Utc::now());
+                    status.error = None;
+
+                    let nodes = xray_parser::work(fetched.content, &sub.name);
+                    subscriptions.push(SubscriptionReport {
+                        name: sub.name.clone(),
+                        nodes: nodes.len(),
+                        error: None,
+                    });
+                    all.extend(nodes);
+                }
+                Err(e) => {
+                    let kept = kept.remove(sub.name.as_str()).unwrap_or_default();
+                    status.error = Some(e.to_string());
+                    subscriptions.push(SubscriptionReport {
+                        name: sub.name.clone(),
+                        nodes: kept.len(),
+                        error: Some(e.to_string()),
+                    });
+                    all.extend(kept);
+                }
+            }
+        }
+
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+        drop(state);
+
+        let (nodes, duplicates) = xray_parser::dedup(all);
+        let report = RefreshReport {
+            subscriptions,
+            nodes: nodes.len(),
+            duplicates,
+        };
+
+        let mut store = self.store.lock().await;
+        store.merge(&nodes);
+        self.save_store(&store);
+
+        *self.nodes.lock().await = nodes;
+        Ok(report)
+    }
+
+    async fn list_subscriptions(&self) -> Result<Vec<SubscriptionInfo>, String> {
+        let config = self.load_config()?;
+        let state = self.state.lock().await;
+        let nodes = self.nodes.lock().await;
+
+        Ok(config
+            .subscriptions
+            .into_iter()
+            .map(|sub| {
+                let status = state.subscriptions.get(&sub.name);
+                SubscriptionInfo {
+                    nodes: nodes
+                        .iter()
+                        .filter(|node| node.subscriptions.contains(&sub.name))
+                        .count(),
+                    usage: status.and_then(|s| s.usage.clone()),
+                    updated: status.and_then(|s| s.updated),
+                    error: status.and_then(|s| s.error.clone()),
+                    name: sub.name,
+                    url: sub.url,
+                }
+            })
+            .collect())
+    }
+
+    /// Appends a subscription to `luxnulla.kdl`; its nodes arrive with the
+    /// next refresh.
+    async fn add_subscription(&self, url: &str, name: Option<&str>) -> Result<String, String> {
+        let path = self.config_dir.join(LUXNULLA_CONFIG_FILE);
+        let source = read_optional(&path)?;
+        let edited = config::add_subscription(&source, url, name)?;
+        state::write_atomic(&path, edited.as_bytes())
+            .map_err(|e| format!("failed to write {:?}: {}", path, e))?;
+
+        let name = name.map_or_else(|| Subscription::name_from_url(url), String::from);
+        Ok(format!("added subscription {}", name))
+    }
+
+    /// Deletes a subscription from `luxnulla.kdl` together with the nodes
+    /// only it listed.
+    async fn remove_subscription(&self, name: &str) -> Result<String, String> {
+        let path = self.config_dir.join(LUXNULLA_CONFIG_FILE);
+        let source = read_optional(&path)?;
+        let edited = config::remove_subscription(&source, name)?;
+        state::write_atomic(&path, edited.as_bytes())
+            .map_err(|e| format!("failed to write {:?}: {}", path, e))?;
+
+        let mut nodes = self.nodes.lock().await;
+        let before = nodes.len();
+        for node in nodes.iter_mut() {
+            node.subscriptions.retain(|sub| sub != name);
+        }
+        nodes.retain(|node| !node.subscriptions.is_empty());
+        let dropped = before - nodes.len();
+        drop(nodes);
+
+        let mut state = self.state.lock().await;
+        if state.subscriptions.remove(name).is_some()
+            && let Err(e) = state.save(&self.config_dir.join(STATE_FILE))
+        {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        Ok(format!(
+            "removed subscription {} and {} nodes only it listed",
+            name, dropped
+        ))
+    }
+
+    /// Pid of xray if it is still running.
+    async fn xray_pid(&self) -> Option<u32> {
+        match self.xray.lock().await.as_mut() {
+            Some(child) => match child.try_wait() {
+                Ok(None) => child.id(),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    async fn status(&self) -> DaemonStatus {
+        let xray_pid = self.xray_pid().await;
+        let config = self.load_config().unwrap_or_default();
+        let state = self.state.lock().await;
+        let store = self.store.lock().await;
+        let nodes = self.nodes.lock().await;
+
+        let selected_node = state.selected_node.as_deref().and_then(|id| {
+            nodes
+                .iter()
+                .find(|node| node.config.fingerprint() == id)
+                .map(|node| node_info(node, &store, Some(id)))
+        });
+
+        DaemonStatus {
+            xray_pid,
+            selected_node,
+            selected_group: state.selected_group.clone(),
+            selected_chain: state.selected_chain.clone(),
+            nodes: nodes.len(),
+            subscriptions: config.subscriptions.len(),
+            routing_presets: state
+                .routing_presets
+                .clone()
+                .unwrap_or(config.routing.presets),
+            tproxy: state.tproxy.is_some(),
+        }
+    }
+
+    /// Writes the outbound of node `id` into `xray.json` and restarts xray.
+    /// The new config is validated first; on failure nothing is touched.
+    async fn select_node(&self, id: String) -> Result<String, String> {
+        let (outbound, name) = {
+            let nodes = self.nodes.lock().await;
+            let node = nodes
+                .iter()
+                .find(|node| node.config.fingerprint() == id)
+                .ok_or_else(|| format!("unknown node id: {}", id))?;
+
+            let name = node
+                .config
+                .name()
+                .map(String::from)
+                .unwrap_or_else(|| format!("{}:{}", node.config.address(), node.config.port()));
+
+            (node.config.to_outbound(xray_config::PROXY_TAG), name)
+        };
+
+        let luxnulla = self.load_config()?;
+        self.apply_config(&luxnulla, Selection::Node(outbound))
+            .await?;
+
+        *self.health_failures.lock().await = 0;
+
+        let mut state = self.state.lock().await;
+        state.selected_node = Some(id);
+        state.selected_group = None;
+        state.selected_chain = None;
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        Ok(name)
+    }
+
+    /// Balances proxied traffic over the members of group `name` and restarts
+    /// xray. Returns the number of members.
+    async fn select_group(&self, name: &str) -> Result<usize, String> {
+        let luxnulla = self.load_config()?;
+        let group = luxnulla
+            .groups
+            .iter()
+            .find(|g| g.name == name)
+            .ok_or_else(|| format!("no group named '{}' in {}", name, LUXNULLA_CONFIG_FILE))?;
+
+        let members = {
+            let store = self.store.lock().await;
+            groups::members(group, &self.nodes.lock().await, &store).len()
+        };
+
+        self.apply_config(&luxnulla, Selection::Group(name.to_string()))
+            .await?;
+
+        let mut state = self.state.lock().await;
+        state.selected_node = None;
+        state.selected_group = Some(name.to_string());
+        state.selected_chain = None;
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        Ok(members)
+    }
+
+    /// Sends proxied traffic through chain `name` and restarts xray. Returns
+    /// the number of hops.
+    async fn select_chain(&self, name: &str) -> Result<usize, String> {
+        let luxnulla = self.load_config()?;
+        let hops = expand_chain(&luxnulla.chains, name)?.len();
+
+        self.apply_config(&luxnulla, Selection::Chain(name.to_string()))
+            .await?;
+
+        let mut state = self.state.lock().await;
+        state.selected_node = None;
+        state.selected_group = None;
+        state.selected_chain = Some(name.to_string());
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        Ok(hops)
+    }
+
+    /// Outbounds of chain `name`, entry hop first and the exit, tagged
+    /// `exit_tag`, last. Every hop must be a known node name or id.
+    async fn chain_outbounds(
+        &self,
+        luxnulla: &LuxnullaConfig,
+        name: &str,
+        exit_tag: &str,
+    ) -> Result<Vec<serde_json::Value>, String> {
+        let members = expand_chain(&luxnulla.chains, name)?;
+        let nodes = self.nodes.lock().await;
+
+        let hops = members
+            .iter()
+            .map(|member| {
+                nodes
+                    .iter()
+                    .find(|node| {
+                        node.config.fingerprint() == *member
+                            || node.config.name() == Some(member.as_str())
+                    })
+                    .map(|node| node.config.to_outbound(exit_tag))
+                    .ok_or_else(|| format!("chain '{}': no node named '{}'", name, member))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(xray_config::chain_outbounds(name, hops, exit_tag))
+    }
+
+    /// Regenerates `xray.json` from luxnulla.kdl with `selection` behind the
+    /// proxy target and restarts xray with it. The new config is validated
+    /// first; on failure nothing is touched.
+    async fn apply_config(
+        &self,
+        luxnulla: &LuxnullaConfig,
+        selection: Selection,
+    ) -> Result<(), String> {
+        let _applying = self.applying.lock().await;
+
+        let selection = match selection {
+            Selection::Current => {
+                let state = self.state.lock().await;
+                match (&state.selected_group, &state.selected_chain) {
+                    (Some(group), _) => Selection::Group(group.clone()),
+                    (_, Some(chain)) => Selection::Chain(chain.clone()),
+                    _ => Selection::Current,
+                }
+            }
+            other => other,
+        };
+
+        let (proxy, dialers, active_group) = match selection {
+            Selection::Current => (None, Vec::new(), None),
+            Selection::Node(outbound) => (Some(outbound), Vec::new(), None),
+            Selection::Group(name) => (None, Vec::new(), Some(name)),
+            Selection::Chain(name) => {
+                let mut outbounds = self
+                    .chain_outbounds(luxnulla, &name, xray_config::PROXY_TAG)
+                    .await?;
+                let exit = outbounds.pop();
+                (exit, outbounds, None)
+            }
+        };
+        let active_group = active_group.as_deref();
+
+        // Presets never target groups, so the ones in luxnulla.kdl will do.
+        let rules = luxnulla.routing.all_rules();
+        let mut wanted: Vec<&str> = rules
+            .iter()
+            .filter_map(|rule| rule.group())
+            .chain(active_group)
+            .collect();
+        wanted.sort_unstable();
+        wanted.dedup();
+
+        let balancers = {
+            let store = self.store.lock().await;
+            let nodes = self.nodes.lock().await;
+            wanted
+                .into_iter()
+                .map(|name| {
+                    let group =
+                        luxnulla
+                            .groups
+                            .iter()
+                            .find(|g| g.name == name)
+                            .ok_or_else(|| {
+                                format!("no group named '{}' in {}", name, LUXNULLA_CONFIG_FILE)
+                            })?;
+                    let outbounds: Vec<_> = groups::members(group, &nodes, &store)
+                        .into_iter()
+                        .map(|node| node.config.to_outbound(xray_config::PROXY_TAG))
+                        .collect();
+                    if outbounds.is_empty() {
+                        return Err(format!("group '{}' matches no nodes", name));
+                    }
+                    Ok(xray_config::GroupBalancer {
+                        name: group.name.clone(),
+                        outbounds,
+                        strategy: group.strategy.as_xray(),
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?
+        };
+
+        let config_path = self.config_dir.join(XRAY_CONFIG_FILE);
+        let base = tokio::fs::read_to_string(&config_path)
+            .await
+            .unwrap_or_default();
+        let mut routing = luxnulla.routing.clone();
+        if let Some(presets) = &self.state.lock().await.routing_presets {
+            routing.presets = presets.clone();
+        }
+
+        let config = xray_config::generate(
+            &base,
+            xray_config::Generation {
+                proxy,
+                dialers,
+                active_group: active_group.map(String::from),
+                balancers,
+                routing: &routing,
+                observatory: &luxnulla.observatory,
+                dns: luxnulla.dns.as_ref(),
+                inbounds: &luxnulla.inbounds,
+                tproxy: luxnulla.tproxy.as_ref(),
+                stats: luxnulla.stats.enabled.then_some(&luxnulla.stats),
+            },
+        )?;
+
+        xray_check::test_config(&luxnulla.xray_binary, &self.config_dir, config.as_bytes())
+            .await
+            .map_err(|e| format!("xray rejected the generated config:\n{}", e))?;
+        self.check_ports(&config).await?;
+
+        state::write_atomic(&config_path, config.as_bytes())
+            .map_err(|e| format!("failed to write {}: {}", XRAY_CONFIG_FILE, e))?;
+
+        self.spawn_xray(&luxnulla.xray_binary, &config_path)
+            .await
+            .map_err(|e| format!("failed to start xray: {}", e))
+    }
+
+    /// Overrides the routing presets of luxnulla.kdl (`None` restores them)
+    /// and applies the result. The previous override is kept on failure.
+    async fn set_routing_presets(
+        &self,
+        presets: Option<Vec<RoutingPreset>>,
+    ) -> Result<String, String> {
+        let luxnulla = self.load_config()?;
+
+        let previous = std::mem::replace(
+            &mut self.state.lock().await.routing_presets,
+            presets.clone(),
+        );
+
+        if let Err(e) = self.apply_config(&luxnulla, Selection::Current).await {
+            self.state.lock().await.routing_presets = previous;
+            return Err(e);
+        }
+
+        let state = self.state.lock().await;
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        let active = presets.unwrap_or(luxnulla.routing.presets);
+        Ok(if active.is_empty() {
+            String::from("routing presets cleared")
+        } else {
+            format!(
+                "routing presets: {}",
+                active
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+
+    async fn tproxy(&self, action: TproxyAction) -> Result<String, String> {
+        if action == TproxyAction::Disable {
+            return match self.remove_tproxy().await? {
+                true => Ok(String::from("transparent proxy disabled")),
+                false => Ok(String::from("transparent proxy was not enabled")),
+            };
+        }
+
+        let luxnulla = self.load_config()?;
+        let config = luxnulla
+            .tproxy
+            .as_ref()
+            .ok_or_else(|| format!("no tproxy section in {}", LUXNULLA_CONFIG_FILE))?;
+
+        if action == TproxyAction::DryRun {
+            return Ok(tproxy::dry_run(config));
+        }
+
+        // The dokodemo-door inbound has to be listening before traffic is
+        // redirected to it. Before the first refresh start_xray runs the
+        // last generated xray.json, which may predate the tproxy section.
+        self.start_xray().await?;
+        let running = tokio::fs::read_to_string(self.config_dir.join(XRAY_CONFIG_FILE))
+            .await
+            .unwrap_or_default();
+        if !xray_config::has_tproxy_inbound(&running, config.port) {
+            return Err(format!(
+                "{} has no {} inbound on port {}; refresh the subscriptions so it is regenerated",
+                XRAY_CONFIG_FILE,
+                xray_config::TPROXY_INBOUND_TAG,
+                config.port
+            ));
+        }
+        self.remove_tproxy().await?;
+
+        let installed = tproxy::install(config).await?;
+        let mut state = self.state.lock().await;
+        state.tproxy = Some(installed);
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+
+        Ok(format!(
+            "transparent proxy enabled on port {} ({})",
+            config.port,
+            config.mode.as_xray()
+        ))
+    }
+
+    /// Removes installed tproxy rules, returning whether there were any.
+    async fn remove_tproxy(&self) -> Result<bool, String> {
+        let mut state = self.state.lock().await;
+        let Some(installed) = state.tproxy.clone() else {
+            return Ok(false);
+        };
+
+        tproxy::remove(&installed).await?;
+        state.tproxy = None;
+        if let Err(e) = state.save(&self.config_dir.join(STATE_FILE)) {
+            eprintln!("failed to save {}: {}", STATE_FILE, e);
+        }
+        Ok(true)
+    }
+
+    /// Refuses a config whose inbound ports are taken by anything but the
+    /// xray it is about to replace.
+    async fn check_ports(&self, config: &str) -> Result<(), String> {
+        let own_pid = self.xray.lock().await.as_ref().and_then(Child::id);
+        ports::check_inbounds(config, own_pid)
+    }
+
+    fn load_config(&self) -> Result<LuxnullaConfig, String> {
+        LuxnullaConfig::load(&self.config_dir.join(LUXNULLA_CONFIG_FILE))
+    }
+
+    fn save_store(&self, store: &NodeStore) {
+        if let Err(e) = store.save(&self.config_dir.join(NODES_FILE)) {
+            eprintln!("failed to save {}: {}", NODES_FILE, e);
+        }
+    }
+
+    /// Regenerates and validates `xray.json` and (re)starts xray with it. An
+    /// invalid config is refused and whatever xray is already running keeps
+    /// running.
+    async fn start_xray(&self) -> Result<(), String> {
+        // Groups cannot be resolved before the first refresh; run the file
+        // as it was last generated.
+        if !self.nodes.lock().await.is_empty() {
+            let luxnulla = self.load_config()?;
+            return self.apply_config(&luxnulla, Selection::Current).await;
+        }
+
+        let config_path = self.config_dir.join(XRAY_CONFIG_FILE);
+
+        let contents = tokio::fs::read(&config_path)
+            .await
+            .map_err(|e| format!("failed to read {}: {}", XRAY_CONFIG_FILE, e))?;
+        let luxnulla = self.load_config()?;
+
+        xray_check::test_config(&luxnulla.xray_binary, &self.config_dir, &contents)
+            .await
+            .map_err(|e| format!("xray rejected {}:\n{}", XRAY_CONFIG_FILE, e))?;
+        self.check_ports(&String::from_utf8_lossy(&contents))
+            .await?;
+
+        self.spawn_xray(&luxnulla.xray_binary, &config_path)
+            .await
+            .map_err(|e| format!("failed to start xray: {}", e))
+    }
+
+    /// Spawns xray with its stdout/stderr routed into the log buffer,
+    /// replacing any instance started earlier.
+    async fn spawn_xray(&self, xray_binary: &str, config: &Path) -> std::io::Result<()> {
+        let mut xray = self.xray.lock().await;
+        if let Some(mut old) = xray.take() {
+            let _ = old.kill().await;
+        }
+
+        let mut child = tokio::process::Command::new(xray_binary)
+            .args(["run", "-c"])
+            .arg(config)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        if let Some(stdout) = child.stdout.take() {
+            let logs = self.logs.clone();
+            tokio::spawn(async move { logs.capture(LogStream::Stdout, stdout).await });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let logs = self.logs.clone();
+            tokio::spawn(async move { logs.capture(LogStream::Stderr, stderr).await });
+        }
+
+        *xray = Some(child);
+        Ok(())
+    }
+
+    /// Probes the requested nodes, answering with one `Probe` message per node
+    /// as results come in.
+    async fn probe_nodes(&self, sock: &mut UnixStream, ids: Vec<String>) {
+        let config = match self.load_config() {
+            Ok(config) => config,
+            Err(e) => {
+                let resp = CommandResponse::Err(ErrorCommandResponse::Message(e));
+                let _ = write_response(sock, &resp).await;
+                return;
+            }
+        };
+
+        let targets: Vec<ProbeTarget> = self
+            .nodes
+            .lock()
+            .await
+            .iter()
+            .map(|node| (node.config.fingerprint(), node))
+            .filter(|(id, _)| ids.is_empty() || ids.contains(id))
+            .map(|(id, node)| ProbeTarget {
+                id,
+                address: node.config.address().to_string(),
+                port: node.config.port(),
+                tls_server_name: node.config.tls_server_name(),
+            })
+            .collect();
+
+        let mut results = std::pin::pin!(probe::probe_all(
+            targets,
+            config.probe.concurrency,
+            config.probe.timeout,
+            config.tproxy.as_ref().map(|tproxy| tproxy.outbound_mark),
+        ));
+
+        // Keep probing even if the client went away so the results are stored.
+        let mut connected = true;
+        while let Some((id, result)) = results.next().await {
+            self.store
+                .lock()
+                .await
+                .record_latency(&id, SampleKind::Connect, result.latency_ms());
+
+            if connected {
+                let resp = CommandResponse::Ok(OkCommandResponse::Probe { id, result });
+                connected = write_response(sock, &resp).await.is_ok();
+            }
+        }
+
+        self.save_store(&*self.store.lock().await);
+    }
+
+    /// Runs the URL test for the requested nodes in batches, answering with
+    /// one `UrlTest` message per node.
+    async fn url_test_nodes(&self, sock: &mut UnixStream, ids: Vec<String>) {
+        let config = match self.load_config() {
+            Ok(config) => config,
+            Err(e) => {
+                let resp = CommandResponse::Err(ErrorCommandResponse::Message(e));
+                let _ = write_response(sock, &resp).await;
+                return;
+            }
+        };
+
+        let mut candidates: Vec<UrlTestCandidate> = self
+            .nodes
+            .lock()
+            .await
+            .iter()
+            .map(|node| (node.config.fingerprint(), node))
+            .filter(|(id, _)| ids.is_empty() || ids.contains(id))
+            .map(|(id, node)| UrlTestCandidate {
+                id,
+                outbound: node.config.to_outbound(xray_config::PROXY_TAG),
+                dialers: Vec::new(),
+            })
+            .collect();
+
+        let mut connected = true;
+        for id in &ids {
+            let Some(chain) = id.strip_prefix(CHAIN_ID_PREFIX) else {
+                continue;
+            };
+
+            let outbounds = self
+                .chain_outbounds(&config, chain, xray_config::PROXY_TAG)
+                .await
+                .and_then(|mut dialers| {
+                    let exit = dialers
+                        .pop()
+                        .ok_or_else(|| format!("chain '{}' has no nodes", chain))?;
+                    Ok((exit, dialers))
+                });
+            match outbounds {
+                Ok((outbound, dialers)) => candidates.push(UrlTestCandidate {
+                    id: id.clone(),
+                    outbound,
+                    dialers,
+                }),
+                Err(e) => {
+                    let resp = CommandResponse::Ok(OkCommandResponse::UrlTest {
+                        id: id.clone(),
+                        result: UrlTestResult {
+                            error: Some(e),
+                            ..Default::default()
+                        },
+                    });
+                    connected = connected && write_response(sock, &resp).await.is_ok();
+                }
+            }
+        }
+
+        let settings = UrlTestSettings::new(&config, &self.config_dir);
+
+        for batch in candidates.chunks(config.url_test.batch_size) {
+            // The client went away (a cancelled test): skip the remaining batches.
+            if !connected {
+                break;
+            }
+
+            let results = match url_test::run_batch(batch, &settings).await {
+                Ok(results) => results,
+                Err(e) => vec![
+                    UrlTestResult {
+                        error: Some(e),
+                        ..Default::default()
+                    };
+                    batch.len()
+                ],
+            };
+
+            // Record under the lock, answer after releasing it: a slow client
+            // must not stall everything else that needs the store.
+            let responses: Vec<CommandResponse> = {
+                let mut store = self.store.lock().await;
+                batch
+                    .iter()
+                    .zip(results)
+                    .map(|(candidate, result)| {
+                        store.record_latency(&candidate.id, SampleKind::Url, result.latency_ms);
+                        CommandResponse::Ok(OkCommandResponse::UrlTest {
+                            id: candidate.id.clone(),
+                            result,
+                        })
+                    })
+                    .collect()
+            };
+
+            for resp in responses {
+                if !connected {
+                    break;
+                }
+                connected = write_response(sock, &resp).await.is_ok();
+            }
+        }
+
+        self.save_store(&*self.store.lock().await);
+    }
+
+    fn emit(&self, event: DaemonEvent) {
+        println!("event: {}", event);
+        let _ = self.events.send(event);
+    }
+
+    async fn stream_events(&self, sock: &mut UnixStream) {
+        let mut rx = self.events.subscribe();
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            let resp = CommandResponse::Ok(OkCommandResponse::Event(event));
+            if write_response(sock, &resp).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Re-tests the active node every `check-interval-secs` and lets the
+    /// selection policy replace it after `max-failures` failed checks in a row.
+    async fn health_loop(self: Arc<Self>) {
+        loop {
+            let interval = self
+                .load_config()
+                .map(|c| c.selection.check_interval)
+                .unwrap_or_else(|_| SelectionConfig::default().check_interval);
+            tokio::time::sleep(interval).await;
+
+            match self.load_config() {
+                Ok(config) => self.check_active_node(&config).await,
+                Err(e) => eprintln!("health check skipped: {}", e),
+            }
+        }
+    }
+
+    /// Samples xray's traffic counters every `stats.interval-ms` while a
+    /// client keeps asking for them.
+    async fn traffic_loop(self: Arc<Self>) {
+        loop {
+            let config = self.load_config().ok();
+            let interval = config
+                .as_ref()
+                .map(|c| c.stats.interval)
+                .unwrap_or_else(|| StatsConfig::default().interval);
+            tokio::time::sleep(interval).await;
+
+            let Some(config) = config.filter(|c| c.stats.enabled) else {
+                continue;
+            };
+            if !self.traffic.lock().await.is_watched() || self.xray_pid().await.is_none() {
+                continue;
+            }
+            if let Err(e) = self.sample_traffic(&config.xray_binary).await {
+                eprintln!("traffic sample failed: {}", e);
+            }
+        }
+    }
+
+    async fn sample_traffic(&self, xray_binary: &str) -> Result<(), String> {
+        let path = self.config_dir.join(XRAY_CONFIG_FILE);
+        let config = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+        let server = xray_config::stats_api(&config).ok_or_else(|| {
+            String::from("xray.json has no stats API inbound, run restart to regenerate it")
+        })?;
+
+        let counters = traffic::query(xray_binary, &server).await?;
+        self.traffic.lock().await.record(counters);
+        Ok(())
+    }
+
+    async fn traffic(&self) -> Result<TrafficStats, String> {
+        let config = self.load_config()?;
+        if !config.stats.enabled {
+            return Err(String::from(
+                "traffic stats are disabled, set stats { enabled true } in luxnulla.kdl",
+            ));
+        }
+        if self.xray_pid().await.is_none() {
+            return Err(String::from("xray is not running"));
+        }
+
+        let stale = {
+            let mut monitor = self.traffic.lock().await;
+            monitor.watch();
+            monitor.is_stale(config.stats.interval)
+        };
+        if stale {
+            self.sample_traffic(&config.xray_binary).await?;
+        }
+
+        Ok(self.traffic.lock().await.stats(config.stats.interval))
+    }
+
+    async fn check_active_node(&self, config: &LuxnullaConfig) {
+        let (selected_node, pinned) = {
+            let state = self.state.lock().await;
+            (
+                state.selected_node.clone(),
+                state.selected_group.is_some() || state.selected_chain.is_some(),
+            )
+        };
+
+        // A balancer's own observatory already routes around dead members,
+        // and a chain is a deliberate route the policy must not replace.
+        if pinned {
+            return;
+        }
+
+        let Some(active) = selected_node else {
+            if config.selection.policy != SelectionPolicy::Manual {
+                self.fail_over(config, None, String::from("no node was selected"))
+                    .await;
+            }
+            return;
+        };
+
+        // The node list is empty until the first refresh; nothing to test yet.
+        let Some(candidate) = self
+            .nodes
+            .lock()
+            .await
+            .iter()
+            .find(|node| node.config.fingerprint() == active)
+            .map(|node| UrlTestCandidate {
+                id: active.clone(),
+                outbound: node.config.to_outbound(xray_config::PROXY_TAG),
+                dialers: Vec::new(),
+            })
+        else {
+            return;
+        };
+
+        let settings = UrlTestSettings::new(config, &self.config_dir);
+        let result = match url_test::run_batch(std::slice::from_ref(&candidate), &settings).await {
+            Ok(mut results) => results.remove(0),
+            Err(e) => UrlTestResult {
+                error: Some(e),
+                ..Default::default()
+            },
+        };
+
+        {
+            let mut store = self.store.lock().await;
+            store.record_latency(&active, SampleKind::Url, result.latency_ms);
+            self.save_store(&store);
+        }
+
+        let mut failures = self.health_failures.lock().await;
+        if result.latency_ms.is_some() {
+            *failures = 0;
+            return;
+        }
+
+        *failures += 1;
+        let error = result
+            .error
+            .unwrap_or_else(|| String::from("unknown error"));
+        self.emit(DaemonEvent::HealthCheckFailed {
+            id: active.clone(),
+            failures: *failures,
+            error: error.clone(),
+        });
+
+        if *failures < config.selection.max_failures
+            || config.selection.policy == SelectionPolicy::Manual
+        {
+            return;
+        }
+        *failures = 0;
+        drop(failures);
+
+        let reason = format!(
+            "active node failed {} checks in a row ({})",
+            config.selection.max_failures, error
+        );
+        self.fail_over(config, Some(active), reason).await;
+    }
+
+    /// Probes every node and switches to the one the selection policy picks.
+    async fn fail_over(&self, config: &LuxnullaConfig, current: Option<String>, why: String) {
+        let targets: Vec<ProbeTarget> = self
+            .nodes
+            .lock()
+            .await
+            .iter()
+            .map(|node| ProbeTarget {
+                id: node.config.fingerprint(),
+                address: node.config.address().to_string(),
+                port: node.config.port(),
+                tls_server_name: node.config.tls_server_name(),
+            })
+            .collect();
+
+        if targets.is_empty() {
+            return;
+        }
+
+        let results: HashMap<String, ProbeResult> = probe::probe_all(
+            targets,
+            config.probe.concurrency,
+            config.probe.timeout,
+            config.tproxy.as_ref().map(|tproxy| tproxy.outbound_mark),
+        )
+        .collect()
+        .await;
+
+        let choice = {
+            let mut store = self.store.lock().await;
+            for (id, result) in &results {
+                store.record_latency(id, SampleKind::Connect, result.latency_ms());
+            }
+            self.save_store(&store);
+
+            let nodes = self.nodes.lock().await;
+            let ids: Vec<String> = nodes.iter().map(|n| n.config.fingerprint()).collect();
+            let candidates: Vec<Candidate> = nodes
+                .iter()
+                .zip(&ids)
+                .map(|(node, id)| Candidate {
+                    id,
+                    name: node.config.name().unwrap_or(id),
+                    latency_ms: results.get(id).and_then(ProbeResult::latency_ms),
+                })
+                .collect();
+
+            selection::choose(
+                config.selection.policy,
+                &config.selection.fallback,
+                &candidates,
+                current.as_deref(),
+            )
+        };
+
+        let Some((to, because)) = choice else {
+            eprintln!("{}, but no healthy replacement was found", why);
+            return;
+        };
+
+        match self.select_node(to.clone()).await {
+            Ok(_) => self.emit(DaemonEvent::NodeSwitched {
+                from: current,
+                to,
+                reason: format!("{}; {}", why, because),
+            }),
+            Err(e) => eprintln!("failed to switch to {}: {}", to, e),
+        }
+    }
+
+    async fn stream_logs(
+        &self,
+        sock: &mut UnixStream,
+        follow: bool,
+        lines: usize,
+        level: Option<LogLevel>,
+    ) {
+        // Subscribe before taking the tail so no line falls in between.
+        let mut rx = self.logs.subscribe();
+
+        for line in self.logs.tail(lines, level) {
+            let resp = CommandResponse::Ok(OkCommandResponse::Log(line));
+            if write_response(sock, &resp).await.is_err() {
+                return;
+            }
+        }
+
+        if !follow {
+            return;
+        }
+
+        loop {
+            let line = match rx.recv().await {
+                Ok(line) => line,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+
+            if level.is_some_and(|level| line.level < level) {
+                continue;
+            }
+
+            let resp = CommandResponse::Ok(OkCommandResponse::Log(line));
+            if write_response(sock, &resp).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Contents of `path`, or nothing if it does not exist yet.
+fn read_optional(path: &Path) -> Result<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("failed to read {:?}: {}", path, e)),
+    }
+}
+
+fn node_info(node: &Node, store: &NodeStore, selected: Option<&str>) -> NodeInfo {
+    let id = node.config.fingerprint();
+    NodeInfo {
+        protocol: node.config.protocol().to_string(),
+        address: node.config.address().to_string(),
+        port: node.config.port(),
+        name: node.config.name().unwrap_or_default().to_string(),
+        active: selected == Some(id.as_str()),
+        favourite: store.get(&id).is_some_and(|s| s.favourite),
+        tags: store.get(&id).map(|s| s.tags.clone()).unwrap_or_default(),
+        subscriptions: node.subscriptions.clone(),
+        latency_ms: store.last_latency(&id),
+        id,
+    }
+}
+
+/// Responses are newline-delimited JSON so a single request can be answered
+/// with a stream of messages.
+async fn write_response(sock: &mut UnixStream, resp: &CommandResponse) -> std::io::Result<()> {
+    let mut out = serde_json::to_vec(resp).unwrap();
+    out.push(b'\n');
+    sock.write_all(&out).await
 }
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
+    let config_dir = config_dir()
+        .ok_or_eyre("cannot get a dir")?
+        .join(CONFIG_DIR);
+
+    let store = NodeStore::load(&config_dir.join(NODES_FILE));
     let application = Arc::new(Application {
-        config_dir: config_dir()
-            .ok_or_eyre("cannot get a dir")?
-            .join(CONFIG_DIR),
+        logs: Arc::new(XrayLogs::new(config_dir.join(XRAY_LOG_FILE))),
+        xray: Mutex::new(None),
+        nodes: Mutex::new(store.last_refresh()),
+        store: Mutex::new(store),
+        state: Mutex::new(DaemonState::load(&config_dir.join(STATE_FILE))),
+        events: broadcast::channel(64).0,
+        health_failures: Mutex::new(0),
+        traffic: Mutex::new(TrafficMonitor::default()),
+        applying: Mutex::new(()),
+        config_dir,
     });
 
     if !application.config_dir.exists() {
@@ -112,7 +1423,7 @@ async fn main() -> eyre::Result<()> {
     }
 
     if !application.config_dir.join(XRAY_CONFIG_FILE).exists() {
-        std::fs::File::create(&application.config_dir.join(XRAY_CONFIG_FILE)).unwrap();
+        std::fs::File::create(application.config_dir.join(XRAY_CONFIG_FILE)).unwrap();
     }
 
     let sock_path = PathBuf::from("/tmp/").join(SOCKET_NAME);
@@ -123,11 +1434,32 @@ async fn main() -> eyre::Result<()> {
     let listener = UnixListener::bind(&sock_path)?;
     println!("Luxnulla listening on {:?}", sock_path);
 
-    loop {
-        let app_clone = application.clone();
+    // Rules left behind by a crash would send all traffic to an xray that
+    // is not running.
+    match application.remove_tproxy().await {
+        Ok(true) => println!("removed tproxy rules left from the last run"),
+        Ok(false) => {}
+        Err(e) => eprintln!("failed to remove leftover tproxy rules: {}", e),
+    }
+
+    tokio::spawn(application.clone().health_loop());
+    tokio::spawn(application.clone().traffic_loop());
 
-        let (sock, _) = listener.accept().await?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        let sock = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
 
+        let app_clone = application.clone();
         tokio::spawn(async move { app_clone.handle_client(sock).await });
     }
+
+    if let Err(e) = application.remove_tproxy().await {
+        eprintln!("failed to remove tproxy rules: {}", e);
+    }
+    let _ = fs::remove_file(&sock_path);
+    Ok(())
 }
@@ -0,0 +1,217 @@
+use chrono::{DateTime, Utc};
+use luxnulla::{LatencySample, SampleKind};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    state::write_atomic,
+    xray_parser::{self, Node},
+};
+
+/// Samples kept per node; older ones are dropped.
+const LATENCY_HISTORY_LEN: usize = 100;
+
+/// Per-node data that outlives a single subscription refresh, keyed by
+/// [`ProxyConfig::fingerprint`](crate::xray_parser::ProxyConfig::fingerprint).
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct NodeStore {
+    nodes: HashMap<String, StoredNode>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoredNode {
+    /// Latest share link seen for this node.
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub favourite: bool,
+    /// User-assigned labels, matched by `tag` in group definitions.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub latency_history: Vec<LatencySample>,
+    pub last_seen: DateTime<Utc>,
+    /// Subscriptions that listed the node in the last refresh; empty once it
+    /// is gone from all of them.
+    #[serde(default)]
+    pub subscriptions: Vec<String>,
+    /// Place in the node list of the last refresh.
+    #[serde(default)]
+    pub position: usize,
+}
+
+impl NodeStore {
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("ignoring corrupt node store {:?}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let contents = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        write_atomic(path, &contents)
+    }
+
+    /// Records the nodes of a refresh. Entries for nodes that disappeared are
+    /// kept so their history comes back if the node does.
+    pub fn merge(&mut self, nodes: &[Node]) {
+        let now = Utc::now();
+
+        for stored in self.nodes.values_mut() {
+            stored.subscriptions.clear();
+        }
+
+        for (position, node) in nodes.iter().enumerate() {
+            let name = node.config.name().unwrap_or_default().to_string();
+
+            let stored = self
+                .nodes
+                .entry(node.config.fingerprint())
+                .or_insert_with(|| StoredNode {
+                    uri: node.uri.clone(),
+                    name: name.clone(),
+                    favourite: false,
+                    tags: Vec::new(),
+                    latency_history: Vec::new(),
+                    last_seen: now,
+                    subscriptions: Vec::new(),
+                    position,
+                });
+            stored.uri = node.uri.clone();
+            stored.name = name;
+            stored.last_seen = now;
+            stored.subscriptions = node.subscriptions.clone();
+            stored.position = position;
+        }
+    }
+
+    /// The node list of the last refresh, so a restarted daemon has nodes
+    /// before it fetches the subscriptions again.
+    pub fn last_refresh(&self) -> Vec<Node> {
+        let mut listed: Vec<&StoredNode> = self
+            .nodes
+            .values()
+            .filter(|stored| !stored.subscriptions.is_empty())
+            .collect();
+        listed.sort_by_key(|stored| stored.position);
+
+        listed
+            .into_iter()
+            .filter_map(|stored| {
+                Some(Node {
+                    uri: stored.uri.clone(),
+                    config: xray_parser::parse_line(&stored.uri).ok()?,
+                    subscriptions: stored.subscriptions.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&StoredNode> {
+        self.nodes.get(fingerprint)
+    }
+
+    pub fn record_latency(&mut self, fingerprint: &str, kind: SampleKind, latency_ms: Option<u32>) {
+        if let Some(stored) = self.nodes.get_mut(fingerprint) {
+            if stored.latency_history.len() == LATENCY_HISTORY_LEN {
+                stored.latency_history.remove(0);
+            }
+            stored.latency_history.push(LatencySample {
+                at: Utc::now(),
+                kind,
+                latency_ms,
+            });
+        }
+    }
+
+    /// Latency of the most recent sample, `None` if untested or it failed.
+    pub fn last_latency(&self, fingerprint: &str) -> Option<u32> {
+        self.nodes
+            .get(fingerprint)?
+            .latency_history
+            .last()?
+            .latency_ms
+    }
+
+    pub fn set_favourite(&mut self, fingerprint: &str, favourite: bool) -> bool {
+        match self.nodes.get_mut(fingerprint) {
+            Some(stored) => {
+                stored.favourite = favourite;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_tag(&mut self, fingerprint: &str, tag: &str, present: bool) -> bool {
+        let Some(stored) = self.nodes.get_mut(fingerprint) else {
+            return false;
+        };
+
+        stored.tags.retain(|t| t != tag);
+        if present {
+            stored.tags.push(tag.to_string());
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(host: &str, subscriptions: &[&str]) -> Node {
+        let uri = format!("vless://id@{}:443?type=tcp#{}", host, host);
+        Node {
+            config: xray_parser::parse_line(&uri).unwrap(),
+            uri,
+            subscriptions: subscriptions.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn hosts(nodes: &[Node]) -> Vec<&str> {
+        nodes.iter().map(|node| node.config.address()).collect()
+    }
+
+    #[test]
+    fn last_refresh_keeps_order_and_subscriptions() {
+        let mut store = NodeStore::default();
+        store.merge(&[
+            node("c.example", &["a"]),
+            node("a.example", &["a", "b"]),
+            node("b.example", &["b"]),
+        ]);
+
+        let nodes = store.last_refresh();
+        assert_eq!(hosts(&nodes), ["c.example", "a.example", "b.example"]);
+        assert_eq!(nodes[1].subscriptions, ["a", "b"]);
+    }
+
+    #[test]
+    fn vanished_nodes_are_not_restored() {
+        let mut store = NodeStore::default();
+        store.merge(&[node("a.example", &["a"]), node("b.example", &["a"])]);
+        store.merge(&[node("b.example", &["a"])]);
+
+        assert_eq!(hosts(&store.last_refresh()), ["b.example"]);
+        // The history of the vanished node is still there
+        let gone = node("a.example", &[]);
+        assert!(store.get(&gone.config.fingerprint()).is_some());
+    }
+
+    #[test]
+    fn survives_a_save() {
+        let path = std::env::temp_dir().join(format!("luxnulla-nodes-{}.json", std::process::id()));
+        let mut store = NodeStore::default();
+        store.merge(&[node("b.example", &["s"]), node("a.example", &["s"])]);
+        store.save(&path).unwrap();
+
+        let loaded = NodeStore::load(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(hosts(&loaded.last_refresh()), ["b.example", "a.example"]);
+    }
+}
@@ -0,0 +1,102 @@
+use serde_json::Value;
+use std::{
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, TcpListener},
+};
+
+/// Fails if a TCP port an inbound of `config` (the contents of `xray.json`)
+/// wants is held by a process other than `own_pid`, the running xray that is
+/// about to be replaced.
+pub fn check_inbounds(config: &str, own_pid: Option<u32>) -> Result<(), String> {
+    let config: Value = serde_json::from_str(config).map_err(|e| e.to_string())?;
+    let Some(inbounds) = config.get("inbounds").and_then(Value::as_array) else {
+        return Ok(());
+    };
+
+    for inbound in inbounds {
+        // Port ranges, env ports and unix sockets are left to xray.
+        let Some(port) = inbound
+            .get("port")
+            .and_then(Value::as_u64)
+            .and_then(|p| u16::try_from(p).ok())
+        else {
+            continue;
+        };
+        let listen = match inbound.get("listen").and_then(Value::as_str) {
+            Some(listen) => match listen.parse::<IpAddr>() {
+                Ok(listen) => listen,
+                Err(_) => continue,
+            },
+            None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+
+        match TcpListener::bind((listen, port)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AddrInUse => match listening_process(port) {
+                Some((pid, _)) if Some(pid) == own_pid => {}
+                Some((pid, name)) => {
+                    return Err(format!(
+                        "port {} on {} is already in use by {} (pid {})",
+                        port, listen, name, pid
+                    ));
+                }
+                None => {
+                    return Err(format!(
+                        "port {} on {} is already in use by another process",
+                        port, listen
+                    ));
+                }
+            },
+            Err(e) => return Err(format!("cannot listen on {}:{}: {}", listen, port, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the process with a TCP socket listening on `port` through procfs.
+/// `None` if there is none or its sockets are not ours to inspect.
+fn listening_process(port: u16) -> Option<(u32, String)> {
+    let inodes: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|table| {
+            table
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    let local_port = fields.get(1)?.rsplit(':').next()?;
+                    // 0A is TCP_LISTEN.
+                    (u16::from_str_radix(local_port, 16).ok()? == port && fields.get(3)? == &"0A")
+                        .then(|| fields.get(9).map(|inode| format!("socket:[{}]", inode)))?
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    if inodes.is_empty() {
+        return None;
+    }
+
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+        let holds = fs::read_dir(entry.path().join("fd"))
+            .ok()?
+            .flatten()
+            .filter_map(|fd| fs::read_link(fd.path()).ok())
+            .any(|target| {
+                inodes
+                    .iter()
+                    .any(|inode| target.as_os_str() == inode.as_str())
+            });
+
+        holds.then(|| {
+            let name = fs::read_to_string(entry.path().join("comm"))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_else(|_| String::from("unknown process"));
+            (pid, name)
+        })
+    })
+}
@@ -0,0 +1,182 @@
+use futures::{Stream, StreamExt, stream};
+use luxnulla::ProbeResult;
+use socket2::SockRef;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpSocket, TcpStream, lookup_host},
+    time::timeout,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{
+        self, ClientConfig, DigitallySignedStruct, SignatureScheme,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{CryptoProvider, ring},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+    },
+};
+
+/// What to connect to for one node.
+pub struct ProbeTarget {
+    pub id: String,
+    pub address: String,
+    pub port: u16,
+    /// SNI to send when the node speaks TLS or REALITY.
+    pub tls_server_name: Option<String>,
+}
+
+/// Probes every target with at most `concurrency` connections in flight,
+/// yielding results in completion order. `mark` is set as SO_MARK on every
+/// connection, so the transparent proxy does not intercept it.
+pub fn probe_all(
+    targets: Vec<ProbeTarget>,
+    concurrency: usize,
+    limit: Duration,
+    mark: Option<u32>,
+) -> impl Stream<Item = (String, ProbeResult)> {
+    let connector = TlsConnector::from(Arc::new(tls_config()));
+
+    stream::iter(targets)
+        .map(move |target| {
+            let connector = connector.clone();
+            async move {
+                let result = probe(&target, &connector, limit, mark).await;
+                (target.id, result)
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
+
+async fn probe(
+    target: &ProbeTarget,
+    connector: &TlsConnector,
+    limit: Duration,
+    mark: Option<u32>,
+) -> ProbeResult {
+    let mut result = ProbeResult::default();
+
+    let started = Instant::now();
+    let tcp = match timeout(limit, connect(&target.address, target.port, mark)).await {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => {
+            result.error = Some(format!("tcp: {}", e));
+            return result;
+        }
+        Err(_) => {
+            result.error = Some("tcp: timed out".to_string());
+            return result;
+        }
+    };
+    result.tcp_ms = Some(started.elapsed().as_millis() as u32);
+
+    let Some(name) = &target.tls_server_name else {
+        return result;
+    };
+
+    let server_name = match ServerName::try_from(name.clone()) {
+        Ok(server_name) => server_name,
+        Err(e) => {
+            result.error = Some(format!("tls: bad server name {:?}: {}", name, e));
+            return result;
+        }
+    };
+
+    let started = Instant::now();
+    match timeout(limit, connector.connect(server_name, tcp)).await {
+        Ok(Ok(_)) => result.tls_ms = Some(started.elapsed().as_millis() as u32),
+        Ok(Err(e)) => result.error = Some(format!("tls: {}", e)),
+        Err(_) => result.error = Some("tls: timed out".to_string()),
+    }
+
+    result
+}
+
+/// `TcpStream::connect` with an optional SO_MARK, trying every resolved
+/// address in turn.
+async fn connect(host: &str, port: u16, mark: Option<u32>) -> io::Result<TcpStream> {
+    let Some(mark) = mark else {
+        return TcpStream::connect((host, port)).await;
+    };
+
+    let mut last_error = None;
+    for addr in lookup_host((host, port)).await? {
+        match connect_marked(addr, mark).await {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "host resolved to no addresses")
+    }))
+}
+
+async fn connect_marked(addr: SocketAddr, mark: u32) -> io::Result<TcpStream> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    SockRef::from(&socket)
+        .set_mark(mark)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to set mark {}: {}", mark, e)))?;
+    socket.connect(addr).await
+}
+
+fn tls_config() -> ClientConfig {
+    let provider = Arc::new(ring::default_provider());
+
+    let mut config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    config
+}
+
+/// The probe only measures handshake time. Nodes commonly use self-signed
+/// certificates or REALITY, so the chain is not verified.
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,185 @@
+use luxnulla::config::SelectionPolicy;
+
+/// A node the policy may switch to, with its freshest latency.
+pub struct Candidate<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    /// `None` means the node is currently unreachable.
+    pub latency_ms: Option<u32>,
+}
+
+/// Picks the node to switch to instead of `current`, returning its id and
+/// why it was chosen. `None` if the policy is manual or nothing is healthy.
+pub fn choose(
+    policy: SelectionPolicy,
+    fallback: &[String],
+    candidates: &[Candidate],
+    current: Option<&str>,
+) -> Option<(String, String)> {
+    let healthy = |c: &&Candidate| c.latency_ms.is_some() && Some(c.id) != current;
+
+    match policy {
+        SelectionPolicy::Manual => None,
+        SelectionPolicy::LowestLatency => candidates
+            .iter()
+            .filter(healthy)
+            .min_by_key(|c| c.latency_ms)
+            .map(|c| {
+                (
+                    c.id.to_string(),
+                    format!(
+                        "{} has the lowest latency ({}ms)",
+                        c.name,
+                        c.latency_ms.unwrap()
+                    ),
+                )
+            }),
+        SelectionPolicy::RoundRobin => {
+            let start = current
+                .and_then(|id| candidates.iter().position(|c| c.id == id))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+
+            candidates
+                .iter()
+                .cycle()
+                .skip(start)
+                .take(candidates.len())
+                .find(healthy)
+                .map(|c| {
+                    (
+                        c.id.to_string(),
+                        format!("{} is the next healthy node in rotation", c.name),
+                    )
+                })
+        }
+        SelectionPolicy::Fallback => fallback.iter().enumerate().find_map(|(i, entry)| {
+            candidates
+                .iter()
+                .filter(healthy)
+                .find(|c| c.id == entry || c.name == entry)
+                .map(|c| {
+                    (
+                        c.id.to_string(),
+                        format!("{} is fallback #{} and healthy", c.name, i + 1),
+                    )
+                })
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<Candidate<'static>> {
+        vec![
+            Candidate {
+                id: "a",
+                name: "A",
+                latency_ms: Some(120),
+            },
+            Candidate {
+                id: "b",
+                name: "B",
+                latency_ms: None,
+            },
+            Candidate {
+                id: "c",
+                name: "C",
+                latency_ms: Some(40),
+            },
+            Candidate {
+                id: "d",
+                name: "D",
+                latency_ms: Some(80),
+            },
+        ]
+    }
+
+    fn chosen(
+        policy: SelectionPolicy,
+        fallback: &[&str],
+        candidates: &[Candidate],
+        current: Option<&str>,
+    ) -> Option<String> {
+        let fallback: Vec<String> = fallback.iter().map(|s| s.to_string()).collect();
+        choose(policy, &fallback, candidates, current).map(|(id, _)| id)
+    }
+
+    #[test]
+    fn manual_never_switches() {
+        assert_eq!(
+            chosen(SelectionPolicy::Manual, &[], &candidates(), None),
+            None
+        );
+    }
+
+    #[test]
+    fn lowest_latency() {
+        let candidates = candidates();
+        assert_eq!(
+            choose(SelectionPolicy::LowestLatency, &[], &candidates, Some("a")),
+            Some((
+                "c".to_string(),
+                "C has the lowest latency (40ms)".to_string()
+            ))
+        );
+        // The current node is what is being replaced.
+        assert_eq!(
+            chosen(SelectionPolicy::LowestLatency, &[], &candidates, Some("c")).as_deref(),
+            Some("d")
+        );
+    }
+
+    #[test]
+    fn round_robin_skips_unhealthy_and_wraps() {
+        let candidates = candidates();
+        let next = |current| chosen(SelectionPolicy::RoundRobin, &[], &candidates, current);
+        assert_eq!(next(None).as_deref(), Some("a"));
+        assert_eq!(next(Some("a")).as_deref(), Some("c"));
+        assert_eq!(next(Some("c")).as_deref(), Some("d"));
+        assert_eq!(next(Some("d")).as_deref(), Some("a"));
+        // A current node that left the list starts over.
+        assert_eq!(next(Some("gone")).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn fallback_order_by_id_or_name() {
+        let candidates = candidates();
+        let pick = |fallback: &[&str], current| {
+            chosen(SelectionPolicy::Fallback, fallback, &candidates, current)
+        };
+        assert_eq!(pick(&["b", "D", "a"], None).as_deref(), Some("d"));
+        assert_eq!(pick(&["b", "D", "a"], Some("d")).as_deref(), Some("a"));
+        assert_eq!(pick(&["b", "missing"], None), None);
+        assert_eq!(
+            choose(
+                SelectionPolicy::Fallback,
+                &["b".to_string(), "C".to_string()],
+                &candidates,
+                None
+            )
+            .map(|(_, why)| why)
+            .as_deref(),
+            Some("C is fallback #2 and healthy")
+        );
+    }
+
+    #[test]
+    fn nothing_healthy() {
+        let candidates = [Candidate {
+            id: "a",
+            name: "A",
+            latency_ms: None,
+        }];
+        for policy in [
+            SelectionPolicy::LowestLatency,
+            SelectionPolicy::RoundRobin,
+            SelectionPolicy::Fallback,
+        ] {
+            assert_eq!(chosen(policy, &["a"], &candidates, None), None);
+        }
+        assert_eq!(chosen(SelectionPolicy::RoundRobin, &[], &[], None), None);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::tproxy::InstalledTproxy;
+use chrono::{DateTime, Utc};
+use luxnulla::SubscriptionUsage;
+use luxnulla::config::RoutingPreset;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Daemon state that has to survive restarts.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DaemonState {
+    /// Fingerprint of the node currently written into `xray.json`.
+    pub selected_node: Option<String>,
+    /// Name of the group balanced over instead. At most one of the
+    /// `selected_*` fields is set.
+    #[serde(default)]
+    pub selected_group: Option<String>,
+    /// Name of the chain proxied traffic goes through instead.
+    #[serde(default)]
+    pub selected_chain: Option<String>,
+    /// Set with `SetRoutingPreset`; replaces the presets of luxnulla.kdl.
+    #[serde(default)]
+    pub routing_presets: Option<Vec<RoutingPreset>>,
+    /// nftables rules and policy routing currently installed.
+    #[serde(default)]
+    pub tproxy: Option<InstalledTproxy>,
+    /// What the last refreshes learned about each subscription, by name.
+    #[serde(default)]
+    pub subscriptions: HashMap<String, SubscriptionStatus>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SubscriptionStatus {
+    pub usage: Option<SubscriptionUsage>,
+    /// Last successful download.
+    pub updated: Option<DateTime<Utc>>,
+    /// Why the last refresh failed, cleared by the next successful one.
+    pub error: Option<String>,
+}
+
+impl DaemonState {
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("ignoring corrupt state file {:?}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let contents = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        write_atomic(path, &contents)
+    }
+}
+
+/// Writes through a sibling temp file so readers never see a partial file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = PathBuf::from(path.as_os_str());
+    tmp.set_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
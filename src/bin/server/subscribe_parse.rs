@@ -1,13 +1,26 @@
 use base64::{Engine as _, engine::general_purpose};
+use luxnulla::SubscriptionUsage;
 use std::error::Error;
 
-pub async fn fetch_and_parse_configs(url: &str) -> Result<String, Box<dyn Error>> {
+pub struct Fetched {
+    pub content: String,
+    /// From the `subscription-userinfo` header, if the provider sent one.
+    pub usage: Option<SubscriptionUsage>,
+}
+
+pub async fn fetch_and_parse_configs(url: &str) -> Result<Fetched, Box<dyn Error + Send + Sync>> {
     let response = reqwest::get(url).await?;
 
     if !response.status().is_success() {
         return Err(format!("Request failed with status: {}", response.status()).into());
     }
 
+    let usage = response
+        .headers()
+        .get("subscription-userinfo")
+        .and_then(|value| value.to_str().ok())
+        .and_then(SubscriptionUsage::from_header);
+
     let body = response.text().await?;
     let body = body.trim();
 
@@ -28,7 +41,7 @@ pub async fn fetch_and_parse_configs(url: &str) -> Result<String, Box<dyn Error>
     // .map(String::from)
     // .collect();
 
-    Ok(content)
+    Ok(Fetched { content, usage })
 }
 
 // #[tokio::main]
@@ -0,0 +1,367 @@
+use luxnulla::config::{TproxyConfig, TproxyMode};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// nftables table holding every rule luxnulla installs.
+const NFT_TABLE: &str = "luxnulla";
+
+/// Private, loopback, link-local, multicast and reserved ranges.
+const BYPASS_V4: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+];
+const BYPASS_V6: &[&str] = &["::1/128", "fc00::/7", "fe80::/10", "ff00::/8"];
+
+/// What was installed, kept in the daemon state so it can be removed even
+/// after luxnulla.kdl changed or the daemon crashed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledTproxy {
+    /// `inet` for tproxy mode, `ip` for redirect mode.
+    pub family: String,
+    /// `(mark, table)` of the policy routing rules, if any were added.
+    pub policy_routing: Option<(u32, u32)>,
+}
+
+/// The nftables script `install` loads. Re-loading it replaces the table.
+pub fn ruleset(config: &TproxyConfig) -> String {
+    let (bypass_v6, bypass_v4): (Vec<&str>, Vec<&str>) = config
+        .bypass
+        .iter()
+        .map(String::as_str)
+        .partition(|range| range.contains(':'));
+    let v4 = BYPASS_V4
+        .iter()
+        .copied()
+        .chain(bypass_v4)
+        .collect::<Vec<_>>();
+    let v6 = BYPASS_V6
+        .iter()
+        .copied()
+        .chain(bypass_v6)
+        .collect::<Vec<_>>();
+
+    let set = |name: &str, kind: &str, elements: &[&str]| {
+        format!(
+            "\tset {} {{\n\t\ttype {}\n\t\tflags interval\n\t\telements = {{ {} }}\n\t}}\n",
+            name,
+            kind,
+            elements.join(", ")
+        )
+    };
+
+    let family = family(config.mode);
+    let mut script = format!(
+        "table {family} {NFT_TABLE}\ndelete table {family} {NFT_TABLE}\ntable {family} {NFT_TABLE} {{\n"
+    );
+    script.push_str(&set("bypass4", "ipv4_addr", &v4));
+
+    match config.mode {
+        TproxyMode::Tproxy => {
+            script.push_str(&set("bypass6", "ipv6_addr", &v6));
+            script.push_str(&format!(
+                "\tchain prerouting {{\n\
+                 \t\ttype filter hook prerouting priority mangle; policy accept;\n\
+                 \t\tfib daddr type local return\n\
+                 \t\tip daddr @bypass4 return\n\
+                 \t\tip6 daddr @bypass6 return\n\
+                 \t\tmeta l4proto {{ tcp, udp }} meta mark set {mark} tproxy to :{port} accept\n\
+                 \t}}\n\
+                 \tchain output {{\n\
+                 \t\ttype route hook output priority mangle; policy accept;\n\
+                 \t\tmeta mark {outbound_mark} return\n\
+                 \t\tip daddr @bypass4 return\n\
+                 \t\tip6 daddr @bypass6 return\n\
+                 \t\tmeta l4proto {{ tcp, udp }} meta mark set {mark}\n\
+                 \t}}\n",
+                mark = config.mark,
+                port = config.port,
+                outbound_mark = config.outbound_mark,
+            ));
+        }
+        TproxyMode::Redirect => {
+            script.push_str(&format!(
+                "\tchain prerouting {{\n\
+                 \t\ttype nat hook prerouting priority dstnat; policy accept;\n\
+                 \t\tip daddr @bypass4 return\n\
+                 \t\tmeta l4proto tcp redirect to :{port}\n\
+                 \t}}\n\
+                 \tchain output {{\n\
+                 \t\ttype nat hook output priority -100; policy accept;\n\
+                 \t\tmeta mark {outbound_mark} return\n\
+                 \t\tip daddr @bypass4 return\n\
+                 \t\tmeta l4proto tcp redirect to :{port}\n\
+                 \t}}\n",
+                port = config.port,
+                outbound_mark = config.outbound_mark,
+            ));
+        }
+    }
+
+    script.push_str("}\n");
+    script
+}
+
+/// `ip` invocations that deliver marked packets to the local socket.
+pub fn policy_routing(config: &TproxyConfig) -> Vec<Vec<String>> {
+    if config.mode != TproxyMode::Tproxy {
+        return Vec::new();
+    }
+
+    let mark = config.mark.to_string();
+    let table = config.table.to_string();
+    ["-4", "-6"]
+        .iter()
+        .flat_map(|version| {
+            [
+                vec![version, "rule", "add", "fwmark", &mark, "table", &table],
+                vec![
+                    version, "route", "add", "local", "default", "dev", "lo", "table", &table,
+                ],
+            ]
+            .map(|args| args.into_iter().map(String::from).collect())
+        })
+        .collect()
+}
+
+/// Human-readable summary of what `install` would do.
+pub fn dry_run(config: &TproxyConfig) -> String {
+    let mut out = format!("# nft -f -\n{}", ruleset(config));
+    for args in policy_routing(config) {
+        out.push_str(&format!("# ip {}\n", args.join(" ")));
+    }
+    out
+}
+
+/// Loads the ruleset and adds the policy routing. A partial install is
+/// rolled back before returning an error.
+pub async fn install(config: &TproxyConfig) -> Result<InstalledTproxy, String> {
+    let mut installed = InstalledTproxy {
+        family: family(config.mode).to_string(),
+        policy_routing: None,
+    };
+
+    load_ruleset(&ruleset(config)).await?;
+
+    if config.mode == TproxyMode::Tproxy {
+        installed.policy_routing = Some((config.mark, config.table));
+        for args in policy_routing(config) {
+            if let Err(e) = run("ip", &args).await {
+                let _ = remove(&installed).await;
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(installed)
+}
+
+/// Removes everything `install` added. Keeps going after a failure and
+/// reports all of them; things that are already gone are not errors.
+pub async fn remove(installed: &InstalledTproxy) -> Result<(), String> {
+    let mut errors = Vec::new();
+
+    if let Err(e) = run("nft", &["delete", "table", &installed.family, NFT_TABLE]).await
+        && !is_missing(&e)
+    {
+        errors.push(e);
+    }
+
+    if let Some((mark, table)) = installed.policy_routing {
+        let (mark, table) = (mark.to_string(), table.to_string());
+        for version in ["-4", "-6"] {
+            // `ip rule add` twice adds two rules; delete until none is left.
+            while run(
+                "ip",
+                &[version, "rule", "del", "fwmark", &mark, "table", &table],
+            )
+            .await
+            .is_ok()
+            {}
+            if let Err(e) = run("ip", &[version, "route", "flush", "table", &table]).await
+                && !is_missing(&e)
+            {
+                errors.push(e);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn family(mode: TproxyMode) -> &'static str {
+    match mode {
+        TproxyMode::Tproxy => "inet",
+        TproxyMode::Redirect => "ip",
+    }
+}
+
+fn is_missing(error: &str) -> bool {
+    error.contains("No such file or directory") || error.contains("FIB table does not exist")
+}
+
+async fn load_ruleset(script: &str) -> Result<(), String> {
+    let mut nft = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run nft: {}", e))?;
+
+    if let Some(mut stdin) = nft.stdin.take() {
+        stdin
+            .write_all(script.as_bytes())
+            .await
+            .map_err(|e| format!("failed to write to nft: {}", e))?;
+    }
+
+    let output = nft
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("failed to run nft: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "nft rejected the ruleset: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+async fn run<S: AsRef<str>>(program: &str, args: &[S]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+    let output = Command::new(program)
+        .args(&args)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", program, e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} {}: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: TproxyMode) -> TproxyConfig {
+        TproxyConfig {
+            mode,
+            bypass: vec!["203.0.113.0/24".to_string(), "2001:db8::/32".to_string()],
+            ..TproxyConfig::default()
+        }
+    }
+
+    #[test]
+    fn tproxy_ruleset() {
+        assert_eq!(
+            ruleset(&config(TproxyMode::Tproxy)),
+            "table inet luxnulla\n\
+             delete table inet luxnulla\n\
+             table inet luxnulla {\n\
+             \tset bypass4 {\n\
+             \t\ttype ipv4_addr\n\
+             \t\tflags interval\n\
+             \t\telements = { 0.0.0.0/8, 10.0.0.0/8, 100.64.0.0/10, 127.0.0.0/8, \
+             169.254.0.0/16, 172.16.0.0/12, 192.168.0.0/16, 224.0.0.0/4, 240.0.0.0/4, \
+             203.0.113.0/24 }\n\
+             \t}\n\
+             \tset bypass6 {\n\
+             \t\ttype ipv6_addr\n\
+             \t\tflags interval\n\
+             \t\telements = { ::1/128, fc00::/7, fe80::/10, ff00::/8, 2001:db8::/32 }\n\
+             \t}\n\
+             \tchain prerouting {\n\
+             \t\ttype filter hook prerouting priority mangle; policy accept;\n\
+             \t\tfib daddr type local return\n\
+             \t\tip daddr @bypass4 return\n\
+             \t\tip6 daddr @bypass6 return\n\
+             \t\tmeta l4proto { tcp, udp } meta mark set 1 tproxy to :12345 accept\n\
+             \t}\n\
+             \tchain output {\n\
+             \t\ttype route hook output priority mangle; policy accept;\n\
+             \t\tmeta mark 255 return\n\
+             \t\tip daddr @bypass4 return\n\
+             \t\tip6 daddr @bypass6 return\n\
+             \t\tmeta l4proto { tcp, udp } meta mark set 1\n\
+             \t}\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn redirect_ruleset() {
+        let script = ruleset(&config(TproxyMode::Redirect));
+        assert!(script.starts_with("table ip luxnulla\ndelete table ip luxnulla\n"));
+        assert!(script.contains("240.0.0.0/4, 203.0.113.0/24 }"));
+        // IPv6 is not redirected, so its ranges have nowhere to go.
+        assert!(!script.contains("bypass6"));
+        assert!(!script.contains("2001:db8::/32"));
+        assert!(script.contains(
+            "\tchain output {\n\
+             \t\ttype nat hook output priority -100; policy accept;\n\
+             \t\tmeta mark 255 return\n\
+             \t\tip daddr @bypass4 return\n\
+             \t\tmeta l4proto tcp redirect to :12345\n\
+             \t}\n"
+        ));
+    }
+
+    #[test]
+    fn policy_routing_per_family() {
+        let rules: Vec<String> = policy_routing(&config(TproxyMode::Tproxy))
+            .iter()
+            .map(|args| args.join(" "))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "-4 rule add fwmark 1 table 100",
+                "-4 route add local default dev lo table 100",
+                "-6 rule add fwmark 1 table 100",
+                "-6 route add local default dev lo table 100",
+            ]
+        );
+        assert!(policy_routing(&config(TproxyMode::Redirect)).is_empty());
+    }
+
+    #[test]
+    fn dry_run_lists_every_step() {
+        let config = config(TproxyMode::Tproxy);
+        let out = dry_run(&config);
+        assert!(out.starts_with(&format!("# nft -f -\n{}", ruleset(&config))));
+        assert!(out.ends_with(
+            "# ip -4 rule add fwmark 1 table 100\n\
+             # ip -4 route add local default dev lo table 100\n\
+             # ip -6 rule add fwmark 1 table 100\n\
+             # ip -6 route add local default dev lo table 100\n"
+        ));
+
+        let out = dry_run(&TproxyConfig {
+            mode: TproxyMode::Redirect,
+            ..TproxyConfig::default()
+        });
+        assert!(!out.contains("# ip "));
+    }
+}
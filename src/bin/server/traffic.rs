@@ -0,0 +1,330 @@
+//! Traffic counters sampled from xray's stats API through `xray api
+//! statsquery`, turned into totals and per-second rates.
+
+use luxnulla::{TrafficSeries, TrafficStats};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::xray_config::{API_INBOUND_TAG, GROUP_TAG_PREFIX, PROXY_TAG};
+
+/// Rate samples kept per series.
+const HISTORY: usize = 120;
+
+/// Sampling stops when nobody asked for traffic for this long.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct TrafficMonitor {
+    /// Counter values of the last sample, by stat name.
+    counters: HashMap<String, u64>,
+    sampled: Option<Instant>,
+    proxy: Series,
+    inbounds: BTreeMap<String, Series>,
+    /// Last `Traffic` request.
+    watched: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Series {
+    uplink: u64,
+    downlink: u64,
+    /// Bytes per second as (uplink, downlink), oldest first.
+    rates: VecDeque<(u64, u64)>,
+}
+
+impl Series {
+    fn push(&mut self, uplink: u64, downlink: u64, elapsed: Duration, previous: (u64, u64)) {
+        // Counters start over when xray restarts.
+        let delta = |now: u64, before: u64| if now >= before { now - before } else { now };
+        let per_second = |bytes: u64| (bytes as f64 / elapsed.as_secs_f64()).round() as u64;
+
+        if self.rates.len() == HISTORY {
+            self.rates.pop_front();
+        }
+        self.rates.push_back((
+            per_second(delta(uplink, previous.0)),
+            per_second(delta(downlink, previous.1)),
+        ));
+        self.uplink = uplink;
+        self.downlink = downlink;
+    }
+
+    fn to_info(&self, tag: &str) -> TrafficSeries {
+        TrafficSeries {
+            tag: tag.to_string(),
+            uplink: self.uplink,
+            downlink: self.downlink,
+            uplink_rates: self.rates.iter().map(|r| r.0).collect(),
+            downlink_rates: self.rates.iter().map(|r| r.1).collect(),
+        }
+    }
+}
+
+/// Counters of one inbound or outbound, summed over `tags`.
+fn totals<'a>(
+    counters: &HashMap<String, u64>,
+    kind: &str,
+    tags: impl Iterator<Item = &'a str> + Clone,
+) -> (u64, u64) {
+    let sum = |direction: &str| {
+        tags.clone()
+            .map(|tag| {
+                counters
+                    .get(&format!("{}>>>{}>>>traffic>>>{}", kind, tag, direction))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .sum()
+    };
+    (sum("uplink"), sum("downlink"))
+}
+
+/// Tags of the counters of `kind` (`inbound` or `outbound`).
+fn tags<'a>(counters: &'a HashMap<String, u64>, kind: &str) -> Vec<&'a str> {
+    let mut tags: Vec<&str> = counters
+        .keys()
+        .filter_map(|name| {
+            let mut parts = name.split(">>>");
+            (parts.next() == Some(kind)).then(|| parts.next())?
+        })
+        .collect();
+    tags.sort_unstable();
+    tags.dedup();
+    tags
+}
+
+impl TrafficMonitor {
+    pub fn watch(&mut self) {
+        self.watched = Some(Instant::now());
+    }
+
+    /// A client asked for traffic recently.
+    pub fn is_watched(&self) -> bool {
+        self.watched.is_some_and(|at| at.elapsed() < WATCH_TIMEOUT)
+    }
+
+    /// The last sample is at least `interval` old.
+    pub fn is_stale(&self, interval: Duration) -> bool {
+        self.sampled.is_none_or(|at| at.elapsed() >= interval)
+    }
+
+    /// Takes in counters from `query`.
+    pub fn record(&mut self, counters: HashMap<String, u64>) {
+        self.record_at(counters, Instant::now());
+    }
+
+    fn record_at(&mut self, counters: HashMap<String, u64>, now: Instant) {
+        // Without an earlier sample there is no rate yet, only totals.
+        let elapsed = self.sampled.map(|at| now - at);
+
+        let outbounds = tags(&counters, "outbound");
+        let proxied = outbounds
+            .iter()
+            .copied()
+            .filter(|tag| *tag == PROXY_TAG || tag.starts_with(GROUP_TAG_PREFIX));
+        let proxy = totals(&counters, "outbound", proxied.clone());
+        let previous = totals(&self.counters, "outbound", proxied);
+        match elapsed {
+            Some(elapsed) => self.proxy.push(proxy.0, proxy.1, elapsed, previous),
+            None => (self.proxy.uplink, self.proxy.downlink) = proxy,
+        }
+
+        let inbound_tags: Vec<&str> = tags(&counters, "inbound")
+            .into_iter()
+            .filter(|tag| *tag != API_INBOUND_TAG)
+            .collect();
+        self.inbounds
+            .retain(|tag, _| inbound_tags.contains(&tag.as_str()));
+        for tag in inbound_tags {
+            let current = totals(&counters, "inbound", std::iter::once(tag));
+            let previous = totals(&self.counters, "inbound", std::iter::once(tag));
+            let series = self.inbounds.entry(tag.to_string()).or_default();
+            match elapsed {
+                Some(elapsed) => series.push(current.0, current.1, elapsed, previous),
+                None => (series.uplink, series.downlink) = current,
+            }
+        }
+
+        self.counters = counters;
+        self.sampled = Some(now);
+    }
+
+    pub fn stats(&self, interval: Duration) -> TrafficStats {
+        TrafficStats {
+            interval_ms: interval.as_millis() as u64,
+            proxy: self.proxy.to_info(PROXY_TAG),
+            inbounds: self
+                .inbounds
+                .iter()
+                .map(|(tag, series)| series.to_info(tag))
+                .collect(),
+        }
+    }
+}
+
+/// Reads every counter through `xray api statsquery`.
+pub async fn query(xray_binary: &str, server: &str) -> Result<HashMap<String, u64>, String> {
+    let output = tokio::process::Command::new(xray_binary)
+        .args(["api", "statsquery"])
+        .arg(format!("--server={}", server))
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", xray_binary, e))?;
+
+    if !output.status.success() {
+        let mut message = String::from_utf8_lossy(&output.stdout).into_owned();
+        message.push_str(&String::from_utf8_lossy(&output.stderr));
+        return Err(format!("xray api statsquery failed: {}", message.trim()));
+    }
+
+    parse_stats(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses `{"stat": [{"name": "...", "value": "123"}]}`. protojson writes
+/// 64-bit values as strings and leaves out zeroes.
+fn parse_stats(output: &str) -> Result<HashMap<String, u64>, String> {
+    let root: Value =
+        serde_json::from_str(output).map_err(|e| format!("unexpected statsquery output: {}", e))?;
+
+    Ok(root
+        .get("stat")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|stat| {
+            let name = stat.get("name")?.as_str()?.to_string();
+            let value = match stat.get("value") {
+                None => 0,
+                Some(Value::String(s)) => s.parse().ok()?,
+                Some(value) => value.as_u64()?,
+            };
+            Some((name, value))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `xray api statsquery` output for the given counters.
+    fn statsquery(counters: &[(&str, &str, &str, u64)]) -> String {
+        let stats: Vec<Value> = counters
+            .iter()
+            .map(|(kind, tag, direction, value)| {
+                let name = format!("{}>>>{}>>>traffic>>>{}", kind, tag, direction);
+                // protojson leaves out zero values.
+                if *value == 0 {
+                    serde_json::json!({ "name": name })
+                } else {
+                    serde_json::json!({ "name": name, "value": value.to_string() })
+                }
+            })
+            .collect();
+        serde_json::json!({ "stat": stats }).to_string()
+    }
+
+    #[test]
+    fn parse_statsquery_output() {
+        let counters = parse_stats(
+            r#"{"stat": [
+                {"name": "outbound>>>proxy>>>traffic>>>uplink", "value": "18446744073709551615"},
+                {"name": "outbound>>>proxy>>>traffic>>>downlink"},
+                {"name": "inbound>>>in:socks-1080>>>traffic>>>uplink", "value": 42},
+                {"name": "broken", "value": "not a number"},
+                {"value": "1"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(counters.len(), 3);
+        assert_eq!(counters["outbound>>>proxy>>>traffic>>>uplink"], u64::MAX);
+        assert_eq!(counters["outbound>>>proxy>>>traffic>>>downlink"], 0);
+        assert_eq!(counters["inbound>>>in:socks-1080>>>traffic>>>uplink"], 42);
+
+        // A fresh xray without traffic answers with an empty object.
+        assert!(parse_stats("{}").unwrap().is_empty());
+        assert!(
+            parse_stats("failed to dial")
+                .unwrap_err()
+                .starts_with("unexpected statsquery output")
+        );
+    }
+
+    #[test]
+    fn proxy_totals_sum_node_and_group_outbounds() {
+        let mut monitor = TrafficMonitor::default();
+        monitor.record(
+            parse_stats(&statsquery(&[
+                ("outbound", "proxy", "uplink", 100),
+                ("outbound", "proxy", "downlink", 1000),
+                ("outbound", "group:europe:0", "uplink", 10),
+                ("outbound", "group:europe:1", "downlink", 20),
+                ("outbound", "direct", "uplink", 5000),
+                ("inbound", "in:socks-1080", "uplink", 7),
+                ("inbound", "in:socks-1080", "downlink", 0),
+                ("inbound", "in:api", "uplink", 9999),
+            ]))
+            .unwrap(),
+        );
+
+        let stats = monitor.stats(Duration::from_secs(1));
+        assert_eq!(stats.interval_ms, 1000);
+        assert_eq!((stats.proxy.uplink, stats.proxy.downlink), (110, 1020));
+        // No rate before the second sample.
+        assert_eq!(stats.proxy.rate(), None);
+        let tags: Vec<_> = stats.inbounds.iter().map(|s| s.tag.as_str()).collect();
+        assert_eq!(tags, ["in:socks-1080"]);
+        assert_eq!(stats.inbounds[0].uplink, 7);
+    }
+
+    #[test]
+    fn rates_and_counter_reset() {
+        let start = Instant::now();
+        let sample = |up: u64, down: u64| {
+            parse_stats(&statsquery(&[
+                ("outbound", "proxy", "uplink", up),
+                ("outbound", "proxy", "downlink", down),
+                ("inbound", "in:http-8080", "uplink", up),
+            ]))
+            .unwrap()
+        };
+
+        let mut monitor = TrafficMonitor::default();
+        monitor.record_at(sample(1000, 4000), start);
+        monitor.record_at(sample(3000, 8000), start + Duration::from_secs(2));
+        assert_eq!(
+            monitor.stats(Duration::from_secs(2)).proxy.rate(),
+            Some((1000, 2000))
+        );
+
+        // xray restarted: the counters start over instead of going negative.
+        monitor.record_at(sample(500, 0), start + Duration::from_secs(3));
+        let stats = monitor.stats(Duration::from_secs(1));
+        assert_eq!(stats.proxy.rate(), Some((500, 0)));
+        assert_eq!((stats.proxy.uplink, stats.proxy.downlink), (500, 0));
+        assert_eq!(stats.proxy.uplink_rates, [1000, 500]);
+        assert_eq!(stats.inbounds[0].uplink_rates, [1000, 500]);
+    }
+
+    #[test]
+    fn history_and_vanished_inbounds() {
+        let start = Instant::now();
+        let mut monitor = TrafficMonitor::default();
+        for i in 0..=HISTORY as u64 + 5 {
+            let mut counters =
+                parse_stats(&statsquery(&[("outbound", "proxy", "uplink", i * 10)])).unwrap();
+            if i < 3 {
+                counters.insert("inbound>>>in:socks-1080>>>traffic>>>uplink".to_string(), i);
+            }
+            monitor.record_at(counters, start + Duration::from_secs(i));
+        }
+
+        let stats = monitor.stats(Duration::from_secs(1));
+        assert_eq!(stats.proxy.uplink_rates.len(), HISTORY);
+        assert!(stats.proxy.uplink_rates.iter().all(|rate| *rate == 10));
+        assert!(stats.inbounds.is_empty());
+    }
+}
@@ -0,0 +1,456 @@
+use futures::future::join_all;
+use luxnulla::{UrlTestResult, config::LuxnullaConfig};
+use rand::Rng;
+use serde_json::{Value, json};
+use std::{
+    path::Path,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, process::Child, time::sleep};
+
+/// How long the throwaway xray gets to open its inbounds.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct UrlTestCandidate {
+    pub id: String,
+    pub outbound: Value,
+    /// Chain hops `outbound` dials through; their tags must be unique
+    /// within the batch.
+    pub dialers: Vec<Value>,
+}
+
+pub struct UrlTestSettings<'a> {
+    pub xray_binary: &'a str,
+    /// Directory for the temporary xray config.
+    pub work_dir: &'a Path,
+    pub url: &'a str,
+    pub timeout: Duration,
+    /// SO_MARK for the test outbounds while a transparent proxy is set up.
+    pub outbound_mark: Option<u32>,
+}
+
+impl<'a> UrlTestSettings<'a> {
+    pub fn new(config: &'a LuxnullaConfig, work_dir: &'a Path) -> Self {
+        Self {
+            xray_binary: &config.xray_binary,
+            work_dir,
+            url: &config.url_test.url,
+            timeout: config.url_test.timeout,
+            outbound_mark: config.tproxy.as_ref().map(|tproxy| tproxy.outbound_mark),
+        }
+    }
+}
+
+/// Tests one batch of candidates through a single temporary xray instance.
+/// Each candidate gets its own socks inbound routed to its outbound, and
+/// `settings.url` is requested through all of them concurrently.
+pub async fn run_batch(
+    candidates: &[UrlTestCandidate],
+    settings: &UrlTestSettings<'_>,
+) -> Result<Vec<UrlTestResult>, String> {
+    let ports = free_ports(candidates.len())?;
+    let config = batch_config(candidates, &ports, settings.outbound_mark);
+
+    let suffix: u32 = rand::rng().random();
+    let config_path = settings
+        .work_dir
+        .join(format!(".xray.urltest.{}.json", suffix));
+    tokio::fs::write(&config_path, config.to_string())
+        .await
+        .map_err(|e| format!("failed to write temporary config: {}", e))?;
+
+    let result = async {
+        let mut xray = tokio::process::Command::new(settings.xray_binary)
+            .args(["run", "-c"])
+            .arg(&config_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("failed to run {}: {}", settings.xray_binary, e))?;
+
+        wait_for_ports(&mut xray, &ports).await?;
+
+        let results = join_all(
+            ports
+                .iter()
+                .map(|port| request_through(*port, settings.url, settings.timeout)),
+        )
+        .await;
+
+        let _ = xray.kill().await;
+        Ok(results)
+    }
+    .await;
+
+    let _ = tokio::fs::remove_file(&config_path).await;
+    result
+}
+
+fn batch_config(candidates: &[UrlTestCandidate], ports: &[u16], mark: Option<u32>) -> Value {
+    let mut inbounds = Vec::new();
+    let mut outbounds = Vec::new();
+    let mut rules = Vec::new();
+
+    for (i, (candidate, port)) in candidates.iter().zip(ports).enumerate() {
+        let inbound_tag = format!("test-in-{}", i);
+        let outbound_tag = format!("test-out-{}", i);
+
+        inbounds.push(json!({
+            "tag": inbound_tag,
+            "listen": "127.0.0.1",
+            "port": port,
+            "protocol": "socks",
+            "settings": { "udp": false },
+        }));
+
+        let mut outbound = candidate.outbound.clone();
+        outbound["tag"] = json!(outbound_tag);
+        outbounds.push(outbound);
+        outbounds.extend(candidate.dialers.iter().cloned());
+
+        rules.push(json!({
+            "type": "field",
+            "inboundTag": [inbound_tag],
+            "outboundTag": outbound_tag,
+        }));
+    }
+
+    if let Some(mark) = mark {
+        for outbound in &mut outbounds {
+            outbound["streamSettings"]["sockopt"]["mark"] = json!(mark);
+        }
+    }
+
+    json!({
+        "log": { "loglevel": "none" },
+        "inbounds": inbounds,
+        "outbounds": outbounds,
+        "routing": { "rules": rules },
+    })
+}
+
+/// Asks the OS for `count` unused local ports. They are released before xray
+/// binds them, which is racy but good enough for a short-lived test.
+fn free_ports(count: usize) -> Result<Vec<u16>, String> {
+    let listeners = (0..count)
+        .map(|_| std::net::TcpListener::bind("127.0.0.1:0"))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to reserve local port: {}", e))?;
+
+    listeners
+        .iter()
+        .map(|l| l.local_addr().map(|a| a.port()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("failed to reserve local port: {}", e))
+}
+
+async fn wait_for_ports(xray: &mut Child, ports: &[u16]) -> Result<(), String> {
+    let started = Instant::now();
+
+    for port in ports {
+        loop {
+            if TcpStream::connect(("127.0.0.1", *port)).await.is_ok() {
+                break;
+            }
+            if let Ok(Some(status)) = xray.try_wait() {
+                return Err(format!("test xray exited early: {}", status));
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                return Err("test xray did not open its inbounds in time".to_string());
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn request_through(port: u16, url: &str, timeout: Duration) -> UrlTestResult {
+    let mut result = UrlTestResult::default();
+
+    let client =
+        match reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", port)).and_then(|proxy| {
+            reqwest::Client::builder()
+                .proxy(proxy)
+                .timeout(timeout)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+        }) {
+            Ok(client) => client,
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+
+    let started = Instant::now();
+    match client.get(url).send().await {
+        Ok(response) => {
+            let status = response.status();
+            result.status = Some(status.as_u16());
+            if status.is_success() || status.is_redirection() {
+                result.latency_ms = Some(started.elapsed().as_millis() as u32);
+            } else {
+                result.error = Some(format!("unexpected status {}", status));
+            }
+        }
+        Err(e) if e.is_timeout() => result.error = Some("timed out".to_string()),
+        Err(e) => result.error = Some(error_chain(&e)),
+    }
+
+    result
+}
+
+/// reqwest's top-level message rarely says what went wrong; append causes.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// An "xray" that only stays alive; the test itself serves the socks
+    /// inbounds from the config it was given. Written once, before any test
+    /// spawns it, so no fork can inherit it open for writing.
+    fn stub_xray() -> &'static str {
+        static STUB: OnceLock<String> = OnceLock::new();
+        STUB.get_or_init(|| {
+            let path =
+                std::env::temp_dir().join(format!("luxnulla-stub-xray-{}", std::process::id()));
+            std::fs::write(&path, "#!/bin/sh\nexec sleep 30\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().into_owned()
+        })
+    }
+
+    fn work_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("luxnulla-urltest-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Answers every request with `status`, or never answers with `None`.
+    async fn http_server(status: Option<u16>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/generate_204", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let Some(status) = status else {
+                    held.push(stream);
+                    continue;
+                };
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    let _ = stream.read(&mut buf).await;
+                    let response = format!(
+                        "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        url
+    }
+
+    /// Plays xray: waits for the batch config in `dir` and serves each socks
+    /// inbound, connecting for `freedom` outbounds and dropping the rest.
+    fn serve_batch(dir: PathBuf) {
+        tokio::spawn(async move {
+            let config: Value = loop {
+                let found = std::fs::read_dir(&dir).unwrap().flatten().find(|entry| {
+                    entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with(".xray.urltest.")
+                });
+                if let Some(config) = found
+                    .and_then(|entry| std::fs::read_to_string(entry.path()).ok())
+                    .and_then(|text| serde_json::from_str(&text).ok())
+                {
+                    break config;
+                }
+                sleep(Duration::from_millis(20)).await;
+            };
+
+            for (i, inbound) in config["inbounds"].as_array().unwrap().iter().enumerate() {
+                let tag = format!("test-out-{}", i);
+                let outbound = config["outbounds"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .find(|o| o["tag"] == tag.as_str())
+                    .unwrap();
+                let connects = outbound["protocol"] == "freedom";
+                let port = inbound["port"].as_u64().unwrap() as u16;
+                let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+                tokio::spawn(async move {
+                    loop {
+                        let (stream, _) = listener.accept().await.unwrap();
+                        tokio::spawn(socks(stream, connects));
+                    }
+                });
+            }
+        });
+    }
+
+    async fn socks(mut client: TcpStream, connects: bool) -> std::io::Result<()> {
+        let mut head = [0; 2];
+        client.read_exact(&mut head).await?;
+        let mut methods = vec![0; head[1] as usize];
+        client.read_exact(&mut methods).await?;
+        client.write_all(&[5, 0]).await?;
+
+        let mut request = [0; 4];
+        client.read_exact(&mut request).await?;
+        let host = match request[3] {
+            1 => {
+                let mut ip = [0; 4];
+                client.read_exact(&mut ip).await?;
+                std::net::Ipv4Addr::from(ip).to_string()
+            }
+            3 => {
+                let mut len = [0; 1];
+                client.read_exact(&mut len).await?;
+                let mut name = vec![0; len[0] as usize];
+                client.read_exact(&mut name).await?;
+                String::from_utf8_lossy(&name).into_owned()
+            }
+            _ => return Ok(()),
+        };
+        let port = client.read_u16().await?;
+        if !connects {
+            return Ok(());
+        }
+
+        let mut upstream = TcpStream::connect((host.as_str(), port)).await?;
+        client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        Ok(())
+    }
+
+    fn candidate(id: &str, protocol: &str) -> UrlTestCandidate {
+        UrlTestCandidate {
+            id: id.to_string(),
+            outbound: json!({ "protocol": protocol }),
+            dialers: Vec::new(),
+        }
+    }
+
+    async fn run(
+        name: &str,
+        status: Option<u16>,
+        candidates: &[UrlTestCandidate],
+    ) -> Vec<UrlTestResult> {
+        let url = http_server(status).await;
+        let dir = work_dir(name);
+        serve_batch(dir.clone());
+        let settings = UrlTestSettings {
+            xray_binary: stub_xray(),
+            work_dir: &dir,
+            url: &url,
+            timeout: Duration::from_millis(500),
+            outbound_mark: None,
+        };
+        let results = run_batch(candidates, &settings).await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        results
+    }
+
+    #[tokio::test]
+    async fn success_has_latency() {
+        let results = run("success", Some(204), &[candidate("a", "freedom")]).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].status, Some(204));
+        assert!(results[0].latency_ms.is_some());
+        assert_eq!(results[0].error, None);
+    }
+
+    #[tokio::test]
+    async fn error_status_fails() {
+        let results = run("status", Some(503), &[candidate("a", "freedom")]).await;
+        assert_eq!(results[0].status, Some(503));
+        assert_eq!(results[0].latency_ms, None);
+        assert!(results[0].error.as_deref().unwrap().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn silent_server_times_out() {
+        let results = run("timeout", None, &[candidate("a", "freedom")]).await;
+        assert_eq!(results[0].latency_ms, None);
+        assert_eq!(results[0].error.as_deref(), Some("timed out"));
+    }
+
+    #[tokio::test]
+    async fn one_failing_node_in_batch() {
+        let results = run(
+            "batch",
+            Some(200),
+            &[
+                candidate("a", "freedom"),
+                candidate("b", "blackhole"),
+                candidate("c", "freedom"),
+            ],
+        )
+        .await;
+        assert_eq!(results.len(), 3);
+        assert!(results[0].latency_ms.is_some());
+        assert_eq!(results[1].latency_ms, None);
+        assert!(results[1].error.is_some());
+        assert!(results[2].latency_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn missing_binary_is_an_error() {
+        let dir = work_dir("missing");
+        let settings = UrlTestSettings {
+            xray_binary: "/nonexistent/xray",
+            work_dir: &dir,
+            url: "http://127.0.0.1:1/",
+            timeout: Duration::from_millis(500),
+            outbound_mark: None,
+        };
+        let result = run_batch(&[candidate("a", "freedom")], &settings).await;
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(result.unwrap_err().contains("failed to run"));
+    }
+
+    #[test]
+    fn outbounds_are_marked_for_tproxy() {
+        let mut chained = candidate("b", "vless");
+        chained
+            .dialers
+            .push(json!({ "tag": "hop", "protocol": "vless" }));
+        let candidates = [candidate("a", "freedom"), chained];
+
+        let config = batch_config(&candidates, &[1, 2], Some(255));
+        let outbounds = config["outbounds"].as_array().unwrap();
+        assert_eq!(outbounds.len(), 3);
+        for outbound in outbounds {
+            assert_eq!(outbound["streamSettings"]["sockopt"]["mark"], 255);
+        }
+
+        let config = batch_config(&candidates, &[1, 2], None);
+        for outbound in config["outbounds"].as_array().unwrap() {
+            assert!(outbound.get("streamSettings").is_none());
+        }
+    }
+}
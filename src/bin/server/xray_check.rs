@@ -0,0 +1,33 @@
+use rand::Rng;
+use std::path::Path;
+
+/// Runs `xray run -test` against `contents` written to a temporary file in
+/// `dir`. On failure returns xray's combined output.
+pub async fn test_config(xray_binary: &str, dir: &Path, contents: &[u8]) -> Result<(), String> {
+    let suffix: u32 = rand::rng().random();
+    let tmp_path = dir.join(format!(".xray.test.{}.json", suffix));
+
+    tokio::fs::write(&tmp_path, contents)
+        .await
+        .map_err(|e| format!("failed to write temporary config: {}", e))?;
+
+    let output = tokio::process::Command::new(xray_binary)
+        .args(["run", "-test", "-c"])
+        .arg(&tmp_path)
+        .output()
+        .await;
+
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+
+    let output = output.map_err(|e| format!("failed to run {}: {}", xray_binary, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let mut message = String::from_utf8_lossy(&output.stdout).into_owned();
+    message.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Err(message
+        .trim()
+        .replace(tmp_path.to_string_lossy().as_ref(), "xray.json"))
+}
@@ -0,0 +1,1300 @@
+use luxnulla::config::{
+    DnsConfig, InboundConfig, InboundProtocol, ObservatoryConfig, RoutingConfig, RoutingRule,
+    RuleTarget, StatsConfig, TproxyConfig, TproxyMode,
+};
+use luxnulla::{RoutingInfo, RuleInfo};
+use serde_json::{Map, Value, json};
+
+/// Tag of the outbound that carries the selected node.
+pub const PROXY_TAG: &str = "proxy";
+pub const DIRECT_TAG: &str = "direct";
+pub const BLOCK_TAG: &str = "block";
+/// Outbound that answers DNS queries caught by the port 53 rule.
+pub const DNS_OUT_TAG: &str = "dns-out";
+
+/// Tag of the generated `dns` block; queries xray makes itself arrive at the
+/// router with it as inbound tag.
+const DNS_TAG: &str = "luxnulla:dns";
+/// Prefix of every outbound and balancer generated for a node group, so the
+/// next generation can remove them again.
+pub const GROUP_TAG_PREFIX: &str = "group:";
+
+/// Prefix of the non-exit hops of a chain, for the same reason.
+pub const CHAIN_TAG_PREFIX: &str = "chain:";
+
+/// Prefix of generated inbound tags, for the same reason.
+pub const INBOUND_TAG_PREFIX: &str = "in:";
+
+/// `ruleTag` prefix of generated routing rules, for the same reason.
+const RULE_TAG_PREFIX: &str = "luxnulla:";
+
+/// Tag of the generated `api` block, which is also the outbound its inbound
+/// is routed to.
+const API_TAG: &str = "luxnulla:api";
+/// The inbound `xray api` commands connect to.
+pub const API_INBOUND_TAG: &str = "in:api";
+/// The dokodemo-door inbound tproxy redirects traffic to.
+pub const TPROXY_INBOUND_TAG: &str = "in:tproxy";
+
+/// Everything luxnulla writes into `xray.json`.
+pub struct Generation<'a> {
+    /// Outbound of the selected node or chain exit; `None` keeps the one
+    /// already there.
+    pub proxy: Option<Value>,
+    /// Hops `proxy` dials through when a chain is selected.
+    pub dialers: Vec<Value>,
+    /// Group whose balancer carries proxied traffic instead of a node.
+    pub active_group: Option<String>,
+    /// Balancers for the active group and every group a rule targets.
+    pub balancers: Vec<GroupBalancer>,
+    pub routing: &'a RoutingConfig,
+    pub observatory: &'a ObservatoryConfig,
+    /// `None` leaves any hand-written `dns` block alone.
+    pub dns: Option<&'a DnsConfig>,
+    /// Empty leaves hand-written inbounds as the only ones.
+    pub inbounds: &'a [InboundConfig],
+    pub tproxy: Option<&'a TproxyConfig>,
+    /// `None` generates no stats API.
+    pub stats: Option<&'a StatsConfig>,
+}
+
+pub struct GroupBalancer {
+    pub name: String,
+    /// Member outbounds; their tags are overwritten.
+    pub outbounds: Vec<Value>,
+    /// xray `strategy.type`, e.g. `leastPing`.
+    pub strategy: &'static str,
+}
+
+/// Returns `base` (the current `xray.json`) with whatever a previous
+/// generation wrote replaced by `generation`. Everything else the user wrote
+/// is preserved.
+pub fn generate(base: &str, generation: Generation) -> Result<String, String> {
+    let mut config: Value = if base.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(base).map_err(|e| format!("xray.json is not valid JSON: {}", e))?
+    };
+
+    let root = config
+        .as_object_mut()
+        .ok_or("xray.json must contain a JSON object")?;
+
+    remove_generated(root)?;
+    let kdl_rules = generation.routing.all_rules();
+
+    let mut inbounds: Vec<Value> = generation.inbounds.iter().map(inbound).collect();
+    if let Some(tproxy) = generation.tproxy {
+        let fake_dns = generation.dns.is_some_and(|dns| dns.fake_dns.is_some());
+        inbounds.push(tproxy_inbound(tproxy, fake_dns));
+    }
+    if let Some(stats) = generation.stats {
+        inbounds.push(json!({
+            "tag": API_INBOUND_TAG,
+            "listen": "127.0.0.1",
+            "port": stats.api_port,
+            "protocol": "dokodemo-door",
+            "settings": { "address": "127.0.0.1" },
+        }));
+        enable_stats(root)?;
+    }
+    if !inbounds.is_empty() {
+        root.entry("inbounds")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .ok_or("\"inbounds\" must be an array")?
+            .splice(0..0, inbounds);
+    }
+
+    let outbounds = root
+        .entry("outbounds")
+        .or_insert_with(|| json!([{ "tag": DIRECT_TAG, "protocol": "freedom" }]))
+        .as_array_mut()
+        .ok_or("\"outbounds\" must be an array")?;
+
+    if generation.active_group.is_some() {
+        outbounds.retain(|o| tag_of(o) != Some(PROXY_TAG));
+    } else if let Some(outbound) = generation.proxy {
+        match outbounds.iter_mut().find(|o| tag_of(o) == Some(PROXY_TAG)) {
+            Some(existing) => *existing = outbound,
+            // xray routes unmatched traffic through the first outbound.
+            None => outbounds.insert(0, outbound),
+        }
+    }
+
+    outbounds.extend(generation.dialers);
+
+    if generation.dns.is_some() && !outbounds.iter().any(|o| tag_of(o) == Some(DNS_OUT_TAG)) {
+        outbounds.push(json!({ "tag": DNS_OUT_TAG, "protocol": "dns" }));
+    }
+
+    for rule in &kdl_rules {
+        let (tag, protocol) = match rule.target {
+            RuleTarget::Direct => (DIRECT_TAG, "freedom"),
+            RuleTarget::Block => (BLOCK_TAG, "blackhole"),
+            _ => continue,
+        };
+        if !outbounds.iter().any(|o| tag_of(o) == Some(tag)) {
+            outbounds.push(json!({ "tag": tag, "protocol": protocol }));
+        }
+    }
+
+    // Appended, so the first outbound (xray's default route) stays as it was.
+    outbounds.extend(generation.balancers.iter().flat_map(|b| {
+        let selector = balancer_selector(&b.name);
+        b.outbounds.iter().enumerate().map(move |(n, outbound)| {
+            let mut outbound = outbound.clone();
+            outbound["tag"] = json!(format!("{}{}", selector, n));
+            outbound
+        })
+    }));
+
+    let routing = root
+        .entry("routing")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or("\"routing\" must be an object")?;
+
+    if generation.routing.domain_strategy != Default::default() {
+        routing.insert(
+            "domainStrategy".to_string(),
+            json!(generation.routing.domain_strategy.as_xray()),
+        );
+    }
+
+    let mut selectors = Vec::new();
+    if !generation.balancers.is_empty() {
+        let balancers = routing
+            .entry("balancers")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .ok_or("\"routing.balancers\" must be an array")?;
+        for balancer in &generation.balancers {
+            let selector = balancer_selector(&balancer.name);
+            balancers.push(json!({
+                "tag": balancer_tag(&balancer.name),
+                "selector": [selector],
+                "strategy": { "type": balancer.strategy },
+            }));
+            selectors.push(selector);
+        }
+    }
+
+    let rules = routing
+        .entry("rules")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or("\"routing.rules\" must be an array")?;
+
+    // luxnulla.kdl rules and presets come first, then whatever the user
+    // wrote into xray.json by hand.
+    let mut generated = Vec::new();
+    if generation.stats.is_some() {
+        generated.push(json!({
+            "type": "field",
+            "ruleTag": format!("{}api", RULE_TAG_PREFIX),
+            "inboundTag": [API_INBOUND_TAG],
+            "outboundTag": API_TAG,
+        }));
+    }
+    if generation.dns.is_some() {
+        generated.push(json!({
+            "type": "field",
+            "ruleTag": format!("{}dns-out", RULE_TAG_PREFIX),
+            "network": "udp",
+            "port": "53",
+            "outboundTag": DNS_OUT_TAG,
+        }));
+    }
+    for (i, rule) in kdl_rules.iter().enumerate() {
+        let mut compiled = compile_rule(rule, generation.active_group.as_deref());
+        compiled["ruleTag"] = json!(format!("{}rule-{}", RULE_TAG_PREFIX, i));
+        generated.push(compiled);
+    }
+    // After the kdl rules, so a server can still be sent direct by address.
+    if generation.dns.is_some() {
+        let mut internal = json!({
+            "type": "field",
+            "ruleTag": format!("{}dns", RULE_TAG_PREFIX),
+            "inboundTag": [DNS_TAG],
+        });
+        set_target(
+            &mut internal,
+            &RuleTarget::Proxy,
+            generation.active_group.as_deref(),
+        );
+        generated.push(internal);
+    }
+    rules.splice(0..0, generated);
+
+    // Catch-all, so it goes last: anything no rule claimed is balanced.
+    if let Some(group) = &generation.active_group {
+        rules.push(json!({
+            "type": "field",
+            "ruleTag": format!("{}default", RULE_TAG_PREFIX),
+            "network": "tcp,udp",
+            "balancerTag": balancer_tag(group),
+        }));
+    }
+
+    if rules.is_empty() {
+        routing.remove("rules");
+    }
+    if routing.is_empty() {
+        root.remove("routing");
+    }
+
+    if let Some(dns) = generation.dns {
+        root.insert("dns".to_string(), dns_block(dns));
+        if let Some(pool) = &dns.fake_dns {
+            root.insert(
+                "fakedns".to_string(),
+                json!([{ "ipPool": pool.ip_pool, "poolSize": pool.pool_size }]),
+            );
+        }
+    }
+
+    // leastPing and leastLoad rank members by what the observatory measured;
+    // leastLoad only works with the burst observatory.
+    if !selectors.is_empty() {
+        let burst = generation.observatory.burst
+            || generation
+                .balancers
+                .iter()
+                .any(|b| b.strategy == "leastLoad");
+
+        if burst {
+            root.insert(
+                "burstObservatory".to_string(),
+                json!({
+                    "subjectSelector": selectors,
+                    "pingConfig": {
+                        "destination": generation.observatory.probe_url,
+                        "interval": generation.observatory.probe_interval,
+                        "sampling": 3,
+                        "timeout": "5s",
+                    },
+                }),
+            );
+        } else {
+            root.insert(
+                "observatory".to_string(),
+                json!({
+                    "subjectSelector": selectors,
+                    "probeUrl": generation.observatory.probe_url,
+                    "probeInterval": generation.observatory.probe_interval,
+                }),
+            );
+        }
+    }
+
+    // Without the mark, xray's own connections would be intercepted again.
+    if let Some(tproxy) = generation.tproxy
+        && let Some(outbounds) = root.get_mut("outbounds").and_then(Value::as_array_mut)
+    {
+        for outbound in outbounds {
+            let sockopt = &mut outbound["streamSettings"]["sockopt"];
+            if sockopt.get("mark").is_none() {
+                sockopt["mark"] = json!(tproxy.outbound_mark);
+            }
+        }
+    }
+
+    serde_json::to_string_pretty(&config).map_err(|e| e.to_string())
+}
+
+/// Whether `config` listens for redirected traffic on `port`.
+pub fn has_tproxy_inbound(config: &str, port: u16) -> bool {
+    serde_json::from_str::<Value>(config)
+        .ok()
+        .as_ref()
+        .and_then(Value::as_object)
+        .and_then(tproxy_inbound_port)
+        == Some(port as u64)
+}
+
+fn tproxy_inbound_port(root: &Map<String, Value>) -> Option<u64> {
+    generated_tproxy_inbound(root)?.get("port")?.as_u64()
+}
+
+/// The outbound mark the previous generation injected.
+fn generated_mark(root: &Map<String, Value>) -> Option<Value> {
+    generated_tproxy_inbound(root)?
+        .pointer("/streamSettings/sockopt/mark")
+        .cloned()
+}
+
+fn generated_tproxy_inbound(root: &Map<String, Value>) -> Option<&Value> {
+    root.get("inbounds")?
+        .as_array()?
+        .iter()
+        .find(|i| tag_of(i) == Some(TPROXY_INBOUND_TAG))
+}
+
+/// Drops `sockopt.mark` from outbounds where it equals `mark`, and the
+/// objects left empty.
+fn remove_outbound_marks(root: &mut Map<String, Value>, mark: &Value) {
+    let Some(outbounds) = root.get_mut("outbounds").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for outbound in outbounds.iter_mut().filter_map(Value::as_object_mut) {
+        let Some(stream) = outbound
+            .get_mut("streamSettings")
+            .and_then(Value::as_object_mut)
+        else {
+            continue;
+        };
+        if let Some(sockopt) = stream.get_mut("sockopt").and_then(Value::as_object_mut) {
+            if sockopt.get("mark") == Some(mark) {
+                sockopt.remove("mark");
+            }
+            if sockopt.is_empty() {
+                stream.remove("sockopt");
+            }
+        }
+        if stream.is_empty() {
+            outbound.remove("streamSettings");
+        }
+    }
+}
+
+/// Turns on xray's stats API and the per inbound and outbound traffic
+/// counters, keeping any other `policy` settings.
+fn enable_stats(root: &mut Map<String, Value>) -> Result<(), String> {
+    root.insert(
+        "api".to_string(),
+        json!({ "tag": API_TAG, "services": ["StatsService"] }),
+    );
+    root.entry("stats").or_insert_with(|| json!({}));
+
+    let system = root
+        .entry("policy")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or("\"policy\" must be an object")?
+        .entry("system")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or("\"policy.system\" must be an object")?;
+    for key in [
+        "statsInboundUplink",
+        "statsInboundDownlink",
+        "statsOutboundUplink",
+        "statsOutboundDownlink",
+    ] {
+        system.insert(key.to_string(), json!(true));
+    }
+    Ok(())
+}
+
+/// Address of the stats API in an `xray.json`, if it was generated with one.
+pub fn stats_api(config: &str) -> Option<String> {
+    let root: Value = serde_json::from_str(config).ok()?;
+    let port = root
+        .get("inbounds")?
+        .as_array()?
+        .iter()
+        .find(|inbound| tag_of(inbound) == Some(API_INBOUND_TAG))?
+        .get("port")?
+        .as_u64()?;
+    Some(format!("127.0.0.1:{}", port))
+}
+
+/// Links `hops` (entry first) through `sockopt.dialerProxy`. The exit hop is
+/// tagged `exit_tag` and comes last; the others are tagged after the chain.
+pub fn chain_outbounds(chain: &str, hops: Vec<Value>, exit_tag: &str) -> Vec<Value> {
+    let count = hops.len();
+    let mut previous: Option<String> = None;
+
+    hops.into_iter()
+        .enumerate()
+        .map(|(i, mut outbound)| {
+            let tag = if i + 1 == count {
+                exit_tag.to_string()
+            } else {
+                format!("{}{}:{}", CHAIN_TAG_PREFIX, chain, i)
+            };
+            outbound["tag"] = json!(tag);
+            if let Some(dialer) = previous.replace(tag) {
+                outbound["streamSettings"]["sockopt"]["dialerProxy"] = json!(dialer);
+            }
+            outbound
+        })
+        .collect()
+}
+
+/// Lists the routing rules of an `xray.json`, generated or hand-written.
+pub fn routing_info(config: &str) -> Result<RoutingInfo, String> {
+    let root: Value = serde_json::from_str(config).map_err(|e| e.to_string())?;
+    let routing = root.get("routing");
+
+    let rules = routing
+        .and_then(|r| r.get("rules"))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_object)
+        .map(|rule| {
+            let target = match (rule.get("outboundTag"), rule.get("balancerTag")) {
+                (Some(Value::String(tag)), _) => tag.clone(),
+                (_, Some(Value::String(tag))) => format!("balancer:{}", tag),
+                _ => String::from("?"),
+            };
+            let conditions = rule
+                .iter()
+                .filter(|(key, _)| {
+                    !matches!(
+                        key.as_str(),
+                        "ruleTag" | "outboundTag" | "balancerTag" | "type"
+                    )
+                })
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) => s.clone(),
+                        Value::Array(values) => values
+                            .iter()
+                            .map(|v| v.as_str().map_or_else(|| v.to_string(), String::from))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        other => other.to_string(),
+                    };
+                    format!("{}: {}", key, value)
+                })
+                .collect();
+
+            RuleInfo {
+                tag: rule
+                    .get("ruleTag")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                target,
+                conditions,
+            }
+        })
+        .collect();
+
+    Ok(RoutingInfo {
+        domain_strategy: routing
+            .and_then(|r| r.get("domainStrategy"))
+            .and_then(Value::as_str)
+            .map(String::from),
+        rules,
+    })
+}
+
+fn compile_rule(rule: &RoutingRule, active_group: Option<&str>) -> Value {
+    let mut compiled = json!({ "type": "field" });
+
+    let domains: Vec<String> = rule
+        .domains
+        .iter()
+        .cloned()
+        .chain(rule.geosites.iter().map(|g| format!("geosite:{}", g)))
+        .collect();
+    if !domains.is_empty() {
+        compiled["domain"] = json!(domains);
+    }
+
+    let ips: Vec<String> = rule
+        .ips
+        .iter()
+        .cloned()
+        .chain(rule.geoips.iter().map(|g| format!("geoip:{}", g)))
+        .collect();
+    if !ips.is_empty() {
+        compiled["ip"] = json!(ips);
+    }
+
+    if let Some(port) = &rule.port {
+        compiled["port"] = json!(port);
+    }
+    if !rule.processes.is_empty() {
+        compiled["process"] = json!(rule.processes);
+    }
+
+    set_target(&mut compiled, &rule.target, active_group);
+    compiled
+}
+
+fn set_target(rule: &mut Value, target: &RuleTarget, active_group: Option<&str>) {
+    match (target, active_group) {
+        (RuleTarget::Direct, _) => rule["outboundTag"] = json!(DIRECT_TAG),
+        (RuleTarget::Block, _) => rule["outboundTag"] = json!(BLOCK_TAG),
+        (RuleTarget::Proxy, None) => rule["outboundTag"] = json!(PROXY_TAG),
+        (RuleTarget::Proxy, Some(group)) => rule["balancerTag"] = json!(balancer_tag(group)),
+        (RuleTarget::Group(group), _) => rule["balancerTag"] = json!(balancer_tag(group)),
+    }
+}
+
+fn inbound(config: &InboundConfig) -> Value {
+    let accounts: Vec<Value> = config
+        .auth
+        .iter()
+        .map(|(user, pass)| json!({ "user": user, "pass": pass }))
+        .collect();
+
+    let (protocol, settings) = match config.protocol {
+        InboundProtocol::Http => ("http", json!({ "accounts": accounts })),
+        // xray's socks inbound answers plain HTTP proxy requests as well.
+        InboundProtocol::Socks | InboundProtocol::Mixed => (
+            "socks",
+            json!({
+                "auth": if accounts.is_empty() { "noauth" } else { "password" },
+                "accounts": accounts,
+                "udp": config.udp,
+            }),
+        ),
+    };
+
+    let mut inbound = json!({
+        "tag": format!("{}{}-{}", INBOUND_TAG_PREFIX, config.protocol.name(), config.port),
+        "listen": config.listen.to_string(),
+        "port": config.port,
+        "protocol": protocol,
+        "settings": settings,
+    });
+    if !config.sniffing.is_empty() {
+        inbound["sniffing"] = json!({
+            "enabled": true,
+            "destOverride": config.sniffing,
+        });
+    }
+    inbound
+}
+
+fn tproxy_inbound(tproxy: &TproxyConfig, fake_dns: bool) -> Value {
+    let mut sniffers = vec!["http", "tls", "quic"];
+    if fake_dns {
+        sniffers.push("fakedns");
+    }
+
+    json!({
+        "tag": TPROXY_INBOUND_TAG,
+        "port": tproxy.port,
+        "protocol": "dokodemo-door",
+        "settings": {
+            "network": match tproxy.mode {
+                TproxyMode::Tproxy => "tcp,udp",
+                TproxyMode::Redirect => "tcp",
+            },
+            "followRedirect": true,
+        },
+        // xray marks the UDP replies with it; the next generation also reads
+        // back which outbound marks were ours.
+        "streamSettings": {
+            "sockopt": { "tproxy": tproxy.mode.as_xray(), "mark": tproxy.outbound_mark },
+        },
+        "sniffing": { "enabled": true, "destOverride": sniffers },
+    })
+}
+
+fn dns_block(dns: &DnsConfig) -> Value {
+    let servers: Vec<Value> = dns
+        .servers
+        .iter()
+        .map(|server| {
+            if server.port.is_none()
+                && server.domains.is_empty()
+                && server.expect_ips.is_empty()
+                && !server.skip_fallback
+            {
+                return json!(server.address);
+            }
+
+            let mut object = json!({ "address": server.address });
+            if let Some(port) = server.port {
+                object["port"] = json!(port);
+            }
+            if !server.domains.is_empty() {
+                object["domains"] = json!(server.domains);
+            }
+            if !server.expect_ips.is_empty() {
+                object["expectIPs"] = json!(server.expect_ips);
+            }
+            if server.skip_fallback {
+                object["skipFallback"] = json!(true);
+            }
+            object
+        })
+        .collect();
+
+    let mut block = json!({
+        "tag": DNS_TAG,
+        "servers": servers,
+        "queryStrategy": dns.query_strategy.as_xray(),
+    });
+    if !dns.hosts.is_empty() {
+        block["hosts"] = dns
+            .hosts
+            .iter()
+            .map(|(domain, addresses)| {
+                let value = match addresses.as_slice() {
+                    [single] => json!(single),
+                    many => json!(many),
+                };
+                (domain.clone(), value)
+            })
+            .collect::<Map<_, _>>()
+            .into();
+    }
+    block
+}
+
+pub fn balancer_tag(group: &str) -> String {
+    format!("{}{}", GROUP_TAG_PREFIX, group)
+}
+
+fn balancer_selector(group: &str) -> String {
+    format!("{}:", balancer_tag(group))
+}
+
+fn tag_of(value: &Value) -> Option<&str> {
+    value.get("tag").and_then(Value::as_str)
+}
+
+/// Strips generated outbounds, balancers, rules, observatories and DNS.
+fn remove_generated(root: &mut Map<String, Value>) -> Result<(), String> {
+    // Outbounds with a mark of their own were left alone when marking, so
+    // only the generated value is ours to remove.
+    if let Some(mark) = generated_mark(root) {
+        remove_outbound_marks(root, &mark);
+    }
+
+    let dns_is_ours = root
+        .get("dns")
+        .and_then(|dns| dns.get("tag"))
+        .and_then(Value::as_str)
+        == Some(DNS_TAG);
+    if root
+        .get("api")
+        .and_then(|api| api.get("tag"))
+        .and_then(Value::as_str)
+        == Some(API_TAG)
+    {
+        root.remove("api");
+        if root.get("stats") == Some(&json!({})) {
+            root.remove("stats");
+        }
+    }
+
+    if dns_is_ours {
+        root.remove("dns");
+        root.remove("fakedns");
+        if let Some(outbounds) = root.get_mut("outbounds").and_then(Value::as_array_mut) {
+            outbounds.retain(|o| tag_of(o) != Some(DNS_OUT_TAG));
+        }
+    }
+
+    let prefixed = |value: &Value, key: &str, prefix: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .is_some_and(|tag| tag.starts_with(prefix))
+    };
+
+    if let Some(outbounds) = root.get_mut("outbounds") {
+        outbounds
+            .as_array_mut()
+            .ok_or("\"outbounds\" must be an array")?
+            .retain(|o| {
+                !prefixed(o, "tag", GROUP_TAG_PREFIX) && !prefixed(o, "tag", CHAIN_TAG_PREFIX)
+            });
+    }
+
+    if let Some(inbounds) = root.get_mut("inbounds") {
+        inbounds
+            .as_array_mut()
+            .ok_or("\"inbounds\" must be an array")?
+            .retain(|i| !prefixed(i, "tag", INBOUND_TAG_PREFIX));
+    }
+
+    if let Some(routing) = root.get_mut("routing").and_then(Value::as_object_mut) {
+        if let Some(balancers) = routing.get_mut("balancers").and_then(Value::as_array_mut) {
+            balancers.retain(|b| !prefixed(b, "tag", GROUP_TAG_PREFIX));
+            if balancers.is_empty() {
+                routing.remove("balancers");
+            }
+        }
+        if let Some(rules) = routing.get_mut("rules").and_then(Value::as_array_mut) {
+            rules.retain(|r| {
+                !prefixed(r, "ruleTag", RULE_TAG_PREFIX)
+                    && !prefixed(r, "balancerTag", GROUP_TAG_PREFIX)
+            });
+        }
+    }
+
+    for key in ["observatory", "burstObservatory"] {
+        let ours = root
+            .get(key)
+            .and_then(|o| o.get("subjectSelector"))
+            .and_then(Value::as_array)
+            .is_some_and(|selectors| {
+                selectors
+                    .iter()
+                    .all(|s| s.as_str().is_some_and(|s| s.starts_with(GROUP_TAG_PREFIX)))
+            });
+        if ours {
+            root.remove(key);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use luxnulla::config::{LuxnullaConfig, ObservatoryConfig};
+
+    const BASE: &str = r#"{
+        "outbounds": [
+            { "tag": "proxy", "protocol": "vless", "streamSettings": { "network": "ws" } },
+            { "tag": "direct", "protocol": "freedom" },
+            { "tag": "marked", "protocol": "freedom", "streamSettings": { "sockopt": { "mark": 7 } } }
+        ]
+    }"#;
+
+    fn generate_with(base: &str, tproxy: Option<&TproxyConfig>) -> Value {
+        let routing = RoutingConfig::default();
+        let observatory = ObservatoryConfig::default();
+        let config = generate(
+            base,
+            Generation {
+                proxy: None,
+                dialers: Vec::new(),
+                active_group: None,
+                balancers: Vec::new(),
+                routing: &routing,
+                observatory: &observatory,
+                dns: None,
+                inbounds: &[],
+                tproxy,
+                stats: None,
+            },
+        )
+        .unwrap();
+        serde_json::from_str(&config).unwrap()
+    }
+
+    fn outbound<'a>(config: &'a Value, tag: &str) -> &'a Value {
+        config["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|o| tag_of(o) == Some(tag))
+            .unwrap()
+    }
+
+    #[test]
+    fn tproxy_marks_outbounds() {
+        let tproxy = TproxyConfig::default();
+        let config = generate_with(BASE, Some(&tproxy));
+        assert_eq!(
+            outbound(&config, "proxy")["streamSettings"]["sockopt"]["mark"],
+            json!(tproxy.outbound_mark)
+        );
+        assert_eq!(
+            outbound(&config, "proxy")["streamSettings"]["network"],
+            "ws"
+        );
+        assert_eq!(
+            outbound(&config, "direct")["streamSettings"]["sockopt"]["mark"],
+            json!(tproxy.outbound_mark)
+        );
+        assert!(has_tproxy_inbound(&config.to_string(), tproxy.port));
+        assert!(!has_tproxy_inbound(&config.to_string(), tproxy.port + 1));
+    }
+
+    #[test]
+    fn marks_go_with_the_tproxy_section() {
+        let tproxy = TproxyConfig::default();
+        let with = generate_with(BASE, Some(&tproxy)).to_string();
+        let without = generate_with(&with, None);
+
+        assert!(!has_tproxy_inbound(&without.to_string(), tproxy.port));
+        assert_eq!(
+            outbound(&without, "proxy")["streamSettings"],
+            json!({ "network": "ws" })
+        );
+        assert_eq!(
+            outbound(&without, "direct"),
+            &json!({ "tag": "direct", "protocol": "freedom" })
+        );
+    }
+
+    #[test]
+    fn changed_outbound_mark_replaces_the_old_one() {
+        let mut tproxy = TproxyConfig::default();
+        let first = generate_with(BASE, Some(&tproxy)).to_string();
+        tproxy.outbound_mark = 300;
+        let second = generate_with(&first, Some(&tproxy));
+        assert_eq!(
+            outbound(&second, "proxy")["streamSettings"]["sockopt"]["mark"],
+            json!(300)
+        );
+    }
+
+    #[test]
+    fn hand_written_marks_survive_tproxy() {
+        let tproxy = TproxyConfig::default();
+        let with = generate_with(BASE, Some(&tproxy));
+        assert_eq!(
+            outbound(&with, "marked")["streamSettings"]["sockopt"]["mark"],
+            json!(7)
+        );
+
+        let without = generate_with(&with.to_string(), None);
+        assert_eq!(
+            outbound(&without, "marked")["streamSettings"]["sockopt"]["mark"],
+            json!(7)
+        );
+        assert!(outbound(&without, "direct").get("streamSettings").is_none());
+    }
+
+    #[test]
+    fn hand_written_marks_stay_without_tproxy() {
+        let config = generate_with(BASE, None);
+        assert_eq!(
+            outbound(&config, "marked")["streamSettings"]["sockopt"]["mark"],
+            json!(7)
+        );
+        assert!(outbound(&config, "direct").get("streamSettings").is_none());
+    }
+
+    fn compiled_rules(routing: &str, active_group: Option<&str>) -> Vec<Value> {
+        let config = LuxnullaConfig::from_kdl(&format!(
+            "group \"europe\" {{ country \"DE\"; }}\n{}",
+            routing
+        ))
+        .unwrap();
+        config
+            .routing
+            .all_rules()
+            .iter()
+            .map(|rule| compile_rule(rule, active_group))
+            .collect()
+    }
+
+    #[test]
+    fn rules_compile_to_xray() {
+        let cases = [
+            (
+                r#"rule "direct" { domain "full:router.lan" "regexp:\\.local$"; geosite "private"; }"#,
+                json!({
+                    "type": "field",
+                    "domain": ["full:router.lan", "regexp:\\.local$", "geosite:private"],
+                    "outboundTag": "direct",
+                }),
+            ),
+            (
+                r#"rule "block" { ip "10.0.0.0/8"; geoip "!cn"; }"#,
+                json!({
+                    "type": "field",
+                    "ip": ["10.0.0.0/8", "geoip:!cn"],
+                    "outboundTag": "block",
+                }),
+            ),
+            (
+                r#"rule "proxy" { port "22" "8000-8999"; process "firefox"; }"#,
+                json!({
+                    "type": "field",
+                    "port": "22,8000-8999",
+                    "process": ["firefox"],
+                    "outboundTag": "proxy",
+                }),
+            ),
+            (
+                r#"rule "group" "europe" { geosite "netflix"; }"#,
+                json!({
+                    "type": "field",
+                    "domain": ["geosite:netflix"],
+                    "balancerTag": "group:europe",
+                }),
+            ),
+        ];
+
+        for (rule, expected) in cases {
+            let compiled = compiled_rules(&format!("routing {{ {} }}", rule), None);
+            assert_eq!(compiled, [expected], "{}", rule);
+        }
+    }
+
+    #[test]
+    fn proxy_rules_follow_the_active_group() {
+        let compiled = compiled_rules(
+            r#"routing { rule "proxy" { domain "a.com"; } }"#,
+            Some("europe"),
+        );
+        assert_eq!(compiled[0]["balancerTag"], "group:europe");
+        assert!(compiled[0].get("outboundTag").is_none());
+    }
+
+    #[test]
+    fn presets_compile_after_rules() {
+        let compiled = compiled_rules(
+            r#"routing {
+                preset "bypass-lan"
+                preset "bypass-country" "cn"
+                preset "block-ads"
+                preset "global"
+                rule "proxy" { domain "a.com"; }
+            }"#,
+            None,
+        );
+        let expected = [
+            json!({ "type": "field", "domain": ["a.com"], "outboundTag": "proxy" }),
+            json!({ "type": "field", "domain": ["geosite:private"], "outboundTag": "direct" }),
+            json!({ "type": "field", "ip": ["geoip:private"], "outboundTag": "direct" }),
+            json!({ "type": "field", "domain": ["geosite:cn"], "outboundTag": "direct" }),
+            json!({ "type": "field", "ip": ["geoip:cn"], "outboundTag": "direct" }),
+            json!({ "type": "field", "domain": ["geosite:category-ads-all"], "outboundTag": "block" }),
+        ];
+        assert_eq!(compiled, expected);
+    }
+
+    /// Generates from the sections of a parsed luxnulla.kdl.
+    fn generate_config(base: &str, config: &LuxnullaConfig) -> Value {
+        let generated = generate(
+            base,
+            Generation {
+                proxy: None,
+                dialers: Vec::new(),
+                active_group: None,
+                balancers: Vec::new(),
+                routing: &config.routing,
+                observatory: &config.observatory,
+                dns: config.dns.as_ref(),
+                inbounds: &config.inbounds,
+                tproxy: config.tproxy.as_ref(),
+                stats: config.stats.enabled.then_some(&config.stats),
+            },
+        )
+        .unwrap();
+        serde_json::from_str(&generated).unwrap()
+    }
+
+    #[test]
+    fn dns_block_and_fakedns() {
+        let config = LuxnullaConfig::from_kdl(
+            r#"dns query-strategy="UseIPv4" {
+                server "fakedns" { domain "geosite:netflix"; }
+                server "https://1.1.1.1/dns-query"
+                server "223.5.5.5" port=53 {
+                    domain "geosite:cn"
+                    expect-ip "geoip:cn"
+                    skip-fallback true
+                }
+                fakedns { pool "198.18.0.0/16"; size 4096; }
+                host "router.lan" "192.168.1.1"
+                host "dual.lan" "10.0.0.1" "fd00::1"
+            }
+            tproxy"#,
+        )
+        .unwrap();
+        let generated = generate_config(BASE, &config);
+
+        assert_eq!(
+            generated["dns"],
+            json!({
+                "tag": "luxnulla:dns",
+                "servers": [
+                    { "address": "fakedns", "domains": ["geosite:netflix"] },
+                    "https://1.1.1.1/dns-query",
+                    {
+                        "address": "223.5.5.5",
+                        "port": 53,
+                        "domains": ["geosite:cn"],
+                        "expectIPs": ["geoip:cn"],
+                        "skipFallback": true,
+                    },
+                ],
+                "queryStrategy": "UseIPv4",
+                "hosts": {
+                    "router.lan": "192.168.1.1",
+                    "dual.lan": ["10.0.0.1", "fd00::1"],
+                },
+            })
+        );
+        assert_eq!(
+            generated["fakedns"],
+            json!([{ "ipPool": "198.18.0.0/16", "poolSize": 4096 }])
+        );
+        assert_eq!(outbound(&generated, DNS_OUT_TAG)["protocol"], "dns");
+
+        let tproxy = generated["inbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|i| tag_of(i) == Some(TPROXY_INBOUND_TAG))
+            .unwrap();
+        assert_eq!(
+            tproxy["sniffing"]["destOverride"],
+            json!(["http", "tls", "quic", "fakedns"])
+        );
+
+        // Port 53 goes to the DNS outbound, xray's own queries to the proxy.
+        let rule = |tag: &str| {
+            generated["routing"]["rules"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|r| r["ruleTag"] == tag)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(rule("luxnulla:dns-out")["outboundTag"], DNS_OUT_TAG);
+        assert_eq!(rule("luxnulla:dns")["inboundTag"], json!([DNS_TAG]));
+        assert_eq!(rule("luxnulla:dns")["outboundTag"], PROXY_TAG);
+
+        // Everything goes again once the section does.
+        let without = LuxnullaConfig::from_kdl("").unwrap();
+        let regenerated = generate_config(&generated.to_string(), &without);
+        assert!(regenerated.get("dns").is_none());
+        assert!(regenerated.get("fakedns").is_none());
+        assert!(
+            !regenerated.to_string().contains("luxnulla:dns"),
+            "{}",
+            regenerated
+        );
+        assert!(
+            !regenerated["outbounds"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|o| tag_of(o) == Some(DNS_OUT_TAG))
+        );
+    }
+
+    #[test]
+    fn hand_written_dns_is_kept() {
+        let base = r#"{ "dns": { "servers": ["8.8.8.8"] }, "outbounds": [] }"#;
+        let config = LuxnullaConfig::from_kdl("").unwrap();
+        assert_eq!(
+            generate_config(base, &config)["dns"],
+            json!({ "servers": ["8.8.8.8"] })
+        );
+    }
+
+    #[test]
+    fn inbounds_with_listen_and_auth() {
+        let config = LuxnullaConfig::from_kdl(
+            r#"inbound "mixed" port=7890 {
+                listen "0.0.0.0"
+                user "me" "secret"
+                udp true
+                sniffing "http" "tls"
+            }
+            inbound "http" port=8080 { user "me" "secret"; }
+            inbound "socks" port=1080
+            stats { enabled false; }"#,
+        )
+        .unwrap();
+        let generated = generate_config(BASE, &config);
+
+        assert_eq!(
+            generated["inbounds"],
+            json!([
+                {
+                    "tag": "in:mixed-7890",
+                    "listen": "0.0.0.0",
+                    "port": 7890,
+                    "protocol": "socks",
+                    "settings": {
+                        "auth": "password",
+                        "accounts": [{ "user": "me", "pass": "secret" }],
+                        "udp": true,
+                    },
+                    "sniffing": { "enabled": true, "destOverride": ["http", "tls"] },
+                },
+                {
+                    "tag": "in:http-8080",
+                    "listen": "127.0.0.1",
+                    "port": 8080,
+                    "protocol": "http",
+                    "settings": { "accounts": [{ "user": "me", "pass": "secret" }] },
+                },
+                {
+                    "tag": "in:socks-1080",
+                    "listen": "127.0.0.1",
+                    "port": 1080,
+                    "protocol": "socks",
+                    "settings": { "auth": "noauth", "accounts": [], "udp": false },
+                },
+            ])
+        );
+
+        // Hand-written inbounds stay behind the generated ones.
+        let base = r#"{ "inbounds": [{ "tag": "mine", "port": 9999, "protocol": "socks" }] }"#;
+        let generated = generate_config(base, &config);
+        let tags: Vec<_> = generated["inbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(tag_of)
+            .collect();
+        assert_eq!(
+            tags,
+            ["in:mixed-7890", "in:http-8080", "in:socks-1080", "mine"]
+        );
+    }
+
+    fn balancer(name: &str, strategy: &'static str, members: &[&str]) -> GroupBalancer {
+        GroupBalancer {
+            name: name.to_string(),
+            outbounds: members
+                .iter()
+                .map(|address| {
+                    json!({
+                        "tag": PROXY_TAG,
+                        "protocol": "vless",
+                        "settings": { "vnext": [{ "address": address }] },
+                    })
+                })
+                .collect(),
+            strategy,
+        }
+    }
+
+    fn generate_groups(
+        base: &str,
+        active_group: Option<&str>,
+        balancers: Vec<GroupBalancer>,
+        observatory: &ObservatoryConfig,
+    ) -> Value {
+        let routing = RoutingConfig::default();
+        let generated = generate(
+            base,
+            Generation {
+                proxy: None,
+                dialers: Vec::new(),
+                active_group: active_group.map(String::from),
+                balancers,
+                routing: &routing,
+                observatory,
+                dns: None,
+                inbounds: &[],
+                tproxy: None,
+                stats: None,
+            },
+        )
+        .unwrap();
+        serde_json::from_str(&generated).unwrap()
+    }
+
+    #[test]
+    fn active_group_replaces_the_proxy_outbound() {
+        let observatory = ObservatoryConfig::default();
+        let generated = generate_groups(
+            BASE,
+            Some("europe"),
+            vec![
+                balancer("europe", "leastPing", &["de.example.com", "nl.example.com"]),
+                balancer("asia", "random", &["jp.example.com"]),
+            ],
+            &observatory,
+        );
+
+        let tags: Vec<_> = generated["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(tag_of)
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                "direct",
+                "marked",
+                "group:europe:0",
+                "group:europe:1",
+                "group:asia:0"
+            ]
+        );
+        assert_eq!(
+            outbound(&generated, "group:europe:1")["settings"]["vnext"][0]["address"],
+            "nl.example.com"
+        );
+
+        assert_eq!(
+            generated["routing"]["balancers"],
+            json!([
+                {
+                    "tag": "group:europe",
+                    "selector": ["group:europe:"],
+                    "strategy": { "type": "leastPing" },
+                },
+                {
+                    "tag": "group:asia",
+                    "selector": ["group:asia:"],
+                    "strategy": { "type": "random" },
+                },
+            ])
+        );
+        assert_eq!(
+            generated["observatory"],
+            json!({
+                "subjectSelector": ["group:europe:", "group:asia:"],
+                "probeUrl": observatory.probe_url,
+                "probeInterval": observatory.probe_interval,
+            })
+        );
+        assert!(generated.get("burstObservatory").is_none());
+
+        // Unmatched traffic is balanced, after every other rule.
+        let rules = generated["routing"]["rules"].as_array().unwrap();
+        assert_eq!(
+            rules.last().unwrap(),
+            &json!({
+                "type": "field",
+                "ruleTag": "luxnulla:default",
+                "network": "tcp,udp",
+                "balancerTag": "group:europe",
+            })
+        );
+    }
+
+    #[test]
+    fn least_load_needs_the_burst_observatory() {
+        let observatory = ObservatoryConfig::default();
+        let generated = generate_groups(
+            BASE,
+            None,
+            vec![balancer("europe", "leastLoad", &["de.example.com"])],
+            &observatory,
+        );
+        assert!(generated.get("observatory").is_none());
+        assert_eq!(
+            generated["burstObservatory"]["subjectSelector"],
+            json!(["group:europe:"])
+        );
+        assert_eq!(
+            generated["burstObservatory"]["pingConfig"]["destination"],
+            json!(observatory.probe_url)
+        );
+        // Without an active group the selected node keeps the proxy outbound.
+        assert_eq!(outbound(&generated, PROXY_TAG)["protocol"], "vless");
+    }
+
+    #[test]
+    fn groups_go_on_regeneration() {
+        let observatory = ObservatoryConfig::default();
+        let with = generate_groups(
+            BASE,
+            Some("europe"),
+            vec![balancer("europe", "leastPing", &["de.example.com"])],
+            &observatory,
+        );
+        let without = generate_groups(&with.to_string(), None, Vec::new(), &observatory);
+
+        assert!(without.get("observatory").is_none());
+        assert!(without.get("routing").is_none());
+        assert!(
+            !without["outbounds"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|o| tag_of(o).is_some_and(|t| t.starts_with(GROUP_TAG_PREFIX)))
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use luxnulla::{LogLevel, LogLine, LogStream};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    sync::broadcast,
+};
+
+const RING_CAPACITY: usize = 2000;
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_ROTATED_FILES: usize = 3;
+
+/// Keeps the latest xray output in memory and mirrors it into a rotating
+/// log file in the config dir.
+pub struct XrayLogs {
+    ring: Mutex<VecDeque<LogLine>>,
+    file: Mutex<RotatingFile>,
+    tx: broadcast::Sender<LogLine>,
+}
+
+impl XrayLogs {
+    pub fn new(path: PathBuf) -> Self {
+        let (tx, _) = broadcast::channel(256);
+
+        Self {
+            ring: Mutex::new(VecDeque::with_capacity(RING_CAPACITY)),
+            file: Mutex::new(RotatingFile::new(path)),
+            tx,
+        }
+    }
+
+    pub fn push(&self, stream: LogStream, line: String) {
+        let level = LogLevel::from_xray_line(&line).unwrap_or(match stream {
+            LogStream::Stdout => LogLevel::Info,
+            LogStream::Stderr => LogLevel::Error,
+        });
+        let entry = LogLine {
+            stream,
+            level,
+            line,
+        };
+
+        if let Err(e) = self.file.lock().unwrap().write_line(&entry.line) {
+            eprintln!("failed to write xray log: {}", e);
+        }
+
+        {
+            let mut ring = self.ring.lock().unwrap();
+            if ring.len() == RING_CAPACITY {
+                ring.pop_front();
+            }
+            ring.push_back(entry.clone());
+        }
+
+        let _ = self.tx.send(entry);
+    }
+
+    /// Returns up to `lines` of the most recent entries at or above `level`.
+    pub fn tail(&self, lines: usize, level: Option<LogLevel>) -> Vec<LogLine> {
+        let ring = self.ring.lock().unwrap();
+        let mut out: Vec<LogLine> = ring
+            .iter()
+            .rev()
+            .filter(|l| level.is_none_or(|level| l.level >= level))
+            .take(lines)
+            .cloned()
+            .collect();
+        out.reverse();
+        out
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> {
+        self.tx.subscribe()
+    }
+
+    /// Reads `reader` line by line until EOF, recording every line.
+    pub async fn capture<R>(&self, stream: LogStream, reader: R)
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => self.push(stream, line),
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to read xray output: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size >= MAX_FILE_SIZE {
+            self.rotate()?;
+        }
+
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        writeln!(file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+
+    /// Shifts `xray.log` -> `xray.log.1` -> ... dropping the oldest file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        self.size = 0;
+
+        for i in (1..MAX_ROTATED_FILES).rev() {
+            let from = self.rotated_path(i);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(i + 1))?;
+            }
+        }
+
+        if self.path.exists() {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+}
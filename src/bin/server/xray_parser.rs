@@ -1,21 +1,20 @@
+use base64::{Engine as _, engine::general_purpose};
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value, json};
 use std::collections::HashMap;
 use url::Url;
 
 #[derive(Debug)]
-enum ParseError {
+pub enum ParseError {
     FieldMissing(String),
-    UnknownFieldType { current: String, expected: String },
+    Malformed(String),
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ParseError::FieldMissing(field) => write!(f, "Missing field: {}", field),
-            ParseError::UnknownFieldType { current, expected } => write!(
-                f,
-                "Unknown field type: {} (expected: {})",
-                current, expected
-            ),
+            ParseError::Malformed(reason) => write!(f, "Malformed link: {}", reason),
         }
     }
 }
@@ -30,7 +29,7 @@ where
 }
 
 #[derive(Debug)]
-enum ProxyConfig {
+pub enum ProxyConfig {
     Vmess(Vmess),
     Vless(Vless),
     Shadowsocks(Shadowsocks),
@@ -38,17 +37,35 @@ enum ProxyConfig {
 }
 
 impl ProxyConfig {
+    /// User id of the protocols that have one; trojan and shadowsocks
+    /// authenticate with a password instead.
     fn id(&self) -> Option<&str> {
-        // piski
         match self {
             ProxyConfig::Vless(value) => Some(&value.id),
             ProxyConfig::Vmess(value) => Some(&value.id),
-            ProxyConfig::Trojan(value) => Some(&value.id),
-            ProxyConfig::Shadowsocks(_) => None,
+            ProxyConfig::Trojan(_) | ProxyConfig::Shadowsocks(_) => None,
+        }
+    }
+
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            ProxyConfig::Vless(_) => "vless",
+            ProxyConfig::Vmess(_) => "vmess",
+            ProxyConfig::Trojan(_) => "trojan",
+            ProxyConfig::Shadowsocks(_) => "shadowsocks",
         }
     }
 
-    fn address(&self) -> &str {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ProxyConfig::Vless(value) => value.name.as_deref(),
+            ProxyConfig::Vmess(value) => value.name.as_deref(),
+            ProxyConfig::Trojan(value) => value.name.as_deref(),
+            ProxyConfig::Shadowsocks(value) => value.name.as_deref(),
+        }
+    }
+
+    pub fn address(&self) -> &str {
         match self {
             ProxyConfig::Vless(value) => &value.address,
             ProxyConfig::Vmess(value) => &value.address,
@@ -57,7 +74,7 @@ impl ProxyConfig {
         }
     }
 
-    fn port(&self) -> u16 {
+    pub fn port(&self) -> u16 {
         match self {
             ProxyConfig::Vless(value) => value.port,
             ProxyConfig::Vmess(value) => value.port,
@@ -65,10 +82,408 @@ impl ProxyConfig {
             ProxyConfig::Shadowsocks(value) => value.port,
         }
     }
+
+    /// SNI for nodes that wrap traffic in TLS or REALITY, `None` otherwise.
+    pub fn tls_server_name(&self) -> Option<String> {
+        let (sni, host, address) = match self {
+            ProxyConfig::Vless(value) => {
+                if !matches!(value.security.as_deref(), Some("tls") | Some("reality")) {
+                    return None;
+                }
+                (value.extras.get("sni"), value.host.as_ref(), &value.address)
+            }
+            ProxyConfig::Vmess(value) => {
+                if !value.tls {
+                    return None;
+                }
+                (value.extras.get("sni"), value.host.as_ref(), &value.address)
+            }
+            ProxyConfig::Trojan(value) => (value.sni.as_ref(), value.host.as_ref(), &value.address),
+            ProxyConfig::Shadowsocks(_) => return None,
+        };
+
+        Some(sni.or(host).unwrap_or(address).clone())
+    }
+
+    /// Stable identity of the node derived from everything that decides
+    /// where and how traffic is sent (but not the display name), so it
+    /// survives renames and reordering within subscriptions.
+    pub fn fingerprint(&self) -> String {
+        let mut key = format!(
+            "{}|{}|{}|{}",
+            self.protocol(),
+            self.address().to_lowercase(),
+            self.port(),
+            self.id().unwrap_or_default()
+        );
+
+        match self {
+            ProxyConfig::Vless(value) => {
+                key.push_str(&format!(
+                    "|{}|{}|{}|{}|{}",
+                    value.network,
+                    value.security.as_deref().unwrap_or_default(),
+                    value.path.as_deref().unwrap_or_default(),
+                    value.host.as_deref().unwrap_or_default(),
+                    transport_key(&value.extras)
+                ));
+            }
+            ProxyConfig::Vmess(value) => {
+                key.push_str(&format!(
+                    "|{}|{}|{}|{}|{}",
+                    value.network,
+                    value.tls,
+                    value.path.as_deref().unwrap_or_default(),
+                    value.host.as_deref().unwrap_or_default(),
+                    transport_key(&value.extras)
+                ));
+            }
+            ProxyConfig::Trojan(value) => {
+                key.push_str(&format!(
+                    "|{}|{}|{}|{}",
+                    value.password,
+                    value.sni.as_deref().unwrap_or_default(),
+                    value.ws_path.as_deref().unwrap_or_default(),
+                    value.host.as_deref().unwrap_or_default()
+                ));
+            }
+            ProxyConfig::Shadowsocks(value) => {
+                key.push_str(&format!("|{}|{}", value.method, value.password));
+            }
+        }
+
+        format!("{:016x}", fnv1a(key.as_bytes()))
+    }
+
+    /// Every parsed field as label and value for display. Well-known extras
+    /// get readable labels; the rest follow under their query key.
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let optional = |v: &Option<String>| v.clone().unwrap_or_else(|| String::from("-"));
+
+        let (mut fields, extras, shown): (Vec<(&str, String)>, _, &[&str]) = match self {
+            ProxyConfig::Vless(value) => (
+                vec![
+                    ("id", value.id.clone()),
+                    ("address", value.address.clone()),
+                    ("port", value.port.to_string()),
+                    ("transport", value.network.clone()),
+                    ("security", optional(&value.security)),
+                    ("encryption", optional(&value.encryption)),
+                    ("path", optional(&value.path)),
+                    ("host", optional(&value.host)),
+                ],
+                &value.extras,
+                &["type", "path", "host"],
+            ),
+            ProxyConfig::Vmess(value) => (
+                vec![
+                    ("id", value.id.clone()),
+                    ("address", value.address.clone()),
+                    ("port", value.port.to_string()),
+                    ("alter id", value.aid.to_string()),
+                    ("transport", value.network.clone()),
+                    ("header type", optional(&value.type_field)),
+                    (
+                        "security",
+                        String::from(if value.tls { "tls" } else { "none" }),
+                    ),
+                    ("path", optional(&value.path)),
+                    ("host", optional(&value.host)),
+                ],
+                &value.extras,
+                &[],
+            ),
+            ProxyConfig::Trojan(value) => (
+                vec![
+                    ("password", value.password.clone()),
+                    ("address", value.address.clone()),
+                    ("port", value.port.to_string()),
+                    ("SNI", optional(&value.sni)),
+                    ("ws path", optional(&value.ws_path)),
+                    ("host", optional(&value.host)),
+                    ("allow insecure", value.allow_insecure.to_string()),
+                ],
+                &value.extras,
+                &["sni", "path", "host", "allowInsecure"],
+            ),
+            ProxyConfig::Shadowsocks(value) => (
+                vec![
+                    ("method", value.method.clone()),
+                    ("password", value.password.clone()),
+                    ("address", value.address.clone()),
+                    ("port", value.port.to_string()),
+                ],
+                &value.extras,
+                &[],
+            ),
+        };
+        fields.insert(0, ("protocol", self.protocol().to_string()));
+        fields.push(("name", self.name().unwrap_or_default().to_string()));
+
+        const KNOWN_EXTRAS: [(&str, &str); 11] = [
+            ("sni", "SNI"),
+            ("alpn", "ALPN"),
+            ("fp", "TLS fingerprint"),
+            ("allowInsecure", "allow insecure"),
+            ("pbk", "REALITY public key"),
+            ("sid", "REALITY short id"),
+            ("spx", "REALITY spider x"),
+            ("flow", "flow"),
+            ("serviceName", "gRPC service"),
+            ("headerType", "header type"),
+            ("mode", "mode"),
+        ];
+
+        let mut fields: Vec<(String, String)> = fields
+            .into_iter()
+            .map(|(label, value)| (label.to_string(), value))
+            .collect();
+        for (key, label) in KNOWN_EXTRAS {
+            if !shown.contains(&key)
+                && let Some(value) = extras.get(key)
+            {
+                fields.push((label.to_string(), value.clone()));
+            }
+        }
+
+        let mut rest: Vec<_> = extras
+            .iter()
+            .filter(|(key, _)| {
+                !shown.contains(&key.as_str()) && !KNOWN_EXTRAS.iter().any(|(k, _)| k == key)
+            })
+            .collect();
+        rest.sort();
+        fields.extend(rest.into_iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        fields
+    }
+
+    /// Builds the xray outbound object for this node.
+    pub fn to_outbound(&self, tag: &str) -> Value {
+        match self {
+            ProxyConfig::Vless(value) => json!({
+                "tag": tag,
+                "protocol": "vless",
+                "settings": {
+                    "vnext": [{
+                        "address": value.address,
+                        "port": value.port,
+                        "users": [{
+                            "id": value.id,
+                            "encryption": value.encryption.as_deref().unwrap_or("none"),
+                            "flow": value.extras.get("flow").map(String::as_str).unwrap_or(""),
+                        }],
+                    }],
+                },
+                "streamSettings": stream_settings(
+                    &value.network,
+                    value.security.as_deref().unwrap_or("none"),
+                    value.path.as_deref(),
+                    value.host.as_deref(),
+                    &value.extras,
+                ),
+            }),
+            ProxyConfig::Vmess(value) => {
+                // v2rayN links keep the header type in `type` and the gRPC
+                // service name in `path`.
+                let mut extras = value.extras.clone();
+                if let Some(header) = &value.type_field {
+                    extras.insert("headerType".to_string(), header.clone());
+                }
+                if value.network == "grpc"
+                    && let Some(path) = &value.path
+                {
+                    extras
+                        .entry("serviceName".to_string())
+                        .or_insert_with(|| path.clone());
+                }
+
+                json!({
+                "tag": tag,
+                "protocol": "vmess",
+                "settings": {
+                    "vnext": [{
+                        "address": value.address,
+                        "port": value.port,
+                        "users": [{
+                            "id": value.id,
+                            "alterId": value.aid,
+                            "security": value.extras.get("scy").map(String::as_str).unwrap_or("auto"),
+                        }],
+                    }],
+                },
+                "streamSettings": stream_settings(
+                    &value.network,
+                    if value.tls { "tls" } else { "none" },
+                    value.path.as_deref(),
+                    value.host.as_deref(),
+                    &extras,
+                ),
+                })
+            }
+            ProxyConfig::Trojan(value) => {
+                let mut extras = value.extras.clone();
+                if let Some(sni) = &value.sni {
+                    extras.insert("sni".to_string(), sni.clone());
+                }
+                if value.allow_insecure {
+                    extras.insert("allowInsecure".to_string(), "1".to_string());
+                }
+
+                json!({
+                    "tag": tag,
+                    "protocol": "trojan",
+                    "settings": {
+                        "servers": [{
+                            "address": value.address,
+                            "port": value.port,
+                            "password": value.password,
+                        }],
+                    },
+                    "streamSettings": stream_settings(
+                        if value.ws_path.is_some() { "ws" } else { "tcp" },
+                        "tls",
+                        value.ws_path.as_deref(),
+                        value.host.as_deref(),
+                        &extras,
+                    ),
+                })
+            }
+            ProxyConfig::Shadowsocks(value) => json!({
+                "tag": tag,
+                "protocol": "shadowsocks",
+                "settings": {
+                    "servers": [{
+                        "address": value.address,
+                        "port": value.port,
+                        "method": value.method,
+                        "password": value.password,
+                    }],
+                },
+            }),
+        }
+    }
+}
+
+/// Transport parameters that live in `extras` but still identify the node.
+fn transport_key(extras: &HashMap<String, String>) -> String {
+    [
+        "sni",
+        "pbk",
+        "sid",
+        "serviceName",
+        "flow",
+        "headerType",
+        "mode",
+    ]
+    .iter()
+    .map(|k| extras.get(*k).map(String::as_str).unwrap_or_default())
+    .collect::<Vec<_>>()
+    .join("|")
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` its output is stable across Rust
+/// releases, which matters because fingerprints are persisted.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn stream_settings(
+    network: &str,
+    security: &str,
+    path: Option<&str>,
+    host: Option<&str>,
+    extras: &HashMap<String, String>,
+) -> Value {
+    let mut stream = Map::new();
+    stream.insert("network".into(), json!(network));
+    stream.insert("security".into(), json!(security));
+
+    let sni = extras.get("sni").map(String::as_str).or(host);
+    let fingerprint = extras.get("fp").map(String::as_str).unwrap_or("chrome");
+
+    match security {
+        "tls" => {
+            let mut tls = json!({
+                "serverName": sni.unwrap_or(""),
+                "fingerprint": fingerprint,
+                "allowInsecure": extras.get("allowInsecure").is_some_and(|v| v == "1"),
+            });
+            if let Some(alpn) = extras.get("alpn") {
+                tls["alpn"] = json!(alpn.split(',').collect::<Vec<_>>());
+            }
+            stream.insert("tlsSettings".into(), tls);
+        }
+        "reality" => {
+            stream.insert(
+                "realitySettings".into(),
+                json!({
+                    "serverName": sni.unwrap_or(""),
+                    "fingerprint": fingerprint,
+                    "publicKey": extras.get("pbk").map(String::as_str).unwrap_or(""),
+                    "shortId": extras.get("sid").map(String::as_str).unwrap_or(""),
+                    "spiderX": extras.get("spx").map(String::as_str).unwrap_or(""),
+                }),
+            );
+        }
+        _ => {}
+    }
+
+    let path = path.unwrap_or("/");
+    match network {
+        "ws" => {
+            stream.insert(
+                "wsSettings".into(),
+                json!({ "path": path, "headers": { "Host": host.unwrap_or("") } }),
+            );
+        }
+        "grpc" => {
+            let service_name = extras.get("serviceName").map(String::as_str).unwrap_or("");
+            stream.insert(
+                "grpcSettings".into(),
+                json!({ "serviceName": service_name, "multiMode": extras.get("mode").is_some_and(|m| m == "multi") }),
+            );
+        }
+        "httpupgrade" => {
+            stream.insert(
+                "httpupgradeSettings".into(),
+                json!({ "path": path, "host": host.unwrap_or("") }),
+            );
+        }
+        "xhttp" | "splithttp" => {
+            stream.insert(
+                "xhttpSettings".into(),
+                json!({
+                    "path": path,
+                    "host": host.unwrap_or(""),
+                    "mode": extras.get("mode").map(String::as_str).unwrap_or("auto"),
+                }),
+            );
+        }
+        "tcp" if extras.get("headerType").is_some_and(|h| h == "http") => {
+            stream.insert(
+                "tcpSettings".into(),
+                json!({
+                    "header": {
+                        "type": "http",
+                        "request": { "path": [path], "headers": { "Host": [host.unwrap_or("")] } },
+                    },
+                }),
+            );
+        }
+        _ => {}
+    }
+
+    Value::Object(stream)
 }
 
 #[derive(Debug)]
-struct Vmess {
+pub struct Vmess {
     id: String,
     address: String,
     port: u16,
@@ -84,7 +499,7 @@ struct Vmess {
 }
 
 #[derive(Debug)]
-struct Vless {
+pub struct Vless {
     id: String,
     address: String,
     port: u16,
@@ -93,13 +508,12 @@ struct Vless {
     network: String,
     path: Option<String>,
     host: Option<String>,
-    tls: bool,
     name: Option<String>,
     extras: HashMap<String, String>,
 }
 
 #[derive(Debug)]
-struct Shadowsocks {
+pub struct Shadowsocks {
     method: String,
     password: String,
     address: String,
@@ -109,8 +523,7 @@ struct Shadowsocks {
 }
 
 #[derive(Debug)]
-struct Trojan {
-    id: String,
+pub struct Trojan {
     password: String,
     address: String,
     port: u16,
@@ -158,144 +571,512 @@ impl Parser for Vless {
             path: query.get("path").cloned(),
             host: query.get("host").cloned(),
 
-            name: url.fragment().map(|s| s.to_string()),
+            // Share links percent-encode the name, flag emoji included
+            name: url.fragment().map(percent_decode),
+            extras,
+        })
+    }
+}
+
+impl Vmess {
+    /// Parses the base64 JSON after `vmess://`, in the v2rayN format.
+    /// Numbers may come as JSON numbers or as strings.
+    fn decode(payload: &str) -> Result<Self, ParseError> {
+        let json = decode_base64(payload)
+            .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+            .and_then(|json| json.as_object().cloned())
+            .ok_or_else(|| ParseError::Malformed("vmess payload is not base64 JSON".to_string()))?;
+
+        let text = |key: &str| {
+            json.get(key)
+                .and_then(|value| match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .filter(|s| !s.is_empty())
+        };
+        let required = |key: &str, field: &str| {
+            text(key).ok_or_else(|| ParseError::FieldMissing(field.to_string()))
+        };
+
+        let port = required("port", "port")?
+            .parse()
+            .map_err(|_| ParseError::Malformed("vmess port is not a number".to_string()))?;
+
+        let mut extras = HashMap::new();
+        for key in json.keys() {
+            if [
+                "v", "add", "port", "id", "aid", "net", "type", "host", "path", "tls", "ps",
+            ]
+            .contains(&key.as_str())
+            {
+                continue;
+            }
+            if let Some(value) = text(key) {
+                extras.insert(key.clone(), value);
+            }
+        }
+
+        Ok(Vmess {
+            id: required("id", "id")?,
+            address: required("add", "address")?,
+            port,
+            aid: text("aid").and_then(|aid| aid.parse().ok()).unwrap_or(0),
+            network: text("net").unwrap_or_else(|| "tcp".to_string()),
+            type_field: text("type").filter(|t| t != "none"),
+            host: text("host"),
+            path: text("path"),
+            tls: text("tls").is_some_and(|tls| tls == "tls"),
+            name: text("ps"),
             extras,
+        })
+    }
+}
+
+impl Parser for Shadowsocks {
+    /// SIP002 links, with the user info either base64 or percent-encoded,
+    /// and the legacy form with everything but the name in base64.
+    fn parse(url: &Url) -> Result<Self, ParseError> {
+        let name = url.fragment().map(percent_decode);
+        let legacy;
+        let url = if url.username().is_empty() {
+            let decoded = url
+                .as_str()
+                .strip_prefix("ss://")
+                .and_then(|rest| rest.split(['#', '?']).next())
+                .and_then(decode_base64)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| {
+                    ParseError::Malformed("shadowsocks link is not base64".to_string())
+                })?;
+            legacy = Url::parse(&format!("ss://{}", decoded))
+                .map_err(|e| ParseError::Malformed(e.to_string()))?;
+            &legacy
+        } else {
+            url
+        };
 
-            tls: query.get("security").map(|s| s == "tls").unwrap_or(false),
+        let user = percent_decode(url.username());
+        let (method, password) = match url.password() {
+            Some(password) => (user, percent_decode(password)),
+            None => decode_base64(&user)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .and_then(|creds| {
+                    creds
+                        .split_once(':')
+                        .map(|(method, password)| (method.to_string(), password.to_string()))
+                })
+                .ok_or_else(|| ParseError::FieldMissing("method".to_string()))?,
+        };
+        if method.is_empty() {
+            return Err(ParseError::FieldMissing("method".to_string()));
+        }
+
+        Ok(Shadowsocks {
+            method,
+            password,
+            address: host(url)?,
+            port: url
+                .port()
+                .ok_or(ParseError::FieldMissing("port".to_string()))?,
+            name,
+            extras: url.query_pairs().into_owned().collect(),
         })
     }
 }
 
-// impl Parser for Vmess {
-//     fn parse(url: Url) -> std::io::Result<ProxyConfig> {
-//         let payload = &line[8..];
-//         let decoded = base64::decode(payload).ok();
-//         let json: serde_json::Value = serde_json::from_slice(&decoded).ok();
-//         let mut extras = HashMap::new();
-//         for (k, v) in json.as_object()?.iter() {
-//             if ["add", "port", "id", "aid", "net", "tls", "ps"].contains(&k.as_str()) {
-//                 continue;
-//             }
-//             extras.insert(k.clone(), v.as_str().unwrap_or_default().to_string());
-//         }
-//         ProxyConfig::Vmess(Vmess {
-//             address: json["add"].as_str()?.to_string(),
-//             port: json["port"].as_str()?.parse().ok()?,
-//             id: json["id"].as_str()?.to_string(),
-//             aid: json["aid"].as_str()?.parse().unwrap_or(0),
-//             network: json["net"].as_str()?.to_string(),
-//             type_field: json
-//                 .get("type")
-//                 .and_then(|v| v.as_str().map(|s| s.to_string())),
-//             host: json
-//                 .get("host")
-//                 .and_then(|v| v.as_str().map(|s| s.to_string())),
-//             path: json
-//                 .get("path")
-//                 .and_then(|v| v.as_str().map(|s| s.to_string())),
-//             tls: json["tls"].as_bool().unwrap_or(false),
-//             name: json
-//                 .get("ps")
-//                 .and_then(|v| v.as_str().map(|s| s.to_string())),
-//             extras,
-//         })
-//     }
-// }
-
-// impl Parser for Vless {
-//     fn parse(url: Url) -> std::io::Result<ProxyConfig> {
-//         let url = Url::parse(line).ok()?;
-//         let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
-//         let mut extras = query.clone();
-//         extras.remove("security");
-//         extras.remove("encryption");
-
-//         ProxyConfig::Vless(Vless {
-//             address: url.host_str()?.to_string(),
-//             port: url.port()?,
-//             id: url.username().to_string(),
-//             security: query.get("security").cloned(),
-//             encryption: query.get("encryption").cloned(),
-//             network: query.get("type").cloned().unwrap_or_else(|| "tcp".into()),
-//             path: query.get("path").cloned(),
-//             host: query.get("host").cloned(),
-//             tls: query.get("security").map(|s| s == "tls").unwrap_or(false),
-//             name: url.fragment().map(|s| s.to_string()),
-//             extras,
-//         })
-//     }
-// }
-
-// impl Parser for Shadowsocks {
-//     fn parse(url: Url) -> std::io::Result<ProxyConfig> {
-//         let after = &line[5..];
-//         let (creds, rest) = after.split_once('@')?;
-//         let (method, password) = creds.split_once(':')?;
-//         let (addrpart, namepart) = rest.split_once('#').unwrap_or((rest, ""));
-//         let (address, port) = addrpart.split_once(':')?;
-//         let port = port.parse().ok()?;
-//         let mut extras = HashMap::new();
-
-//         ProxyConfig::Shadowsocks(Shadowsocks {
-//             method: method.to_string(),
-//             password: password.to_string(),
-//             address: address.to_string(),
-//             port,
-//             name: if namepart.is_empty() {
-//                 None
-//             } else {
-//                 Some(namepart.to_string())
-//             },
-//             extras,
-//         })
-//     }
-// }
-
-// impl Parser for Trojan {
-//     fn parse(url: Url) -> Result<Self, ParseError> {
-//         let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
-//         let mut extras = query.clone();
-
-//         ProxyConfig::Trojan(Trojan {
-//             password: url.username().to_string(),
-//             address: url.host_str()?.to_string(),
-//             port: url.port()?,
-//             sni: query.get("sni").cloned(),
-//             ws_path: query.get("path").cloned(),
-//             host: query.get("host").cloned(),
-//             allow_insecure: query
-//                 .get("allowInsecure")
-//                 .map(|v| v == "1")
-//                 .unwrap_or(false),
-//             name: url.fragment().map(|s| s.to_string()),
-//             extras,
-//         })
-//     }
-// }
-
-fn parse_line(line: &str) -> Result<ProxyConfig, String> {
-    let url = Url::parse(line).unwrap();
+impl Parser for Trojan {
+    fn parse(url: &Url) -> Result<Self, ParseError> {
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        let mut extras = query.clone();
+        for key in ["sni", "path", "host", "allowInsecure"] {
+            extras.remove(key);
+        }
+
+        let password = percent_decode(url.username());
+        if password.is_empty() {
+            return Err(ParseError::FieldMissing("password".to_string()));
+        }
+
+        Ok(Trojan {
+            password,
+            address: host(url)?,
+            port: url
+                .port()
+                .ok_or(ParseError::FieldMissing("port".to_string()))?,
+            sni: query.get("sni").or(query.get("peer")).cloned(),
+            ws_path: query
+                .get("path")
+                .filter(|_| query.get("type").is_some_and(|t| t == "ws"))
+                .cloned(),
+            host: query.get("host").cloned(),
+            allow_insecure: query.get("allowInsecure").is_some_and(|v| v == "1"),
+            name: url.fragment().map(percent_decode),
+            extras,
+        })
+    }
+}
+
+fn host(url: &Url) -> Result<String, ParseError> {
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .ok_or(ParseError::FieldMissing("address".to_string()))?;
+    // IPv6 literals come bracketed, xray wants them bare.
+    Ok(host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string())
+}
+
+fn percent_decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+/// Share links use either base64 alphabet, with or without padding.
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim().trim_end_matches('=');
+    general_purpose::STANDARD_NO_PAD
+        .decode(s)
+        .or_else(|_| general_purpose::URL_SAFE_NO_PAD.decode(s))
+        .ok()
+}
+
+pub fn parse_line(line: &str) -> Result<ProxyConfig, String> {
+    // The payload is base64 rather than a URL, which `Url` could reject.
+    if let Some(payload) = line.strip_prefix("vmess://") {
+        return Vmess::decode(payload)
+            .map(ProxyConfig::Vmess)
+            .map_err(|err| format!("{}", err));
+    }
+
+    let url = Url::parse(line).map_err(|err| format!("invalid url: {}", err))?;
 
     match url.scheme() {
-        "vless" => Vless::parse(&url)
-            .map(ProxyConfig::Vless)
-            .map_err(|err| format!("{}", err)),
-        other => Err(format!("unknown url scheme: \"{other}\"")),
+        "vless" => Vless::parse(&url).map(ProxyConfig::Vless),
+        "ss" => Shadowsocks::parse(&url).map(ProxyConfig::Shadowsocks),
+        "trojan" => Trojan::parse(&url).map(ProxyConfig::Trojan),
+        other => return Err(format!("unknown url scheme: \"{other}\"")),
     }
+    .map_err(|err| format!("{}", err))
 }
 
-pub fn work(payload: String) {
-    let mut configs = Vec::new();
+/// A parsed node together with the share link it came from.
+#[derive(Debug)]
+pub struct Node {
+    pub uri: String,
+    pub config: ProxyConfig,
+    /// Names of the subscriptions that list this node.
+    pub subscriptions: Vec<String>,
+}
+
+pub fn work(payload: String, subscription: &str) -> Vec<Node> {
+    let mut nodes = Vec::new();
 
-    for line in payload.lines().take(20) {
-        println!("{}", line);
+    for line in payload.lines().map(str::trim).filter(|l| !l.is_empty()) {
         match parse_line(line) {
-            Ok(parsed) => configs.push(parsed),
+            Ok(config) => nodes.push(Node {
+                uri: line.to_string(),
+                config,
+                subscriptions: vec![subscription.to_string()],
+            }),
             Err(err) => println!("failed to parse line: {}", err),
         }
     }
 
-    for cfg in configs.iter().take(20) {
-        println!("{}", cfg.address())
+    nodes
+}
+
+/// Collapses nodes with the same fingerprint, keeping the first occurrence
+/// and merging the subscription lists. Returns how many entries were dropped.
+pub fn dedup(nodes: Vec<Node>) -> (Vec<Node>, usize) {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut unique: Vec<Node> = Vec::with_capacity(nodes.len());
+    let mut duplicates = 0;
+
+    for node in nodes {
+        match index.get(&node.config.fingerprint()) {
+            Some(&i) => {
+                duplicates += 1;
+                for sub in node.subscriptions {
+                    if !unique[i].subscriptions.contains(&sub) {
+                        unique[i].subscriptions.push(sub);
+                    }
+                }
+            }
+            None => {
+                index.insert(node.config.fingerprint(), unique.len());
+                unique.push(node);
+            }
+        }
+    }
+
+    (unique, duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vmess_link(json: Value) -> String {
+        format!(
+            "vmess://{}",
+            general_purpose::STANDARD.encode(json.to_string())
+        )
+    }
+
+    fn vmess_json() -> Value {
+        json!({
+            "v": "2", "ps": "Vmess-A", "add": "vm.example.com", "port": "443",
+            "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0", "scy": "auto",
+            "net": "ws", "type": "none", "host": "cdn.example.com", "path": "/ws",
+            "tls": "tls", "sni": "sni.example.com"
+        })
+    }
+
+    const SS_SIP002: &str = "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNz@ss.example.com:8388#SS-A";
+    const TROJAN: &str = "trojan://p%40ss@tr.example.com:443?security=tls&sni=sni.example.com&type=ws&path=%2Fws&host=cdn.example.com#Trojan-A";
+
+    #[test]
+    fn vmess() {
+        let config = parse_line(&vmess_link(vmess_json())).unwrap();
+        let ProxyConfig::Vmess(vmess) = &config else {
+            panic!("{:?}", config);
+        };
+        assert_eq!(vmess.address, "vm.example.com");
+        assert_eq!(vmess.port, 443);
+        assert!(vmess.tls);
+        assert_eq!(vmess.type_field, None);
+        assert_eq!(config.name(), Some("Vmess-A"));
+        assert_eq!(config.tls_server_name().as_deref(), Some("sni.example.com"));
+
+        // Numbers as numbers, URL-safe alphabet without padding.
+        let mut json = vmess_json();
+        json["port"] = json!(443);
+        json["aid"] = json!(0);
+        let link = format!(
+            "vmess://{}",
+            general_purpose::URL_SAFE_NO_PAD.encode(json.to_string())
+        );
+        assert_eq!(
+            parse_line(&link).unwrap().fingerprint(),
+            config.fingerprint()
+        );
+    }
+
+    #[test]
+    fn vmess_errors() {
+        assert!(
+            parse_line("vmess://not base64")
+                .unwrap_err()
+                .contains("not base64 JSON")
+        );
+        let mut json = vmess_json();
+        json.as_object_mut().unwrap().remove("id");
+        assert_eq!(
+            parse_line(&vmess_link(json)).unwrap_err(),
+            "Missing field: id"
+        );
+    }
+
+    #[test]
+    fn shadowsocks_forms() {
+        let plain = "ss://chacha20-ietf-poly1305:pass@ss.example.com:8388#SS-A";
+        let legacy = format!(
+            "ss://{}#SS-A",
+            general_purpose::STANDARD.encode("chacha20-ietf-poly1305:pass@ss.example.com:8388")
+        );
+
+        let expected = parse_line(SS_SIP002).unwrap();
+        let ProxyConfig::Shadowsocks(ss) = &expected else {
+            panic!("{:?}", expected);
+        };
+        assert_eq!(ss.method, "chacha20-ietf-poly1305");
+        assert_eq!(ss.password, "pass");
+        assert_eq!(ss.address, "ss.example.com");
+        assert_eq!(ss.port, 8388);
+        assert_eq!(expected.name(), Some("SS-A"));
+        assert_eq!(expected.tls_server_name(), None);
+
+        for link in [plain, legacy.as_str()] {
+            let config = parse_line(link).unwrap();
+            assert_eq!(config.fingerprint(), expected.fingerprint(), "{}", link);
+            assert_eq!(config.name(), Some("SS-A"));
+        }
+    }
+
+    #[test]
+    fn trojan() {
+        let config = parse_line(TROJAN).unwrap();
+        let ProxyConfig::Trojan(trojan) = &config else {
+            panic!("{:?}", config);
+        };
+        assert_eq!(trojan.password, "p@ss");
+        assert_eq!(trojan.ws_path.as_deref(), Some("/ws"));
+        assert_eq!(config.tls_server_name().as_deref(), Some("sni.example.com"));
+
+        // A path without `type=ws` is not a websocket path.
+        let tcp = parse_line("trojan://pass@tr.example.com:443?path=%2Fws").unwrap();
+        let ProxyConfig::Trojan(trojan) = &tcp else {
+            panic!("{:?}", tcp);
+        };
+        assert_eq!(trojan.ws_path, None);
+
+        assert_eq!(
+            parse_line("trojan://tr.example.com:443").unwrap_err(),
+            "Missing field: password"
+        );
+    }
+
+    #[test]
+    fn fingerprint_ignores_name_and_parameter_order() {
+        let mut renamed = vmess_json();
+        renamed["ps"] = json!("Vmess-B");
+        let pairs = [
+            (vmess_link(vmess_json()), vmess_link(renamed)),
+            (
+                SS_SIP002.to_string(),
+                SS_SIP002.replace("#SS-A", "#Renamed"),
+            ),
+            (
+                TROJAN.to_string(),
+                "trojan://p%40ss@tr.example.com:443?host=cdn.example.com&path=%2Fws&type=ws&sni=sni.example.com&security=tls#Other"
+                    .to_string(),
+            ),
+        ];
+        for (a, b) in pairs {
+            assert_eq!(
+                parse_line(&a).unwrap().fingerprint(),
+                parse_line(&b).unwrap().fingerprint(),
+                "{}",
+                a
+            );
+        }
+
+        // But anything that changes where traffic goes does change it.
+        assert_ne!(
+            parse_line(TROJAN).unwrap().fingerprint(),
+            parse_line(&TROJAN.replace("p%40ss", "other"))
+                .unwrap()
+                .fingerprint()
+        );
+        assert_ne!(
+            parse_line(SS_SIP002).unwrap().fingerprint(),
+            parse_line(&SS_SIP002.replace(":8388", ":8389"))
+                .unwrap()
+                .fingerprint()
+        );
+    }
+
+    #[test]
+    fn vmess_outbound() {
+        let outbound = parse_line(&vmess_link(vmess_json()))
+            .unwrap()
+            .to_outbound("proxy");
+        assert_eq!(
+            outbound,
+            json!({
+                "tag": "proxy",
+                "protocol": "vmess",
+                "settings": {
+                    "vnext": [{
+                        "address": "vm.example.com",
+                        "port": 443,
+                        "users": [{
+                            "id": "b831381d-6324-4d53-ad4f-8cda48b30811",
+                            "alterId": 0,
+                            "security": "auto",
+                        }],
+                    }],
+                },
+                "streamSettings": {
+                    "network": "ws",
+                    "security": "tls",
+                    "tlsSettings": {
+                        "serverName": "sni.example.com",
+                        "fingerprint": "chrome",
+                        "allowInsecure": false,
+                    },
+                    "wsSettings": { "path": "/ws", "headers": { "Host": "cdn.example.com" } },
+                },
+            })
+        );
+
+        let mut grpc = vmess_json();
+        grpc["net"] = json!("grpc");
+        grpc["path"] = json!("service");
+        let outbound = parse_line(&vmess_link(grpc)).unwrap().to_outbound("proxy");
+        assert_eq!(
+            outbound["streamSettings"]["grpcSettings"]["serviceName"],
+            "service"
+        );
+    }
+
+    #[test]
+    fn shadowsocks_outbound() {
+        assert_eq!(
+            parse_line(SS_SIP002).unwrap().to_outbound("proxy"),
+            json!({
+                "tag": "proxy",
+                "protocol": "shadowsocks",
+                "settings": {
+                    "servers": [{
+                        "address": "ss.example.com",
+                        "port": 8388,
+                        "method": "chacha20-ietf-poly1305",
+                        "password": "pass",
+                    }],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn trojan_outbound() {
+        let outbound = parse_line(TROJAN).unwrap().to_outbound("proxy");
+        assert_eq!(
+            outbound["settings"],
+            json!({
+                "servers": [{ "address": "tr.example.com", "port": 443, "password": "p@ss" }],
+            })
+        );
+        let stream = &outbound["streamSettings"];
+        assert_eq!(stream["network"], "ws");
+        assert_eq!(stream["security"], "tls");
+        assert_eq!(stream["tlsSettings"]["serverName"], "sni.example.com");
+        assert_eq!(
+            stream["wsSettings"],
+            json!({ "path": "/ws", "headers": { "Host": "cdn.example.com" } })
+        );
+    }
+
+    #[test]
+    fn names_are_percent_decoded() {
+        let cases = [
+            (
+                "vless://id@de.example.com:443?type=tcp&security=tls#%F0%9F%87%A9%F0%9F%87%AA%20Frankfurt%20%231",
+                "🇩🇪 Frankfurt #1",
+            ),
+            (
+                "trojan://pass@tr.example.com:443#Tokyo%20%E2%80%94%20fast",
+                "Tokyo — fast",
+            ),
+            (
+                "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNz@ss.example.com:8388#50%25%20off",
+                "50% off",
+            ),
+            // Not valid UTF-8: replaced rather than rejected.
+            (
+                "vless://id@a.example.com:443?type=tcp#bad%FF",
+                "bad\u{FFFD}",
+            ),
+            // Already decoded names stay as they are.
+            ("vless://id@a.example.com:443?type=tcp#Plain", "Plain"),
+        ];
+
+        for (link, name) in cases {
+            assert_eq!(parse_line(link).unwrap().name(), Some(name), "{}", link);
+        }
     }
 }
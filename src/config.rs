@@ -0,0 +1,720 @@
+//! `luxnulla.kdl`, the user-facing configuration of the daemon.
+//!
+//! ```kdl
+//! xray-binary "/usr/local/bin/xray"
+//!
+//! subscription "https://example.com/sub.txt" name="work"
+//!
+//! probe {
+//!     concurrency 32
+//!     timeout-ms 3000
+//! }
+//!
+//! url-test {
+//!     url "https://www.gstatic.com/generate_204"
+//!     timeout-ms 5000
+//!     batch-size 16
+//! }
+//!
+//! selection {
+//!     policy "fallback" // manual, lowest-latency, round-robin or fallback
+//!     check-interval-secs 60
+//!     max-failures 3
+//!     fallback "Node-A" "Node-B" // node names or ids, in order of preference
+//! }
+//!
+//! // Every listed matcher kind must match; values within one kind are
+//! // alternatives.
+//! group "europe" strategy="leastPing" {
+//!     subscription "work"
+//!     protocol "vless" "trojan"
+//!     country "DE" "NL"
+//!     tag "fast"
+//!     name-regex "(?i)premium"
+//! }
+//!
+//! observatory {
+//!     probe-url "https://www.gstatic.com/generate_204"
+//!     probe-interval "1m"
+//!     burst false
+//! }
+//!
+//! // First matching rule wins; traffic no rule matches goes through "proxy".
+//! routing domain-strategy="IPIfNonMatch" {
+//!     rule "direct" {
+//!         domain "full:router.lan" "regexp:\\.local$"
+//!         geosite "private"
+//!     }
+//!     rule "group" "europe" { geosite "netflix"; }
+//!     rule "block" { geosite "category-ads-all"; }
+//!     rule "direct" { ip "192.168.0.0/16"; port "22" "8000-8999"; }
+//!     rule "proxy" { process "firefox"; }
+//!
+//!     // Expanded after the rules above: global, bypass-lan,
+//!     // bypass-country "<code>" or block-ads.
+//!     preset "bypass-lan"
+//!     preset "bypass-country" "cn"
+//! }
+//!
+//! dns query-strategy="UseIPv4" {
+//!     server "https://1.1.1.1/dns-query"
+//!     server "223.5.5.5" port=53 {
+//!         domain "geosite:cn"
+//!         expect-ip "geoip:cn"
+//!         skip-fallback true
+//!     }
+//!     server "fakedns" { domain "geosite:geolocation-!cn"; }
+//!     fakedns { pool "198.18.0.0/15"; size 65535; }
+//!     host "router.lan" "192.168.1.1"
+//! }
+//!
+//! inbound "mixed" port=7890 {
+//!     listen "0.0.0.0" // share with the LAN
+//!     user "alice" "secret"
+//!     udp true
+//!     sniffing "http" "tls" "quic"
+//! }
+//! inbound "http" port=8080
+//!
+//! // Selected with `luxnulla select-chain`; the last hop is the exit.
+//! chain "domestic" {
+//!     node "Relay-A"
+//! }
+//! chain "via-relay" {
+//!     chain "domestic"
+//!     node "Exit-B"
+//! }
+//!
+//! // Traffic counters for `luxnulla traffic` and the TUI, read through
+//! // xray's stats API on a local port. Off without this section.
+//! stats {
+//!     enabled true
+//!     api-port 10085
+//!     interval-ms 1000
+//! }
+//!
+//! // Keys and colours of `luxnulla tui`.
+//! tui {
+//!     keymap "vim" // adds j/k, g/G and Ctrl-d/Ctrl-u to the arrow keys
+//!     keys {
+//!         quit "q" "ctrl-c"
+//!         details "i" "space"
+//!     }
+//!     theme "light" // dark, light or mono
+//!     colors {
+//!         header-bg "#3b4252"
+//!         highlight "light-cyan"
+//!     }
+//! }
+//!
+//! // Enabled with `luxnulla tproxy on`; needs root, nft and ip.
+//! tproxy mode="tproxy" { // or "redirect" (TCP over IPv4 only)
+//!     port 12345
+//!     mark 1
+//!     outbound-mark 255
+//!     table 100
+//!     bypass "203.0.113.0/24" // private ranges are always bypassed
+//! }
+//! ```
+
+use crate::{
+    XRAY_BINARY,
+    kdl::{self, KdlNode},
+};
+use std::{path::Path, time::Duration};
+
+mod chain;
+mod dns;
+mod edit;
+mod inbound;
+mod routing;
+mod tproxy;
+mod tui;
+
+pub use chain::{ChainConfig, ChainHop, expand_chain, flatten_chain};
+pub use dns::{DnsConfig, DnsServer, FakeDnsPool, QueryStrategy};
+pub use edit::{add_subscription, remove_subscription};
+pub use inbound::{InboundConfig, InboundProtocol};
+pub use routing::{DomainStrategy, RoutingConfig, RoutingPreset, RoutingRule, RuleTarget};
+pub use tproxy::{TproxyConfig, TproxyMode};
+pub use tui::{Key, KeyBinding, ThemeName, ThemeSlot, TuiAction, TuiConfig};
+
+#[derive(Debug, Clone)]
+pub struct LuxnullaConfig {
+    pub xray_binary: String,
+    pub subscriptions: Vec<Subscription>,
+    pub probe: ProbeConfig,
+    pub url_test: UrlTestConfig,
+    pub selection: SelectionConfig,
+    pub groups: Vec<NodeGroup>,
+    pub observatory: ObservatoryConfig,
+    pub routing: RoutingConfig,
+    /// `None` leaves the `dns` block of xray.json alone.
+    pub dns: Option<DnsConfig>,
+    /// Empty leaves the inbounds of xray.json alone.
+    pub inbounds: Vec<InboundConfig>,
+    pub tproxy: Option<TproxyConfig>,
+    pub chains: Vec<ChainConfig>,
+    pub stats: StatsConfig,
+    /// Only read by the TUI, which gets it from the daemon.
+    pub tui: TuiConfig,
+}
+
+impl Default for LuxnullaConfig {
+    fn default() -> Self {
+        Self {
+            xray_binary: XRAY_BINARY.to_string(),
+            subscriptions: Vec::new(),
+            probe: ProbeConfig::default(),
+            url_test: UrlTestConfig::default(),
+            selection: SelectionConfig::default(),
+            groups: Vec::new(),
+            observatory: ObservatoryConfig::default(),
+            routing: RoutingConfig::default(),
+            dns: None,
+            inbounds: Vec::new(),
+            tproxy: None,
+            chains: Vec::new(),
+            stats: StatsConfig::default(),
+            tui: TuiConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    /// Maximum number of nodes probed at the same time.
+    pub concurrency: usize,
+    pub timeout: Duration,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 32,
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UrlTestConfig {
+    pub url: String,
+    pub timeout: Duration,
+    /// Nodes tested through one temporary xray instance.
+    pub batch_size: usize,
+}
+
+impl Default for UrlTestConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://www.gstatic.com/generate_204".to_string(),
+            timeout: Duration::from_secs(5),
+            batch_size: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionPolicy {
+    /// Never switch on our own, only report failures.
+    Manual,
+    LowestLatency,
+    /// The next healthy node after the active one, in list order.
+    RoundRobin,
+    /// The first healthy node of `SelectionConfig::fallback`.
+    Fallback,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectionConfig {
+    pub policy: SelectionPolicy,
+    /// How often the active node is re-tested.
+    pub check_interval: Duration,
+    /// Consecutive failed checks before switching away.
+    pub max_failures: u32,
+    /// Node names or ids for the fallback policy, most preferred first.
+    pub fallback: Vec<String>,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        Self {
+            policy: SelectionPolicy::Manual,
+            check_interval: Duration::from_secs(60),
+            max_failures: 3,
+            fallback: Vec::new(),
+        }
+    }
+}
+
+/// xray balancer strategies, spelled as in xray's `strategy.type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalancerStrategy {
+    LeastPing,
+    LeastLoad,
+    Random,
+    RoundRobin,
+}
+
+impl BalancerStrategy {
+    pub fn as_xray(&self) -> &'static str {
+        match self {
+            BalancerStrategy::LeastPing => "leastPing",
+            BalancerStrategy::LeastLoad => "leastLoad",
+            BalancerStrategy::Random => "random",
+            BalancerStrategy::RoundRobin => "roundRobin",
+        }
+    }
+}
+
+/// A set of nodes xray balances between itself.
+#[derive(Debug, Clone)]
+pub struct NodeGroup {
+    pub name: String,
+    pub strategy: BalancerStrategy,
+    pub subscriptions: Vec<String>,
+    pub tags: Vec<String>,
+    /// ISO 3166 alpha-2 codes, matched against the flag or code in node names.
+    pub countries: Vec<String>,
+    pub protocols: Vec<String>,
+    pub name_regex: Option<regex::Regex>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObservatoryConfig {
+    pub probe_url: String,
+    /// xray duration string such as `"1m"` or `"30s"`.
+    pub probe_interval: String,
+    /// Emit `burstObservatory` instead of `observatory`.
+    pub burst: bool,
+}
+
+impl Default for ObservatoryConfig {
+    fn default() -> Self {
+        Self {
+            probe_url: "https://www.gstatic.com/generate_204".to_string(),
+            probe_interval: "1m".to_string(),
+            burst: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsConfig {
+    /// Generate xray's stats API and traffic counters.
+    pub enabled: bool,
+    /// Port of the API inbound on 127.0.0.1.
+    pub api_port: u16,
+    /// How often the daemon samples the counters while a client watches.
+    pub interval: Duration,
+}
+
+/// Off by default: the API inbound takes a local port, which must not keep
+/// xray from starting for people who never look at traffic.
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api_port: 10085,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub name: String,
+    pub url: String,
+}
+
+impl LuxnullaConfig {
+    /// Reads the config at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::from_kdl(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("failed to read {:?}: {}", path, e)),
+        }
+    }
+
+    pub fn from_kdl(input: &str) -> Result<Self, String> {
+        let nodes = kdl::parse(input).map_err(|e| format!("luxnulla.kdl: {}", e))?;
+        let mut config = Self::default();
+
+        for node in &nodes {
+            match node.name.as_str() {
+                "subscription" => config.subscriptions.push(Subscription::from_node(node)?),
+                "probe" => config.probe = ProbeConfig::from_node(node)?,
+                "url-test" => config.url_test = UrlTestConfig::from_node(node)?,
+                "selection" => config.selection = SelectionConfig::from_node(node)?,
+                "group" => {
+                    let group = NodeGroup::from_node(node)?;
+                    if config.groups.iter().any(|g| g.name == group.name) {
+                        return Err(node_error(
+                            node,
+                            format!("group '{}' is defined twice", group.name),
+                        ));
+                    }
+                    config.groups.push(group);
+                }
+                "observatory" => config.observatory = ObservatoryConfig::from_node(node)?,
+                "stats" => config.stats = StatsConfig::from_node(node)?,
+                "routing" => config.routing = RoutingConfig::from_node(node)?,
+                "dns" => config.dns = Some(DnsConfig::from_node(node)?),
+                "chain" => {
+                    let chain = ChainConfig::from_node(node)?;
+                    if config.chains.iter().any(|c| c.name == chain.name) {
+                        return Err(node_error(
+                            node,
+                            format!("chain '{}' is defined twice", chain.name),
+                        ));
+                    }
+                    config.chains.push(chain);
+                }
+                "tproxy" => config.tproxy = Some(TproxyConfig::from_node(node)?),
+                "tui" => config.tui = TuiConfig::from_node(node)?,
+                "inbound" => {
+                    let inbound = InboundConfig::from_node(node)?;
+                    if config.inbounds.iter().any(|i| i.port == inbound.port) {
+                        return Err(node_error(
+                            node,
+                            format!("port {} is used by two inbounds", inbound.port),
+                        ));
+                    }
+                    config.inbounds.push(inbound);
+                }
+                "xray-binary" => config.xray_binary = string_arg(node)?,
+                other => {
+                    return Err(node_error(node, format!("unknown section '{}'", other)));
+                }
+            }
+        }
+
+        if let Some(tproxy) = &config.tproxy
+            && config.inbounds.iter().any(|i| i.port == tproxy.port)
+        {
+            return Err(format!(
+                "luxnulla.kdl: tproxy port {} is also used by an inbound",
+                tproxy.port
+            ));
+        }
+
+        if config.stats.enabled
+            && (config
+                .inbounds
+                .iter()
+                .any(|i| i.port == config.stats.api_port)
+                || config.tproxy.as_ref().map(|t| t.port) == Some(config.stats.api_port))
+        {
+            return Err(format!(
+                "luxnulla.kdl: stats api-port {} is also used by an inbound",
+                config.stats.api_port
+            ));
+        }
+
+        // Hop counts are checked when a chain is used; one-node chains may be
+        // fragments of others.
+        for chain in &config.chains {
+            flatten_chain(&config.chains, &chain.name)
+                .map_err(|e| format!("luxnulla.kdl: {}", e))?;
+        }
+
+        for rule in &config.routing.rules {
+            if let Some(group) = rule.group()
+                && !config.groups.iter().any(|g| g.name == group)
+            {
+                return Err(format!(
+                    "luxnulla.kdl: routing rule targets unknown group '{}'",
+                    group
+                ));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Subscription {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let url = node
+            .arg(0)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| node_error(node, "subscription needs a URL argument"))?
+            .to_string();
+
+        let name = match node.prop("name") {
+            Some(value) => value
+                .as_str()
+                .ok_or_else(|| node_error(node, "name must be a string"))?
+                .to_string(),
+            None => Self::name_from_url(&url),
+        };
+
+        Ok(Self { name, url })
+    }
+
+    /// Name of a subscription without a `name` property: the URL host.
+    pub fn name_from_url(url: &str) -> String {
+        url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(String::from))
+            .unwrap_or_else(|| url.to_string())
+    }
+}
+
+impl ProbeConfig {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "concurrency" => config.concurrency = positive_arg(child)? as usize,
+                "timeout-ms" => config.timeout = Duration::from_millis(positive_arg(child)?),
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown probe option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl UrlTestConfig {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "url" => config.url = string_arg(child)?,
+                "timeout-ms" => config.timeout = Duration::from_millis(positive_arg(child)?),
+                "batch-size" => config.batch_size = positive_arg(child)? as usize,
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown url-test option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl SelectionConfig {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "policy" => {
+                    config.policy = match string_arg(child)?.as_str() {
+                        "manual" => SelectionPolicy::Manual,
+                        "lowest-latency" => SelectionPolicy::LowestLatency,
+                        "round-robin" => SelectionPolicy::RoundRobin,
+                        "fallback" => SelectionPolicy::Fallback,
+                        other => {
+                            return Err(node_error(
+                                child,
+                                format!(
+                                    "unknown policy '{}', expected manual, lowest-latency, round-robin or fallback",
+                                    other
+                                ),
+                            ));
+                        }
+                    }
+                }
+                "check-interval-secs" => {
+                    config.check_interval = Duration::from_secs(positive_arg(child)?)
+                }
+                "max-failures" => config.max_failures = positive_arg(child)? as u32,
+                "fallback" => config.fallback = string_args(child)?,
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown selection option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        if config.policy == SelectionPolicy::Fallback && config.fallback.is_empty() {
+            return Err(node_error(node, "fallback policy needs a fallback list"));
+        }
+
+        Ok(config)
+    }
+}
+
+impl NodeGroup {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let name = string_arg(node)?;
+
+        let strategy = match node.prop("strategy").map(|v| v.as_str()) {
+            None | Some(Some("leastPing")) => BalancerStrategy::LeastPing,
+            Some(Some("leastLoad")) => BalancerStrategy::LeastLoad,
+            Some(Some("random")) => BalancerStrategy::Random,
+            Some(Some("roundRobin")) => BalancerStrategy::RoundRobin,
+            Some(other) => {
+                return Err(node_error(
+                    node,
+                    format!(
+                        "unknown strategy {}, expected leastPing, leastLoad, random or roundRobin",
+                        other.unwrap_or("(not a string)")
+                    ),
+                ));
+            }
+        };
+
+        let mut group = Self {
+            name,
+            strategy,
+            subscriptions: Vec::new(),
+            tags: Vec::new(),
+            countries: Vec::new(),
+            protocols: Vec::new(),
+            name_regex: None,
+        };
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "subscription" => group.subscriptions.extend(string_args(child)?),
+                "tag" => group.tags.extend(string_args(child)?),
+                "country" => group
+                    .countries
+                    .extend(string_args(child)?.into_iter().map(|c| c.to_uppercase())),
+                "protocol" => group.protocols.extend(string_args(child)?),
+                "name-regex" => {
+                    let pattern = string_arg(child)?;
+                    group.name_regex = Some(
+                        regex::Regex::new(&pattern)
+                            .map_err(|e| node_error(child, format!("invalid regex: {}", e)))?,
+                    );
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown group matcher '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(group)
+    }
+}
+
+impl ObservatoryConfig {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "probe-url" => config.probe_url = string_arg(child)?,
+                "probe-interval" => config.probe_interval = string_arg(child)?,
+                "burst" => {
+                    config.burst = child
+                        .arg(0)
+                        .and_then(|v| v.as_bool())
+                        .ok_or_else(|| node_error(child, "burst needs true or false"))?
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown observatory option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl StatsConfig {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        // Writing the section is asking for stats.
+        let mut config = Self {
+            enabled: true,
+            ..Self::default()
+        };
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "enabled" => {
+                    config.enabled = child
+                        .arg(0)
+                        .and_then(|v| v.as_bool())
+                        .ok_or_else(|| node_error(child, "enabled needs true or false"))?
+                }
+                "api-port" => {
+                    config.api_port = u16::try_from(positive_arg(child)?)
+                        .map_err(|_| node_error(child, "api-port must be a port number"))?
+                }
+                "interval-ms" => config.interval = Duration::from_millis(positive_arg(child)?),
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown stats option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Reads all arguments of `node` as strings, requiring at least one.
+pub(crate) fn string_args(node: &KdlNode) -> Result<Vec<String>, String> {
+    node.args
+        .iter()
+        .map(|v| v.as_str().map(String::from))
+        .collect::<Option<Vec<_>>>()
+        .filter(|args| !args.is_empty())
+        .ok_or_else(|| node_error(node, format!("{} needs string arguments", node.name)))
+}
+
+/// Reads the first argument of `node` as a string.
+pub(crate) fn string_arg(node: &KdlNode) -> Result<String, String> {
+    node.arg(0)
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .ok_or_else(|| node_error(node, format!("{} needs a string argument", node.name)))
+}
+
+/// Reads the first argument of `node` as an integer greater than zero.
+pub(crate) fn positive_arg(node: &KdlNode) -> Result<u64, String> {
+    node.arg(0)
+        .and_then(|v| v.as_i64())
+        .filter(|v| *v > 0)
+        .map(|v| v as u64)
+        .ok_or_else(|| node_error(node, format!("{} needs a positive integer", node.name)))
+}
+
+pub(crate) fn node_error(node: &KdlNode, message: impl std::fmt::Display) -> String {
+    format!("luxnulla.kdl: line {}: {}", node.line, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_are_off_unless_asked_for() {
+        let config = LuxnullaConfig::from_kdl("").unwrap();
+        assert!(!config.stats.enabled);
+
+        let config = LuxnullaConfig::from_kdl("stats { api-port 10086; }").unwrap();
+        assert!(config.stats.enabled);
+        assert_eq!(config.stats.api_port, 10086);
+
+        let config = LuxnullaConfig::from_kdl("stats { enabled false; }").unwrap();
+        assert!(!config.stats.enabled);
+    }
+}
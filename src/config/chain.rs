@@ -0,0 +1,208 @@
+//! `chain` sections: nodes dialled through each other, entry hop first.
+
+use super::{node_error, string_arg};
+use crate::kdl::KdlNode;
+
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    pub name: String,
+    pub hops: Vec<ChainHop>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainHop {
+    /// A node name or id.
+    Node(String),
+    /// Another chain, spliced in place.
+    Chain(String),
+}
+
+impl ChainConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let name = string_arg(node)?;
+
+        let hops = node
+            .children
+            .iter()
+            .map(|child| match child.name.as_str() {
+                "node" => Ok(ChainHop::Node(string_arg(child)?)),
+                "chain" => Ok(ChainHop::Chain(string_arg(child)?)),
+                other => Err(node_error(
+                    child,
+                    format!("unknown chain hop '{}', expected node or chain", other),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if hops.is_empty() {
+            return Err(node_error(node, "chain needs at least one hop"));
+        }
+
+        Ok(Self { name, hops })
+    }
+}
+
+/// Flattens chain `name` into node names or ids, entry first. Fails on
+/// unknown chains, cycles and a node appearing twice; a single node is fine,
+/// as a chain may only exist to be spliced into others.
+pub fn flatten_chain(chains: &[ChainConfig], name: &str) -> Result<Vec<String>, String> {
+    let mut nodes = Vec::new();
+    expand_into(chains, name, &mut Vec::new(), &mut nodes)?;
+
+    for (i, node) in nodes.iter().enumerate() {
+        if nodes[..i].contains(node) {
+            return Err(format!(
+                "chain '{}' goes through node '{}' twice",
+                name, node
+            ));
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// [`flatten_chain`] for a chain that is dialled through, which needs at
+/// least an entry and an exit node.
+pub fn expand_chain(chains: &[ChainConfig], name: &str) -> Result<Vec<String>, String> {
+    let nodes = flatten_chain(chains, name)?;
+    if nodes.len() < 2 {
+        return Err(format!("chain '{}' needs at least two nodes", name));
+    }
+    Ok(nodes)
+}
+
+fn expand_into<'a>(
+    chains: &'a [ChainConfig],
+    name: &'a str,
+    stack: &mut Vec<&'a str>,
+    nodes: &mut Vec<String>,
+) -> Result<(), String> {
+    if stack.contains(&name) {
+        stack.push(name);
+        return Err(format!("chain cycle: {}", stack.join(" -> ")));
+    }
+
+    let chain = chains
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| format!("unknown chain '{}'", name))?;
+
+    stack.push(name);
+    for hop in &chain.hops {
+        match hop {
+            ChainHop::Node(node) => nodes.push(node.clone()),
+            ChainHop::Chain(inner) => expand_into(chains, inner, stack, nodes)?,
+        }
+    }
+    stack.pop();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl;
+
+    fn chains(source: &str) -> Vec<ChainConfig> {
+        kdl::parse(source)
+            .unwrap()
+            .iter()
+            .map(|node| ChainConfig::from_node(node).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn nested_chains_are_spliced_in_place() {
+        let chains = chains(
+            r#"
+            chain "domestic" { node "Relay-A"; }
+            chain "abroad" { node "Relay-B"; node "Relay-C"; }
+            chain "via-relay" { chain "domestic"; chain "abroad"; node "Exit"; }
+            "#,
+        );
+        assert_eq!(
+            expand_chain(&chains, "via-relay").unwrap(),
+            ["Relay-A", "Relay-B", "Relay-C", "Exit"]
+        );
+    }
+
+    #[test]
+    fn one_node_fragment() {
+        let chains = chains(
+            r#"
+            chain "domestic" { node "Relay-A"; }
+            chain "via-relay" { chain "domestic"; node "Exit-B"; }
+            "#,
+        );
+        assert_eq!(flatten_chain(&chains, "domestic").unwrap(), ["Relay-A"]);
+        assert_eq!(
+            expand_chain(&chains, "domestic").unwrap_err(),
+            "chain 'domestic' needs at least two nodes"
+        );
+        assert_eq!(
+            expand_chain(&chains, "via-relay").unwrap(),
+            ["Relay-A", "Exit-B"]
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        let chains = chains(
+            r#"
+            chain "a" { node "A"; chain "b"; }
+            chain "b" { node "B"; chain "c"; }
+            chain "c" { chain "a"; }
+            chain "self" { node "S"; chain "self"; }
+            "#,
+        );
+        assert_eq!(
+            flatten_chain(&chains, "a").unwrap_err(),
+            "chain cycle: a -> b -> c -> a"
+        );
+        assert_eq!(
+            flatten_chain(&chains, "self").unwrap_err(),
+            "chain cycle: self -> self"
+        );
+    }
+
+    #[test]
+    fn duplicate_hops() {
+        let chains = chains(
+            r#"
+            chain "twice" { node "A"; node "B"; node "A"; }
+            chain "inner" { node "A"; node "B"; }
+            chain "outer" { chain "inner"; node "B"; }
+            chain "diamond" { chain "inner"; chain "inner"; }
+            "#,
+        );
+        for name in ["twice", "outer", "diamond"] {
+            let error = flatten_chain(&chains, name).unwrap_err();
+            assert!(error.contains("twice"), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn unknown_chain() {
+        let chains = chains(r#"chain "a" { node "A"; chain "missing"; }"#);
+        assert_eq!(
+            flatten_chain(&chains, "a").unwrap_err(),
+            "unknown chain 'missing'"
+        );
+    }
+
+    #[test]
+    fn hop_errors() {
+        let node = &kdl::parse("chain \"a\" {\n    relay \"A\"\n}").unwrap()[0];
+        assert_eq!(
+            ChainConfig::from_node(node).unwrap_err(),
+            "luxnulla.kdl: line 2: unknown chain hop 'relay', expected node or chain"
+        );
+        let node = &kdl::parse("chain \"a\"").unwrap()[0];
+        assert!(
+            ChainConfig::from_node(node)
+                .unwrap_err()
+                .contains("at least one hop")
+        );
+    }
+}
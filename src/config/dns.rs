@@ -0,0 +1,411 @@
+//! The `dns` section: xray's built-in DNS server, so lookups go through the
+//! tunnel instead of the system resolver.
+
+use super::{
+    node_error, positive_arg,
+    routing::{validate_domain, validate_geo_name, validate_ip},
+    string_arg, string_args,
+};
+use crate::kdl::KdlNode;
+
+/// URL schemes xray accepts for a DNS server address.
+const SERVER_SCHEMES: &[&str] = &[
+    "https",
+    "https+local",
+    "h2c",
+    "tcp",
+    "tcp+local",
+    "tls",
+    "quic+local",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct DnsConfig {
+    /// Queried in order; per-domain servers are preferred for their domains.
+    pub servers: Vec<DnsServer>,
+    pub query_strategy: QueryStrategy,
+    /// Present when any server is `fakedns`.
+    pub fake_dns: Option<FakeDnsPool>,
+    /// Static answers, domain to addresses.
+    pub hosts: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DnsServer {
+    /// Plain IP, `localhost`, `fakedns` or a DoH/DoT/DoQ URL.
+    pub address: String,
+    pub port: Option<u16>,
+    /// Domain matchers this server is used for, as in routing rules.
+    pub domains: Vec<String>,
+    /// Answers outside these ranges are discarded.
+    pub expect_ips: Vec<String>,
+    /// Only use this server for `domains`, never as a fallback.
+    pub skip_fallback: bool,
+}
+
+/// xray `dns.queryStrategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueryStrategy {
+    #[default]
+    UseIp,
+    UseIpv4,
+    UseIpv6,
+}
+
+impl QueryStrategy {
+    pub fn as_xray(&self) -> &'static str {
+        match self {
+            QueryStrategy::UseIp => "UseIP",
+            QueryStrategy::UseIpv4 => "UseIPv4",
+            QueryStrategy::UseIpv6 => "UseIPv6",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeDnsPool {
+    /// CIDR fake addresses are handed out from.
+    pub ip_pool: String,
+    pub pool_size: u32,
+}
+
+impl Default for FakeDnsPool {
+    fn default() -> Self {
+        Self {
+            ip_pool: "198.18.0.0/15".to_string(),
+            pool_size: 65535,
+        }
+    }
+}
+
+impl DnsConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        if let Some(value) = node.prop("query-strategy") {
+            config.query_strategy = match value.as_str() {
+                Some("UseIP") => QueryStrategy::UseIp,
+                Some("UseIPv4") => QueryStrategy::UseIpv4,
+                Some("UseIPv6") => QueryStrategy::UseIpv6,
+                _ => {
+                    return Err(node_error(
+                        node,
+                        "query-strategy must be UseIP, UseIPv4 or UseIPv6",
+                    ));
+                }
+            };
+        }
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "server" => config.servers.push(DnsServer::from_node(child)?),
+                "fakedns" => config.fake_dns = Some(FakeDnsPool::from_node(child)?),
+                "host" => {
+                    let mut args = string_args(child)?;
+                    if args.len() < 2 {
+                        return Err(node_error(child, "host needs a domain and an address"));
+                    }
+                    let domain = args.remove(0);
+                    config.hosts.push((domain, args));
+                }
+                other => {
+                    return Err(node_error(child, format!("unknown dns option '{}'", other)));
+                }
+            }
+        }
+
+        if config.servers.is_empty() {
+            return Err(node_error(node, "dns needs at least one server"));
+        }
+
+        if config.fake_dns.is_none() && config.servers.iter().any(|s| s.address == "fakedns") {
+            config.fake_dns = Some(FakeDnsPool::default());
+        }
+
+        Ok(config)
+    }
+}
+
+impl DnsServer {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let address = string_arg(node)?;
+        validate_address(&address).map_err(|e| node_error(node, e))?;
+
+        let port = match node.prop("port") {
+            Some(value) => Some(
+                value
+                    .as_i64()
+                    .and_then(|p| u16::try_from(p).ok())
+                    .filter(|p| *p > 0)
+                    .ok_or_else(|| node_error(node, "port must be between 1 and 65535"))?,
+            ),
+            None => None,
+        };
+
+        let mut server = Self {
+            address,
+            port,
+            ..Default::default()
+        };
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "domain" => {
+                    let domains = string_args(child)?;
+                    for domain in &domains {
+                        validate_domain(domain).map_err(|e| node_error(child, e))?;
+                    }
+                    server.domains.extend(domains);
+                }
+                "expect-ip" => {
+                    let ips = string_args(child)?;
+                    for ip in &ips {
+                        match ip.strip_prefix("geoip:") {
+                            Some(code) => validate_geo_name(code),
+                            None => validate_ip(ip),
+                        }
+                        .map_err(|e| node_error(child, e))?;
+                    }
+                    server.expect_ips.extend(ips);
+                }
+                "skip-fallback" => {
+                    server.skip_fallback = child
+                        .arg(0)
+                        .and_then(|v| v.as_bool())
+                        .ok_or_else(|| node_error(child, "skip-fallback needs true or false"))?
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown dns server option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(server)
+    }
+}
+
+impl FakeDnsPool {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut pool = Self::default();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "pool" => {
+                    pool.ip_pool = string_arg(child)?;
+                    if !pool.ip_pool.contains('/') {
+                        return Err(node_error(child, "pool must be a CIDR"));
+                    }
+                    validate_ip(&pool.ip_pool).map_err(|e| node_error(child, e))?;
+                }
+                "size" => pool.pool_size = positive_arg(child)? as u32,
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown fakedns option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        if pool
+            .capacity()
+            .is_some_and(|capacity| u64::from(pool.pool_size) > capacity)
+        {
+            return Err(node_error(
+                node,
+                format!(
+                    "size {} does not fit in pool {}",
+                    pool.pool_size, pool.ip_pool
+                ),
+            ));
+        }
+
+        Ok(pool)
+    }
+
+    /// Addresses in `ip_pool`; `None` when there are more than fit in a `u64`.
+    fn capacity(&self) -> Option<u64> {
+        let (address, prefix) = self.ip_pool.split_once('/')?;
+        let bits = if address.contains(':') { 128 } else { 32 };
+        let host_bits = bits - prefix.parse::<u32>().ok()?;
+        1u64.checked_shl(host_bits)
+    }
+}
+
+fn validate_address(address: &str) -> Result<(), String> {
+    if address == "localhost" || address == "fakedns" || validate_ip(address).is_ok() {
+        return Ok(());
+    }
+
+    let url = url::Url::parse(address)
+        .map_err(|_| format!("'{}' is not an IP, localhost, fakedns or URL", address))?;
+    if !SERVER_SCHEMES.contains(&url.scheme()) {
+        return Err(format!(
+            "unsupported DNS scheme '{}://', expected one of {}",
+            url.scheme(),
+            SERVER_SCHEMES.join(", ")
+        ));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("DNS server URL '{}' has no host", address));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl;
+
+    fn dns(source: &str) -> Result<DnsConfig, String> {
+        DnsConfig::from_node(&kdl::parse(source).unwrap()[0])
+    }
+
+    #[test]
+    fn servers_and_hosts() {
+        let config = dns(r#"dns query-strategy="UseIPv4" {
+                server "https://1.1.1.1/dns-query"
+                server "223.5.5.5" port=53 {
+                    domain "geosite:cn"
+                    expect-ip "geoip:cn" "10.0.0.0/8"
+                    skip-fallback true
+                }
+                host "router.lan" "192.168.1.1" "fd00::1"
+            }"#)
+        .unwrap();
+
+        assert_eq!(config.query_strategy, QueryStrategy::UseIpv4);
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.servers[1].port, Some(53));
+        assert_eq!(config.servers[1].domains, ["geosite:cn"]);
+        assert_eq!(config.servers[1].expect_ips, ["geoip:cn", "10.0.0.0/8"]);
+        assert!(config.servers[1].skip_fallback);
+        assert_eq!(
+            config.hosts,
+            [(
+                "router.lan".to_string(),
+                vec!["192.168.1.1".to_string(), "fd00::1".to_string()]
+            )]
+        );
+        assert!(config.fake_dns.is_none());
+    }
+
+    #[test]
+    fn fakedns_pool() {
+        let config = dns(r#"dns { server "fakedns"; server "1.1.1.1"; }"#).unwrap();
+        let pool = config.fake_dns.unwrap();
+        assert_eq!(pool.ip_pool, "198.18.0.0/15");
+        assert_eq!(pool.pool_size, 65535);
+
+        let config = dns(r#"dns {
+                server "fakedns"
+                fakedns { pool "fc00::/18"; size 1000; }
+            }"#)
+        .unwrap();
+        let pool = config.fake_dns.unwrap();
+        assert_eq!(pool.ip_pool, "fc00::/18");
+        assert_eq!(pool.pool_size, 1000);
+
+        let config = dns(r#"dns {
+                server "fakedns"
+                fakedns { pool "198.18.0.0/24"; size 256; }
+            }"#)
+        .unwrap();
+        assert_eq!(config.fake_dns.unwrap().pool_size, 256);
+    }
+
+    #[test]
+    fn rejections() {
+        let cases = [
+            (
+                r#"dns query-strategy="UseIPv5" { server "1.1.1.1"; }"#,
+                "line 1: query-strategy must be UseIP, UseIPv4 or UseIPv6",
+            ),
+            ("dns {\n}", "line 1: dns needs at least one server"),
+            (
+                "dns {\n    server \"1.1.1.1\"\n    cache true\n}",
+                "line 3: unknown dns option 'cache'",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\"\n    host \"router.lan\"\n}",
+                "line 3: host needs a domain and an address",
+            ),
+            (
+                "dns {\n    server \"dns.google\"\n}",
+                "line 2: 'dns.google' is not an IP, localhost, fakedns or URL",
+            ),
+            (
+                "dns {\n    server \"udp://1.1.1.1\"\n}",
+                "line 2: unsupported DNS scheme 'udp://', expected one of https, https+local, h2c, tcp, tcp+local, tls, quic+local",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\" port=70000\n}",
+                "line 2: port must be between 1 and 65535",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\" {\n        domain \"sub:a.com\"\n    }\n}",
+                "line 3: unknown domain matcher 'sub:'",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\" {\n        expect-ip \"10.0.0.0/40\"\n    }\n}",
+                "line 3: invalid CIDR prefix in '10.0.0.0/40'",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\" {\n        skip-fallback \"yes\"\n    }\n}",
+                "line 3: skip-fallback needs true or false",
+            ),
+            (
+                "dns {\n    server \"1.1.1.1\" {\n        timeout 5\n    }\n}",
+                "line 3: unknown dns server option 'timeout'",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let error = dns(source).unwrap_err();
+            assert!(
+                error.starts_with(&format!("luxnulla.kdl: {}", expected)),
+                "{:?}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_fakedns_range() {
+        let cases = [
+            ("pool \"198.18.0.0\"", "line 4: pool must be a CIDR"),
+            (
+                "pool \"198.18.0.0/33\"",
+                "line 4: invalid CIDR prefix in '198.18.0.0/33'",
+            ),
+            (
+                "pool \"198.18.0/15\"",
+                "line 4: invalid IP address '198.18.0/15'",
+            ),
+            ("size 0", "line 4:"),
+            ("range \"a\"", "line 4: unknown fakedns option 'range'"),
+            (
+                "pool \"198.18.0.0/24\"; size 1000",
+                "line 3: size 1000 does not fit in pool 198.18.0.0/24",
+            ),
+        ];
+
+        for (option, expected) in cases {
+            let source = format!(
+                "dns {{\n    server \"fakedns\"\n    fakedns {{\n{}\n    }}\n}}",
+                option
+            );
+            let error = dns(&source).unwrap_err();
+            assert!(
+                error.starts_with(&format!("luxnulla.kdl: {}", expected)),
+                "{}: {}",
+                option,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Edits of `luxnulla.kdl` made on the user's behalf. They work on the text
+//! so comments and formatting survive, and re-parse the result to make sure
+//! only the intended node changed.
+
+use super::{LuxnullaConfig, Subscription};
+use crate::kdl::{self, KdlNode, KdlValue};
+
+/// Appends a `subscription` node. `name` is only written when given; without
+/// it the name is derived from the URL host, the same as when parsing.
+pub fn add_subscription(source: &str, url: &str, name: Option<&str>) -> Result<String, String> {
+    let parsed = url::Url::parse(url).map_err(|e| format!("invalid URL {}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("unsupported URL scheme: {}", parsed.scheme()));
+    }
+
+    let config = LuxnullaConfig::from_kdl(source)?;
+    let name = name.map(str::trim).filter(|name| !name.is_empty());
+    let effective = name.map_or_else(|| Subscription::name_from_url(url), String::from);
+    if config.subscriptions.iter().any(|sub| sub.name == effective) {
+        return Err(format!(
+            "a subscription named '{}' already exists",
+            effective
+        ));
+    }
+
+    let mut line = format!("subscription {}", KdlValue::String(url.to_string()));
+    if let Some(name) = name {
+        line.push_str(&format!(" name={}", KdlValue::String(name.to_string())));
+    }
+
+    let mut edited = source.to_string();
+    if !edited.is_empty() && !edited.ends_with('\n') {
+        edited.push('\n');
+    }
+    edited.push_str(&line);
+    edited.push('\n');
+
+    let check = LuxnullaConfig::from_kdl(&edited)?;
+    if check.subscriptions.len() != config.subscriptions.len() + 1 {
+        return Err(String::from(
+            "luxnulla.kdl did not pick up the new subscription",
+        ));
+    }
+    Ok(edited)
+}
+
+/// Deletes the line of the `subscription` named `name`. Refuses when that
+/// line holds anything else, e.g. a node spanning several lines.
+pub fn remove_subscription(source: &str, name: &str) -> Result<String, String> {
+    let nodes = kdl::parse(source).map_err(|e| format!("luxnulla.kdl: {}", e))?;
+
+    let mut matches = Vec::new();
+    for (index, node) in nodes.iter().enumerate() {
+        if node.name == "subscription" && Subscription::from_node(node)?.name == name {
+            matches.push(index);
+        }
+    }
+    let index = match matches.as_slice() {
+        [] => return Err(format!("no subscription named '{}'", name)),
+        [index] => *index,
+        _ => return Err(format!("'{}' names several subscriptions", name)),
+    };
+
+    let line = nodes[index].line;
+    let edited: String = source
+        .split_inclusive('\n')
+        .enumerate()
+        .filter(|(i, _)| i + 1 != line)
+        .map(|(_, text)| text)
+        .collect();
+
+    let remaining = kdl::parse(&edited).map_err(|_| edit_by_hand(name))?;
+    let expected = nodes.iter().enumerate().filter(|(i, _)| *i != index);
+    if remaining.len() != nodes.len() - 1
+        || !remaining
+            .iter()
+            .zip(expected)
+            .all(|(a, (_, b))| same_node(a, b))
+    {
+        return Err(edit_by_hand(name));
+    }
+    LuxnullaConfig::from_kdl(&edited)?;
+    Ok(edited)
+}
+
+fn edit_by_hand(name: &str) -> String {
+    format!(
+        "subscription '{}' does not sit on a line of its own, remove it with `luxnulla edit luxnulla`",
+        name
+    )
+}
+
+/// Compares everything but the line numbers.
+fn same_node(a: &KdlNode, b: &KdlNode) -> bool {
+    a.name == b.name
+        && a.args == b.args
+        && a.props == b.props
+        && a.children.len() == b.children.len()
+        && a.children
+            .iter()
+            .zip(&b.children)
+            .all(|(a, b)| same_node(a, b))
+}
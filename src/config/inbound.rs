@@ -0,0 +1,256 @@
+//! `inbound` sections: the local proxies xray listens on.
+
+use super::{node_error, string_arg, string_args};
+use crate::kdl::KdlNode;
+use std::net::IpAddr;
+
+/// Values xray accepts in `sniffing.destOverride`.
+const SNIFFERS: &[&str] = &["http", "tls", "quic", "fakedns", "fakedns+others"];
+
+#[derive(Debug, Clone)]
+pub struct InboundConfig {
+    pub protocol: InboundProtocol,
+    /// `127.0.0.1` by default; `0.0.0.0` shares the proxy with the LAN.
+    pub listen: IpAddr,
+    pub port: u16,
+    /// Username and password clients must present.
+    pub auth: Option<(String, String)>,
+    /// Relay UDP; socks and mixed only.
+    pub udp: bool,
+    /// `destOverride` protocols; empty disables sniffing.
+    pub sniffing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboundProtocol {
+    Socks,
+    Http,
+    /// SOCKS and HTTP on one port.
+    Mixed,
+}
+
+impl InboundProtocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InboundProtocol::Socks => "socks",
+            InboundProtocol::Http => "http",
+            InboundProtocol::Mixed => "mixed",
+        }
+    }
+}
+
+impl InboundConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let protocol = match string_arg(node)?.as_str() {
+            "socks" => InboundProtocol::Socks,
+            "http" => InboundProtocol::Http,
+            "mixed" => InboundProtocol::Mixed,
+            other => {
+                return Err(node_error(
+                    node,
+                    format!(
+                        "unknown inbound protocol '{}', expected socks, http or mixed",
+                        other
+                    ),
+                ));
+            }
+        };
+
+        let port = node
+            .prop("port")
+            .and_then(|v| v.as_i64())
+            .and_then(|p| u16::try_from(p).ok())
+            .filter(|p| *p > 0)
+            .ok_or_else(|| node_error(node, "inbound needs port=<1-65535>"))?;
+
+        let mut inbound = Self {
+            protocol,
+            listen: IpAddr::from([127, 0, 0, 1]),
+            port,
+            auth: None,
+            udp: false,
+            sniffing: Vec::new(),
+        };
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "listen" => {
+                    inbound.listen = string_arg(child)?
+                        .parse()
+                        .map_err(|_| node_error(child, "listen must be an IP address"))?
+                }
+                "user" => match string_args(child)?.as_slice() {
+                    [user, pass] => inbound.auth = Some((user.clone(), pass.clone())),
+                    _ => return Err(node_error(child, "user needs a name and a password")),
+                },
+                "udp" => {
+                    inbound.udp = child
+                        .arg(0)
+                        .and_then(|v| v.as_bool())
+                        .ok_or_else(|| node_error(child, "udp needs true or false"))?
+                }
+                "sniffing" => {
+                    let sniffers = string_args(child)?;
+                    if let Some(unknown) = sniffers.iter().find(|s| !SNIFFERS.contains(&s.as_str()))
+                    {
+                        return Err(node_error(
+                            child,
+                            format!(
+                                "unknown sniffer '{}', expected one of {}",
+                                unknown,
+                                SNIFFERS.join(", ")
+                            ),
+                        ));
+                    }
+                    inbound.sniffing = sniffers;
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown inbound option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        if inbound.udp && protocol == InboundProtocol::Http {
+            return Err(node_error(node, "http inbounds cannot relay UDP"));
+        }
+
+        Ok(inbound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::LuxnullaConfig, kdl};
+
+    fn inbound(source: &str) -> Result<InboundConfig, String> {
+        InboundConfig::from_node(&kdl::parse(source).unwrap()[0])
+    }
+
+    #[test]
+    fn defaults() {
+        let config = inbound(r#"inbound "socks" port=1080"#).unwrap();
+        assert_eq!(config.protocol, InboundProtocol::Socks);
+        assert_eq!(config.listen, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(config.port, 1080);
+        assert_eq!(config.auth, None);
+        assert!(!config.udp);
+        assert!(config.sniffing.is_empty());
+    }
+
+    #[test]
+    fn listen_auth_and_options() {
+        let config = inbound(
+            r#"inbound "mixed" port=7890 {
+                listen "0.0.0.0"
+                user "me" "secret"
+                udp true
+                sniffing "http" "tls" "fakedns+others"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.listen, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.auth, Some(("me".to_string(), "secret".to_string())));
+        assert!(config.udp);
+        assert_eq!(config.sniffing, ["http", "tls", "fakedns+others"]);
+
+        let config = inbound("inbound \"http\" port=8080 { listen \"::1\"; }").unwrap();
+        assert_eq!(config.listen, "::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn rejections() {
+        let cases = [
+            (
+                "inbound \"vless\" port=1",
+                "line 1: unknown inbound protocol 'vless', expected socks, http or mixed",
+            ),
+            ("inbound \"socks\"", "line 1: inbound needs port=<1-65535>"),
+            (
+                "inbound \"socks\" port=0",
+                "line 1: inbound needs port=<1-65535>",
+            ),
+            (
+                "inbound \"socks\" port=65536",
+                "line 1: inbound needs port=<1-65535>",
+            ),
+            (
+                "inbound \"socks\" port=1080 {\n    listen \"localhost\"\n}",
+                "line 2: listen must be an IP address",
+            ),
+            (
+                "inbound \"socks\" port=1080 {\n    user \"me\"\n}",
+                "line 2: user needs a name and a password",
+            ),
+            (
+                "inbound \"socks\" port=1080 {\n    udp \"yes\"\n}",
+                "line 2: udp needs true or false",
+            ),
+            (
+                "inbound \"socks\" port=1080 {\n    sniffing \"ssh\"\n}",
+                "line 2: unknown sniffer 'ssh', expected one of http, tls, quic, fakedns, fakedns+others",
+            ),
+            (
+                "inbound \"socks\" port=1080 {\n    auth \"me\"\n}",
+                "line 2: unknown inbound option 'auth'",
+            ),
+            (
+                "inbound \"http\" port=8080 {\n    udp true\n}",
+                "line 1: http inbounds cannot relay UDP",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(
+                inbound(source).unwrap_err(),
+                format!("luxnulla.kdl: {}", expected),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn port_clashes() {
+        let cases = [
+            (
+                "inbound \"socks\" port=1080\ninbound \"http\" port=1080",
+                "luxnulla.kdl: line 2: port 1080 is used by two inbounds",
+            ),
+            (
+                "inbound \"socks\" port=12345\ntproxy { port 12345; }",
+                "luxnulla.kdl: tproxy port 12345 is also used by an inbound",
+            ),
+            (
+                "inbound \"socks\" port=10085\nstats { enabled true; }",
+                "luxnulla.kdl: stats api-port 10085 is also used by an inbound",
+            ),
+            (
+                "tproxy { port 9000; }\nstats { enabled true; api-port 9000; }",
+                "luxnulla.kdl: stats api-port 9000 is also used by an inbound",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(
+                LuxnullaConfig::from_kdl(source).unwrap_err(),
+                expected,
+                "{}",
+                source
+            );
+        }
+
+        assert!(
+            LuxnullaConfig::from_kdl("inbound \"socks\" port=1080\ninbound \"http\" port=8080")
+                .is_ok()
+        );
+        // The API inbound only exists with stats on.
+        assert!(
+            LuxnullaConfig::from_kdl("inbound \"socks\" port=10085\nstats { enabled false; }")
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,537 @@
+//! The `routing` section: rules compiled into xray `routing.rules`.
+
+use super::{node_error, string_arg, string_args};
+use crate::kdl::KdlNode;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// Prefixes xray understands in front of a domain matcher.
+const DOMAIN_PREFIXES: &[&str] = &["domain", "full", "regexp", "keyword", "geosite", "ext"];
+
+#[derive(Debug, Clone, Default)]
+pub struct RoutingConfig {
+    pub domain_strategy: DomainStrategy,
+    /// In priority order; the first matching rule wins.
+    pub rules: Vec<RoutingRule>,
+    /// Expanded after `rules`, so hand-written rules take precedence.
+    pub presets: Vec<RoutingPreset>,
+}
+
+/// Canned rule sets for common split-tunnel setups.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RoutingPreset {
+    /// Everything through the proxy; adds no rules.
+    Global,
+    /// LAN and other private addresses go direct.
+    BypassLan,
+    /// Sites and addresses of a country (ISO code, lower case) go direct.
+    BypassCountry(String),
+    /// Ad and tracker domains are dropped.
+    BlockAds,
+}
+
+impl RoutingPreset {
+    pub fn rules(&self) -> Vec<RoutingRule> {
+        let geosite = |target, name: &str| RoutingRule {
+            target,
+            geosites: vec![name.to_string()],
+            ..Default::default()
+        };
+        let geoip = |target, code: &str| RoutingRule {
+            target,
+            geoips: vec![code.to_string()],
+            ..Default::default()
+        };
+
+        match self {
+            RoutingPreset::Global => Vec::new(),
+            // Separate rules: within one rule, domain and IP must both match.
+            RoutingPreset::BypassLan => vec![
+                geosite(RuleTarget::Direct, "private"),
+                geoip(RuleTarget::Direct, "private"),
+            ],
+            RoutingPreset::BypassCountry(country) => vec![
+                geosite(RuleTarget::Direct, country),
+                geoip(RuleTarget::Direct, country),
+            ],
+            RoutingPreset::BlockAds => vec![geosite(RuleTarget::Block, "category-ads-all")],
+        }
+    }
+}
+
+impl FromStr for RoutingPreset {
+    type Err = String;
+
+    /// Parses `global`, `bypass-lan`, `bypass-country:<code>` or `block-ads`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "global" => Ok(RoutingPreset::Global),
+            None if s == "bypass-lan" => Ok(RoutingPreset::BypassLan),
+            None if s == "block-ads" => Ok(RoutingPreset::BlockAds),
+            Some(("bypass-country", code))
+                if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                Ok(RoutingPreset::BypassCountry(code.to_lowercase()))
+            }
+            Some(("bypass-country", code)) => {
+                Err(format!("'{}' is not a two-letter country code", code))
+            }
+            _ => Err(format!(
+                "unknown preset '{}', expected global, bypass-lan, bypass-country:<code> or block-ads",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RoutingPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingPreset::Global => write!(f, "global"),
+            RoutingPreset::BypassLan => write!(f, "bypass-lan"),
+            RoutingPreset::BypassCountry(code) => write!(f, "bypass-country:{}", code),
+            RoutingPreset::BlockAds => write!(f, "block-ads"),
+        }
+    }
+}
+
+/// xray `routing.domainStrategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DomainStrategy {
+    #[default]
+    AsIs,
+    IpIfNonMatch,
+    IpOnDemand,
+}
+
+impl DomainStrategy {
+    pub fn as_xray(&self) -> &'static str {
+        match self {
+            DomainStrategy::AsIs => "AsIs",
+            DomainStrategy::IpIfNonMatch => "IPIfNonMatch",
+            DomainStrategy::IpOnDemand => "IPOnDemand",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RuleTarget {
+    Direct,
+    /// Whatever carries proxied traffic: the selected node or group.
+    #[default]
+    Proxy,
+    Block,
+    /// A `group` from luxnulla.kdl, balanced independently of the selection.
+    Group(String),
+}
+
+/// Matchers of one rule. As in xray, every non-empty kind must match and
+/// values within one kind are alternatives.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingRule {
+    pub target: RuleTarget,
+    /// xray domain matchers: `example.com`, `full:`, `regexp:`, `keyword:`...
+    pub domains: Vec<String>,
+    /// geosite list names, e.g. `cn` or `category-ads-all`.
+    pub geosites: Vec<String>,
+    /// Addresses or CIDRs.
+    pub ips: Vec<String>,
+    /// geoip codes, e.g. `cn` or `private`.
+    pub geoips: Vec<String>,
+    /// xray port list, e.g. `"53,443,1000-2000"`.
+    pub port: Option<String>,
+    /// Executable names of the local process that opened the connection.
+    pub processes: Vec<String>,
+}
+
+impl RoutingConfig {
+    /// Hand-written rules followed by the expansion of every preset.
+    pub fn all_rules(&self) -> Vec<RoutingRule> {
+        self.rules
+            .iter()
+            .cloned()
+            .chain(self.presets.iter().flat_map(RoutingPreset::rules))
+            .collect()
+    }
+}
+
+impl RoutingRule {
+    /// The group this rule routes to, so the generator can emit it.
+    pub fn group(&self) -> Option<&str> {
+        match &self.target {
+            RuleTarget::Group(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        if let Some(value) = node.prop("domain-strategy") {
+            config.domain_strategy = match value.as_str() {
+                Some("AsIs") => DomainStrategy::AsIs,
+                Some("IPIfNonMatch") => DomainStrategy::IpIfNonMatch,
+                Some("IPOnDemand") => DomainStrategy::IpOnDemand,
+                _ => {
+                    return Err(node_error(
+                        node,
+                        "domain-strategy must be AsIs, IPIfNonMatch or IPOnDemand",
+                    ));
+                }
+            };
+        }
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "rule" => config.rules.push(RoutingRule::from_node(child)?),
+                "preset" => {
+                    let name = string_arg(child)?;
+                    let spec = match child.arg(1).and_then(|v| v.as_str()) {
+                        Some(country) => format!("{}:{}", name, country),
+                        None => name,
+                    };
+                    config
+                        .presets
+                        .push(spec.parse().map_err(|e| node_error(child, e))?);
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown routing option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl RoutingRule {
+    fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let target = match string_arg(node)?.as_str() {
+            "direct" => RuleTarget::Direct,
+            "proxy" => RuleTarget::Proxy,
+            "block" => RuleTarget::Block,
+            "group" => RuleTarget::Group(
+                node.arg(1)
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| node_error(node, "rule \"group\" needs a group name"))?
+                    .to_string(),
+            ),
+            other => {
+                return Err(node_error(
+                    node,
+                    format!(
+                        "unknown rule target '{}', expected direct, proxy, block or group",
+                        other
+                    ),
+                ));
+            }
+        };
+
+        let mut rule = Self {
+            target,
+            ..Default::default()
+        };
+
+        for child in &node.children {
+            let values = string_args(child)?;
+            let check = |valid: fn(&str) -> Result<(), String>| {
+                values
+                    .iter()
+                    .try_for_each(|v| valid(v).map_err(|e| node_error(child, e)))
+            };
+
+            match child.name.as_str() {
+                "domain" => {
+                    check(validate_domain)?;
+                    rule.domains.extend(values);
+                }
+                "geosite" => {
+                    check(validate_geo_name)?;
+                    rule.geosites.extend(values);
+                }
+                "ip" => {
+                    check(validate_ip)?;
+                    rule.ips.extend(values);
+                }
+                "geoip" => {
+                    check(validate_geo_name)?;
+                    rule.geoips.extend(values);
+                }
+                "port" => {
+                    check(validate_ports)?;
+                    let ports = values.join(",");
+                    rule.port = Some(match rule.port.take() {
+                        Some(previous) => format!("{},{}", previous, ports),
+                        None => ports,
+                    });
+                }
+                "process" => rule.processes.extend(values),
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown rule matcher '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        if rule.domains.is_empty()
+            && rule.geosites.is_empty()
+            && rule.ips.is_empty()
+            && rule.geoips.is_empty()
+            && rule.port.is_none()
+            && rule.processes.is_empty()
+        {
+            return Err(node_error(node, "rule needs at least one matcher"));
+        }
+
+        Ok(rule)
+    }
+}
+
+pub(super) fn validate_domain(value: &str) -> Result<(), String> {
+    let Some((prefix, rest)) = value.split_once(':') else {
+        return if value.is_empty() || value.contains(char::is_whitespace) {
+            Err(format!("invalid domain '{}'", value))
+        } else {
+            Ok(())
+        };
+    };
+
+    if !DOMAIN_PREFIXES.contains(&prefix) {
+        return Err(format!(
+            "unknown domain matcher '{}:', expected one of {}",
+            prefix,
+            DOMAIN_PREFIXES.join(", ")
+        ));
+    }
+    if rest.is_empty() {
+        return Err(format!("empty domain matcher '{}'", value));
+    }
+    if prefix == "regexp" {
+        regex::Regex::new(rest).map_err(|e| format!("invalid regexp '{}': {}", rest, e))?;
+    }
+    Ok(())
+}
+
+pub(super) fn validate_geo_name(value: &str) -> Result<(), String> {
+    let name = value.strip_prefix('!').unwrap_or(value);
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '!' | '@' | '.'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid geo list name '{}'", value))
+    }
+}
+
+pub(super) fn validate_ip(value: &str) -> Result<(), String> {
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+
+    let address: IpAddr = address
+        .parse()
+        .map_err(|_| format!("invalid IP address '{}'", value))?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+
+    match prefix.map(str::parse::<u8>) {
+        None => Ok(()),
+        Some(Ok(bits)) if bits <= max => Ok(()),
+        Some(_) => Err(format!("invalid CIDR prefix in '{}'", value)),
+    }
+}
+
+fn validate_ports(value: &str) -> Result<(), String> {
+    for part in value.split(',') {
+        let part = part.trim();
+        let (low, high) = part.split_once('-').unwrap_or((part, part));
+
+        match (low.trim().parse::<u16>(), high.trim().parse::<u16>()) {
+            (Ok(low), Ok(high)) if low > 0 && low <= high => {}
+            _ => return Err(format!("invalid port or port range '{}'", part)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl;
+
+    fn routing(source: &str) -> Result<RoutingConfig, String> {
+        RoutingConfig::from_node(&kdl::parse(source).unwrap()[0])
+    }
+
+    #[test]
+    fn presets_parse_and_print() {
+        for spec in ["global", "bypass-lan", "bypass-country:cn", "block-ads"] {
+            let preset: RoutingPreset = spec.parse().unwrap();
+            assert_eq!(preset.to_string(), spec);
+        }
+        assert_eq!(
+            "bypass-country:RU".parse(),
+            Ok(RoutingPreset::BypassCountry("ru".to_string()))
+        );
+        assert_eq!(
+            "bypass-country:rus".parse::<RoutingPreset>().unwrap_err(),
+            "'rus' is not a two-letter country code"
+        );
+        assert!(
+            "bypass"
+                .parse::<RoutingPreset>()
+                .unwrap_err()
+                .starts_with("unknown preset 'bypass'")
+        );
+    }
+
+    #[test]
+    fn preset_rules() {
+        let targets = |preset: RoutingPreset| {
+            preset
+                .rules()
+                .into_iter()
+                .map(|rule| (rule.target, rule.geosites, rule.geoips))
+                .collect::<Vec<_>>()
+        };
+        assert!(targets(RoutingPreset::Global).is_empty());
+        assert_eq!(
+            targets(RoutingPreset::BypassCountry("cn".to_string())),
+            [
+                (RuleTarget::Direct, vec!["cn".to_string()], vec![]),
+                (RuleTarget::Direct, vec![], vec!["cn".to_string()]),
+            ]
+        );
+        assert_eq!(
+            targets(RoutingPreset::BlockAds),
+            [(
+                RuleTarget::Block,
+                vec!["category-ads-all".to_string()],
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn rules_keep_their_order_before_presets() {
+        let config = routing(
+            r#"routing domain-strategy="IPOnDemand" {
+                preset "bypass-country" "CN"
+                rule "group" "europe" { geosite "netflix"; }
+                rule "direct" { port "22" "8000-8999"; port "53"; }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.domain_strategy, DomainStrategy::IpOnDemand);
+        assert_eq!(
+            config.presets,
+            [RoutingPreset::BypassCountry("cn".to_string())]
+        );
+
+        let rules = config.all_rules();
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].group(), Some("europe"));
+        assert_eq!(rules[1].port.as_deref(), Some("22,8000-8999,53"));
+        assert_eq!(rules[2].geosites, ["cn"]);
+    }
+
+    #[test]
+    fn rejections() {
+        let cases = [
+            (
+                r#"routing domain-strategy="IPAlways""#,
+                "line 1: domain-strategy must be AsIs, IPIfNonMatch or IPOnDemand",
+            ),
+            (
+                "routing {\n    rules\n}",
+                "line 2: unknown routing option 'rules'",
+            ),
+            (
+                "routing {\n    preset \"bypass-country\" \"cnn\"\n}",
+                "line 2: 'cnn' is not a two-letter country code",
+            ),
+            (
+                "routing {\n    rule \"tunnel\" { domain \"a.com\"; }\n}",
+                "line 2: unknown rule target 'tunnel', expected direct, proxy, block or group",
+            ),
+            (
+                "routing {\n    rule \"group\" { domain \"a.com\"; }\n}",
+                "line 2: rule \"group\" needs a group name",
+            ),
+            (
+                "routing {\n    rule \"direct\"\n}",
+                "line 2: rule needs at least one matcher",
+            ),
+            (
+                "routing {\n    rule \"direct\" {\n        url \"a.com\"\n    }\n}",
+                "line 3: unknown rule matcher 'url'",
+            ),
+            (
+                "routing {\n    rule \"direct\" {\n        domain \"sub:a.com\"\n    }\n}",
+                "line 3: unknown domain matcher 'sub:', expected one of domain, full, regexp, keyword, geosite, ext",
+            ),
+            (
+                "routing {\n    rule \"direct\" { domain \"full:\"; }\n}",
+                "line 2: empty domain matcher 'full:'",
+            ),
+            (
+                "routing {\n    rule \"direct\" { domain \"regexp:(\"; }\n}",
+                "line 2: invalid regexp '('",
+            ),
+            (
+                "routing {\n    rule \"direct\" { geoip \"c n\"; }\n}",
+                "line 2: invalid geo list name 'c n'",
+            ),
+            (
+                "routing {\n    rule \"direct\" { ip \"10.0.0.0/33\"; }\n}",
+                "line 2: invalid CIDR prefix in '10.0.0.0/33'",
+            ),
+            (
+                "routing {\n    rule \"direct\" { ip \"10.0.0\"; }\n}",
+                "line 2: invalid IP address '10.0.0'",
+            ),
+            (
+                "routing {\n    rule \"direct\" { port \"0\"; }\n}",
+                "line 2: invalid port or port range '0'",
+            ),
+            (
+                "routing {\n    rule \"direct\" { port \"90-80\"; }\n}",
+                "line 2: invalid port or port range '90-80'",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let error = routing(source).unwrap_err();
+            assert!(
+                error.starts_with(&format!("luxnulla.kdl: {}", expected)),
+                "{:?}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn valid_matchers() {
+        for domain in ["example.com", "full:a.com", "regexp:\\.lan$", "geosite:cn"] {
+            assert_eq!(validate_domain(domain), Ok(()), "{}", domain);
+        }
+        for ip in ["10.0.0.0/8", "::1", "2001:db8::/32", "1.2.3.4/32"] {
+            assert_eq!(validate_ip(ip), Ok(()), "{}", ip);
+        }
+        for name in ["cn", "!cn", "category-ads-all", "geolocation-!cn"] {
+            assert_eq!(validate_geo_name(name), Ok(()), "{}", name);
+        }
+        assert_eq!(validate_ports("53, 1000-2000,443"), Ok(()));
+    }
+}
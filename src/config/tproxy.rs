@@ -0,0 +1,220 @@
+//! The `tproxy` section: system-wide proxying through a `dokodemo-door`
+//! inbound fed by nftables.
+
+use super::{node_error, positive_arg, routing::validate_ip, string_args};
+use crate::kdl::KdlNode;
+
+#[derive(Debug, Clone)]
+pub struct TproxyConfig {
+    pub mode: TproxyMode,
+    /// Port of the `dokodemo-door` inbound.
+    pub port: u16,
+    /// fwmark that sends intercepted packets to `table` (tproxy mode).
+    pub mark: u32,
+    /// SO_MARK on xray's own connections, so they are not intercepted again.
+    pub outbound_mark: u32,
+    /// Policy routing table delivering marked packets locally (tproxy mode).
+    pub table: u32,
+    /// Destinations never intercepted, on top of private and reserved ranges.
+    pub bypass: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TproxyMode {
+    /// TCP and UDP, IPv4 and IPv6, through TPROXY and policy routing.
+    Tproxy,
+    /// TCP over IPv4 only, through NAT REDIRECT.
+    Redirect,
+}
+
+impl TproxyMode {
+    /// Value of xray's `streamSettings.sockopt.tproxy`.
+    pub fn as_xray(&self) -> &'static str {
+        match self {
+            TproxyMode::Tproxy => "tproxy",
+            TproxyMode::Redirect => "redirect",
+        }
+    }
+}
+
+impl Default for TproxyConfig {
+    fn default() -> Self {
+        Self {
+            mode: TproxyMode::Tproxy,
+            port: 12345,
+            mark: 1,
+            outbound_mark: 255,
+            table: 100,
+            bypass: Vec::new(),
+        }
+    }
+}
+
+impl TproxyConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        if let Some(value) = node.prop("mode") {
+            config.mode = match value.as_str() {
+                Some("tproxy") => TproxyMode::Tproxy,
+                Some("redirect") => TproxyMode::Redirect,
+                _ => return Err(node_error(node, "mode must be tproxy or redirect")),
+            };
+        }
+
+        for child in &node.children {
+            let number = |max: u64| {
+                positive_arg(child).and_then(|n| {
+                    if n <= max {
+                        Ok(n)
+                    } else {
+                        Err(node_error(
+                            child,
+                            format!("{} must be at most {}", child.name, max),
+                        ))
+                    }
+                })
+            };
+
+            match child.name.as_str() {
+                "port" => config.port = number(u16::MAX as u64)? as u16,
+                "mark" => config.mark = number(u32::MAX as u64)? as u32,
+                "outbound-mark" => config.outbound_mark = number(u32::MAX as u64)? as u32,
+                // Tables above 252 are reserved (default, main, local).
+                "table" => config.table = number(252)? as u32,
+                "bypass" => {
+                    let ranges = string_args(child)?;
+                    for range in &ranges {
+                        validate_ip(range).map_err(|e| node_error(child, e))?;
+                    }
+                    config.bypass.extend(ranges);
+                }
+                other => {
+                    return Err(node_error(
+                        child,
+                        format!("unknown tproxy option '{}'", other),
+                    ));
+                }
+            }
+        }
+
+        if config.mark == config.outbound_mark {
+            return Err(node_error(node, "mark and outbound-mark must differ"));
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl;
+
+    fn tproxy(source: &str) -> Result<TproxyConfig, String> {
+        TproxyConfig::from_node(&kdl::parse(source).unwrap()[0])
+    }
+
+    #[test]
+    fn defaults() {
+        let config = tproxy("tproxy").unwrap();
+        assert_eq!(config.mode, TproxyMode::Tproxy);
+        assert_eq!(
+            (config.port, config.mark, config.outbound_mark, config.table),
+            (12345, 1, 255, 100)
+        );
+        assert!(config.bypass.is_empty());
+    }
+
+    #[test]
+    fn options() {
+        let config = tproxy(
+            r#"tproxy mode="redirect" {
+                port 7893
+                mark 4294967295
+                outbound-mark 2
+                table 252
+                bypass "203.0.113.0/24" "2001:db8::/32"
+                bypass "198.51.100.7"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, TproxyMode::Redirect);
+        assert_eq!(config.port, 7893);
+        assert_eq!(config.mark, u32::MAX);
+        assert_eq!(config.outbound_mark, 2);
+        assert_eq!(config.table, 252);
+        assert_eq!(
+            config.bypass,
+            ["203.0.113.0/24", "2001:db8::/32", "198.51.100.7"]
+        );
+    }
+
+    #[test]
+    fn rejections() {
+        let cases = [
+            (
+                "tproxy mode=\"nat\"",
+                "line 1: mode must be tproxy or redirect",
+            ),
+            (
+                "tproxy {\n    port 65536\n}",
+                "line 2: port must be at most 65535",
+            ),
+            (
+                "tproxy {\n    mark 4294967296\n}",
+                "line 2: mark must be at most 4294967295",
+            ),
+            (
+                "tproxy {\n    outbound-mark 4294967296\n}",
+                "line 2: outbound-mark must be at most 4294967295",
+            ),
+            (
+                "tproxy {\n    table 253\n}",
+                "line 2: table must be at most 252",
+            ),
+            (
+                "tproxy {\n    table 0\n}",
+                "line 2: table needs a positive integer",
+            ),
+            (
+                "tproxy {\n    mark -1\n}",
+                "line 2: mark needs a positive integer",
+            ),
+            (
+                "tproxy {\n    mark 7\n    outbound-mark 7\n}",
+                "line 1: mark and outbound-mark must differ",
+            ),
+            (
+                "tproxy {\n    outbound-mark 1\n}",
+                "line 1: mark and outbound-mark must differ",
+            ),
+            (
+                "tproxy {\n    bypass \"10.0.0.0/8\" \"10.0.0.0/33\"\n}",
+                "line 2: invalid CIDR prefix in '10.0.0.0/33'",
+            ),
+            (
+                "tproxy {\n    bypass \"2001:db8::/129\"\n}",
+                "line 2: invalid CIDR prefix in '2001:db8::/129'",
+            ),
+            (
+                "tproxy {\n    bypass \"example.com\"\n}",
+                "line 2: invalid IP address 'example.com'",
+            ),
+            (
+                "tproxy {\n    interface \"eth0\"\n}",
+                "line 2: unknown tproxy option 'interface'",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let error = tproxy(source).unwrap_err();
+            assert!(
+                error.starts_with(&format!("luxnulla.kdl: {}", expected)),
+                "{:?}: {}",
+                source,
+                error
+            );
+        }
+    }
+}
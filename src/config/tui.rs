@@ -0,0 +1,671 @@
+//! The `tui` section: key bindings and colours of `luxnulla tui`.
+
+use super::{node_error, string_arg, string_args};
+use crate::kdl::KdlNode;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TuiConfig {
+    pub theme: ThemeName,
+    /// Colours replacing those of `theme`: a name, a 256-colour index or
+    /// `#rrggbb`.
+    pub colors: Vec<(ThemeSlot, String)>,
+    /// Keys of every action, with the keymap preset and `keys` applied.
+    pub keys: Vec<(TuiAction, Vec<KeyBinding>)>,
+}
+
+impl Default for TuiConfig {
+    fn default() -> Self {
+        Self {
+            theme: ThemeName::Dark,
+            colors: Vec::new(),
+            keys: TuiAction::ALL
+                .iter()
+                .map(|&action| (action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ThemeName {
+    /// For dark terminal backgrounds.
+    Dark,
+    /// For light terminal backgrounds.
+    Light,
+    /// The terminal's own colours only, with bold and reverse for emphasis.
+    Mono,
+}
+
+/// What a themed colour is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ThemeSlot {
+    Text,
+    /// Secondary text: placeholders, untested latencies, rule tags.
+    Muted,
+    /// Keys in the help bar and labels.
+    Accent,
+    HeaderFg,
+    HeaderBg,
+    /// The selected row.
+    Highlight,
+    Good,
+    Warning,
+    Error,
+    Info,
+    Uplink,
+    Downlink,
+}
+
+impl ThemeSlot {
+    const ALL: [ThemeSlot; 12] = [
+        ThemeSlot::Text,
+        ThemeSlot::Muted,
+        ThemeSlot::Accent,
+        ThemeSlot::HeaderFg,
+        ThemeSlot::HeaderBg,
+        ThemeSlot::Highlight,
+        ThemeSlot::Good,
+        ThemeSlot::Warning,
+        ThemeSlot::Error,
+        ThemeSlot::Info,
+        ThemeSlot::Uplink,
+        ThemeSlot::Downlink,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThemeSlot::Text => "text",
+            ThemeSlot::Muted => "muted",
+            ThemeSlot::Accent => "accent",
+            ThemeSlot::HeaderFg => "header-fg",
+            ThemeSlot::HeaderBg => "header-bg",
+            ThemeSlot::Highlight => "highlight",
+            ThemeSlot::Good => "good",
+            ThemeSlot::Warning => "warning",
+            ThemeSlot::Error => "error",
+            ThemeSlot::Info => "info",
+            ThemeSlot::Uplink => "uplink",
+            ThemeSlot::Downlink => "downlink",
+        }
+    }
+}
+
+/// Something a key does in the TUI. Which tab it applies to depends on the
+/// action; `a` for example both adds subscriptions and tests all nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TuiAction {
+    Quit,
+    NextTab,
+    PreviousTab,
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    First,
+    Last,
+    Select,
+    Cancel,
+    Reload,
+    Test,
+    TestFiltered,
+    TestAll,
+    Sort,
+    ReverseSort,
+    AliveOnly,
+    Protocol,
+    Favourites,
+    Favourite,
+    Search,
+    Details,
+    Copy,
+    Add,
+    Remove,
+    Level,
+}
+
+impl TuiAction {
+    pub const ALL: [TuiAction; 27] = [
+        TuiAction::Quit,
+        TuiAction::NextTab,
+        TuiAction::PreviousTab,
+        TuiAction::Down,
+        TuiAction::Up,
+        TuiAction::PageDown,
+        TuiAction::PageUp,
+        TuiAction::First,
+        TuiAction::Last,
+        TuiAction::Select,
+        TuiAction::Cancel,
+        TuiAction::Reload,
+        TuiAction::Test,
+        TuiAction::TestFiltered,
+        TuiAction::TestAll,
+        TuiAction::Sort,
+        TuiAction::ReverseSort,
+        TuiAction::AliveOnly,
+        TuiAction::Protocol,
+        TuiAction::Favourites,
+        TuiAction::Favourite,
+        TuiAction::Search,
+        TuiAction::Details,
+        TuiAction::Copy,
+        TuiAction::Add,
+        TuiAction::Remove,
+        TuiAction::Level,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TuiAction::Quit => "quit",
+            TuiAction::NextTab => "next-tab",
+            TuiAction::PreviousTab => "previous-tab",
+            TuiAction::Down => "down",
+            TuiAction::Up => "up",
+            TuiAction::PageDown => "page-down",
+            TuiAction::PageUp => "page-up",
+            TuiAction::First => "first",
+            TuiAction::Last => "last",
+            TuiAction::Select => "select",
+            TuiAction::Cancel => "cancel",
+            TuiAction::Reload => "reload",
+            TuiAction::Test => "test",
+            TuiAction::TestFiltered => "test-filtered",
+            TuiAction::TestAll => "test-all",
+            TuiAction::Sort => "sort",
+            TuiAction::ReverseSort => "reverse-sort",
+            TuiAction::AliveOnly => "alive-only",
+            TuiAction::Protocol => "protocol",
+            TuiAction::Favourites => "favourites",
+            TuiAction::Favourite => "favourite",
+            TuiAction::Search => "search",
+            TuiAction::Details => "details",
+            TuiAction::Copy => "copy",
+            TuiAction::Add => "add",
+            TuiAction::Remove => "remove",
+            TuiAction::Level => "level",
+        }
+    }
+
+    /// Actions checked before the active tab sees the key.
+    pub fn is_global(self) -> bool {
+        matches!(
+            self,
+            TuiAction::Quit | TuiAction::NextTab | TuiAction::PreviousTab
+        )
+    }
+
+    fn default_keys(self) -> Vec<KeyBinding> {
+        use KeyBinding as K;
+
+        match self {
+            TuiAction::Quit => vec![K::char('q')],
+            TuiAction::NextTab => vec![K::key(Key::Tab)],
+            TuiAction::PreviousTab => vec![K::key(Key::BackTab)],
+            TuiAction::Down => vec![K::key(Key::Down)],
+            TuiAction::Up => vec![K::key(Key::Up)],
+            TuiAction::PageDown => vec![K::key(Key::PageDown)],
+            TuiAction::PageUp => vec![K::key(Key::PageUp)],
+            TuiAction::First => vec![K::key(Key::Home)],
+            TuiAction::Last => vec![K::key(Key::End)],
+            TuiAction::Select => vec![K::key(Key::Enter)],
+            TuiAction::Cancel => vec![K::key(Key::Esc)],
+            TuiAction::Reload => vec![K::char('r')],
+            TuiAction::Test => vec![K::char('t')],
+            TuiAction::TestFiltered => vec![K::char('v')],
+            TuiAction::TestAll => vec![K::char('a')],
+            TuiAction::Sort => vec![K::char('s')],
+            TuiAction::ReverseSort => vec![K::char('S')],
+            TuiAction::AliveOnly => vec![K::char('o')],
+            TuiAction::Protocol => vec![K::char('p')],
+            TuiAction::Favourites => vec![K::char('f')],
+            TuiAction::Favourite => vec![K::char('*')],
+            TuiAction::Search => vec![K::char('/')],
+            TuiAction::Details => vec![K::char('i')],
+            TuiAction::Copy => vec![K::char('c')],
+            TuiAction::Add => vec![K::char('a')],
+            TuiAction::Remove => vec![K::char('d')],
+            TuiAction::Level => vec![K::char('l')],
+        }
+    }
+
+    /// Keys `keymap "vim"` puts in front of the default ones.
+    fn vim_keys(self) -> Vec<KeyBinding> {
+        use KeyBinding as K;
+
+        match self {
+            TuiAction::Down => vec![K::char('j')],
+            TuiAction::Up => vec![K::char('k')],
+            TuiAction::PageDown => vec![K::ctrl('d')],
+            TuiAction::PageUp => vec![K::ctrl('u')],
+            TuiAction::First => vec![K::char('g')],
+            TuiAction::Last => vec![K::char('G')],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A key with or without Ctrl. Shift is part of the character: `G`, not
+/// `shift-g`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KeyBinding {
+    pub key: Key,
+    pub ctrl: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Esc,
+    Tab,
+    BackTab,
+    Backspace,
+    Delete,
+    F(u8),
+}
+
+impl KeyBinding {
+    fn key(key: Key) -> Self {
+        Self { key, ctrl: false }
+    }
+
+    fn char(c: char) -> Self {
+        Self {
+            key: Key::Char(c),
+            ctrl: false,
+        }
+    }
+
+    fn ctrl(c: char) -> Self {
+        Self {
+            key: Key::Char(c),
+            ctrl: true,
+        }
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    /// Parses a character (`j`, `G`, `/`), `space`, a key name such as
+    /// `down`, `pagedown` or `f5`, optionally prefixed with `ctrl-`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ctrl, name) = match s.get(..5) {
+            Some(prefix) if s.len() > 5 && prefix.eq_ignore_ascii_case("ctrl-") => (true, &s[5..]),
+            _ => (false, s),
+        };
+
+        let mut chars = name.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(c), None) => Key::Char(c),
+            _ => match name.to_lowercase().as_str() {
+                "space" => Key::Char(' '),
+                "up" => Key::Up,
+                "down" => Key::Down,
+                "left" => Key::Left,
+                "right" => Key::Right,
+                "pageup" | "pgup" => Key::PageUp,
+                "pagedown" | "pgdn" => Key::PageDown,
+                "home" => Key::Home,
+                "end" => Key::End,
+                "enter" => Key::Enter,
+                "esc" => Key::Esc,
+                "tab" => Key::Tab,
+                "backtab" | "shift-tab" => Key::BackTab,
+                "backspace" => Key::Backspace,
+                "delete" => Key::Delete,
+                lower => match lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => Key::F(n),
+                    _ => return Err(format!("unknown key '{}'", s)),
+                },
+            },
+        };
+        Ok(Self { key, ctrl })
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    /// The key as the help bar shows it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl-")?;
+        }
+        match self.key {
+            Key::Char(' ') => write!(f, "Space"),
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Up => write!(f, "↑"),
+            Key::Down => write!(f, "↓"),
+            Key::Left => write!(f, "←"),
+            Key::Right => write!(f, "→"),
+            Key::PageUp => write!(f, "PgUp"),
+            Key::PageDown => write!(f, "PgDn"),
+            Key::Home => write!(f, "Home"),
+            Key::End => write!(f, "End"),
+            Key::Enter => write!(f, "Enter"),
+            Key::Esc => write!(f, "Esc"),
+            Key::Tab => write!(f, "Tab"),
+            Key::BackTab => write!(f, "Shift-Tab"),
+            Key::Backspace => write!(f, "Backspace"),
+            Key::Delete => write!(f, "Del"),
+            Key::F(n) => write!(f, "F{}", n),
+        }
+    }
+}
+
+impl TuiConfig {
+    pub(crate) fn from_node(node: &KdlNode) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut keys: Vec<(TuiAction, Vec<KeyBinding>)> = Vec::new();
+
+        for child in &node.children {
+            match child.name.as_str() {
+                "theme" => {
+                    config.theme = match string_arg(child)?.as_str() {
+                        "dark" => ThemeName::Dark,
+                        "light" => ThemeName::Light,
+                        "mono" => ThemeName::Mono,
+                        _ => return Err(node_error(child, "theme must be dark, light or mono")),
+                    }
+                }
+                "keymap" => match string_arg(child)?.as_str() {
+                    "default" => {}
+                    "vim" => {
+                        for (action, bindings) in &mut config.keys {
+                            bindings.splice(0..0, action.vim_keys());
+                        }
+                    }
+                    _ => return Err(node_error(child, "keymap must be default or vim")),
+                },
+                "colors" => {
+                    for color in &child.children {
+                        let slot = ThemeSlot::ALL
+                            .into_iter()
+                            .find(|slot| slot.name() == color.name)
+                            .ok_or_else(|| {
+                                node_error(color, format!("unknown colour '{}'", color.name))
+                            })?;
+                        let value = string_arg(color)?;
+                        ratatui::style::Color::from_str(&value).map_err(|_| {
+                            node_error(
+                                color,
+                                format!(
+                                    "'{}' is not a colour name, 256-colour index or #rrggbb",
+                                    value
+                                ),
+                            )
+                        })?;
+                        config.colors.push((slot, value));
+                    }
+                }
+                "keys" => {
+                    for binding in &child.children {
+                        let action = TuiAction::ALL
+                            .into_iter()
+                            .find(|action| action.name() == binding.name)
+                            .ok_or_else(|| {
+                                node_error(binding, format!("unknown action '{}'", binding.name))
+                            })?;
+                        let bindings = string_args(binding)?
+                            .iter()
+                            .map(|key| key.parse())
+                            .collect::<Result<Vec<KeyBinding>, String>>()
+                            .map_err(|e| node_error(binding, e))?;
+                        keys.push((action, bindings));
+                    }
+                }
+                other => {
+                    return Err(node_error(child, format!("unknown tui option '{}'", other)));
+                }
+            }
+        }
+
+        // `keys` replaces the bindings of an action whatever the keymap.
+        for (action, bindings) in keys {
+            if let Some(entry) = config.keys.iter_mut().find(|(a, _)| *a == action) {
+                entry.1 = bindings;
+            }
+        }
+
+        config
+            .check_global_keys()
+            .map_err(|e| node_error(node, e))?;
+        Ok(config)
+    }
+
+    /// Keys of global actions and the tab digits act on every tab, so they
+    /// cannot also be bound to something else.
+    fn check_global_keys(&self) -> Result<(), String> {
+        for (action, bindings) in &self.keys {
+            for binding in bindings {
+                if let Key::Char('1'..='5') = binding.key
+                    && !binding.ctrl
+                {
+                    return Err(format!(
+                        "{} is bound to {}, but 1-5 switch tabs",
+                        binding,
+                        action.name()
+                    ));
+                }
+
+                let Some((other, _)) = self
+                    .keys
+                    .iter()
+                    .find(|(other, keys)| other != action && keys.contains(binding))
+                else {
+                    continue;
+                };
+                if action.is_global() || other.is_global() {
+                    return Err(format!(
+                        "{} is bound to both {} and {}",
+                        binding,
+                        action.name(),
+                        other.name()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl;
+
+    fn tui(source: &str) -> Result<TuiConfig, String> {
+        TuiConfig::from_node(&kdl::parse(source).unwrap()[0])
+    }
+
+    fn keys_of(config: &TuiConfig, action: TuiAction) -> Vec<String> {
+        config
+            .keys
+            .iter()
+            .find(|(a, _)| *a == action)
+            .unwrap()
+            .1
+            .iter()
+            .map(|binding| binding.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn key_bindings() {
+        let cases = [
+            ("j", Key::Char('j'), false),
+            ("G", Key::Char('G'), false),
+            ("/", Key::Char('/'), false),
+            ("space", Key::Char(' '), false),
+            ("Down", Key::Down, false),
+            ("pgdn", Key::PageDown, false),
+            ("shift-tab", Key::BackTab, false),
+            ("f5", Key::F(5), false),
+            ("F12", Key::F(12), false),
+            ("ctrl-d", Key::Char('d'), true),
+            ("Ctrl-Space", Key::Char(' '), true),
+            ("ctrl-f1", Key::F(1), true),
+        ];
+        for (source, key, ctrl) in cases {
+            assert_eq!(source.parse(), Ok(KeyBinding { key, ctrl }), "{}", source);
+        }
+
+        for source in ["", "ctrl-", "f0", "f13", "hyper-x", "ctrl-ctrl-x", "jk"] {
+            assert_eq!(
+                source.parse::<KeyBinding>(),
+                Err(format!("unknown key '{}'", source))
+            );
+        }
+
+        let shown: Vec<String> = ["space", "ctrl-u", "pagedown", "backtab", "f3"]
+            .iter()
+            .map(|s| s.parse::<KeyBinding>().unwrap().to_string())
+            .collect();
+        assert_eq!(shown, ["Space", "Ctrl-u", "PgDn", "Shift-Tab", "F3"]);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = tui("tui").unwrap();
+        assert_eq!(config.theme, ThemeName::Dark);
+        assert!(config.colors.is_empty());
+        assert_eq!(config.keys.len(), TuiAction::ALL.len());
+        assert_eq!(keys_of(&config, TuiAction::Down), ["↓"]);
+        assert_eq!(keys_of(&config, TuiAction::Favourite), ["*"]);
+        // `a` is both test-all and add: neither is global.
+        assert_eq!(keys_of(&config, TuiAction::TestAll), ["a"]);
+        assert_eq!(keys_of(&config, TuiAction::Add), ["a"]);
+    }
+
+    #[test]
+    fn keymaps_and_keys() {
+        let config = tui(r#"tui { keymap "vim"; }"#).unwrap();
+        assert_eq!(keys_of(&config, TuiAction::Down), ["j", "↓"]);
+        assert_eq!(keys_of(&config, TuiAction::PageUp), ["Ctrl-u", "PgUp"]);
+        assert_eq!(keys_of(&config, TuiAction::Last), ["G", "End"]);
+        assert_eq!(keys_of(&config, TuiAction::Quit), ["q"]);
+
+        // `keys` wins over the preset, before or after it.
+        for source in [
+            r#"tui {
+                keymap "vim"
+                keys { down "n" "ctrl-n"; quit "Q"; }
+            }"#,
+            r#"tui {
+                keys { down "n" "ctrl-n"; quit "Q"; }
+                keymap "vim"
+            }"#,
+        ] {
+            let config = tui(source).unwrap();
+            assert_eq!(keys_of(&config, TuiAction::Down), ["n", "Ctrl-n"]);
+            assert_eq!(keys_of(&config, TuiAction::Up), ["k", "↑"]);
+            assert_eq!(keys_of(&config, TuiAction::Quit), ["Q"]);
+        }
+
+        let config = tui(r#"tui { keymap "default"; }"#).unwrap();
+        assert_eq!(keys_of(&config, TuiAction::Down), ["↓"]);
+    }
+
+    #[test]
+    fn themes_and_colours() {
+        let config = tui(r##"tui {
+                theme "light"
+                colors {
+                    accent "#ff8800"
+                    muted "244"
+                    good "lightgreen"
+                }
+            }"##)
+        .unwrap();
+        assert_eq!(config.theme, ThemeName::Light);
+        assert_eq!(
+            config.colors,
+            [
+                (ThemeSlot::Accent, String::from("#ff8800")),
+                (ThemeSlot::Muted, String::from("244")),
+                (ThemeSlot::Good, String::from("lightgreen")),
+            ]
+        );
+        assert_eq!(
+            tui(r#"tui { theme "mono"; }"#).unwrap().theme,
+            ThemeName::Mono
+        );
+    }
+
+    #[test]
+    fn rejections() {
+        let cases = [
+            (
+                "tui {\n    theme \"solarized\"\n}",
+                "line 2: theme must be dark, light or mono",
+            ),
+            (
+                "tui {\n    keymap \"emacs\"\n}",
+                "line 2: keymap must be default or vim",
+            ),
+            (
+                "tui {\n    colors {\n        border \"red\"\n    }\n}",
+                "line 3: unknown colour 'border'",
+            ),
+            (
+                "tui {\n    colors {\n        accent \"#ff88\"\n    }\n}",
+                "line 3: '#ff88' is not a colour name, 256-colour index or #rrggbb",
+            ),
+            (
+                "tui {\n    colors {\n        accent \"256\"\n    }\n}",
+                "line 3: '256' is not a colour name, 256-colour index or #rrggbb",
+            ),
+            (
+                "tui {\n    keys {\n        jump \"j\"\n    }\n}",
+                "line 3: unknown action 'jump'",
+            ),
+            (
+                "tui {\n    keys {\n        down \"j\" \"hyper-j\"\n    }\n}",
+                "line 3: unknown key 'hyper-j'",
+            ),
+            (
+                "tui {\n    mouse #false\n}",
+                "line 2: unknown tui option 'mouse'",
+            ),
+            // Global keys and tab digits.
+            (
+                "tui {\n    keys {\n        search \"1\"\n    }\n}",
+                "line 1: 1 is bound to search, but 1-5 switch tabs",
+            ),
+            (
+                "tui {\n    keys {\n        reload \"q\"\n    }\n}",
+                "line 1: q is bound to both quit and reload",
+            ),
+            (
+                "tui {\n    keys {\n        next-tab \"j\"\n    }\n    keymap \"vim\"\n}",
+                "line 1: j is bound to both next-tab and down",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let error = tui(source).unwrap_err();
+            assert!(
+                error.starts_with(&format!("luxnulla.kdl: {}", expected)),
+                "{:?}: {}",
+                source,
+                error
+            );
+        }
+
+        // Ctrl with a digit does not switch tabs, and actions of one tab may
+        // share keys.
+        let config =
+            tui("tui {\n    keys {\n        search \"ctrl-1\"\n        sort \"a\"\n    }\n}")
+                .unwrap();
+        assert_eq!(keys_of(&config, TuiAction::Search), ["Ctrl-1"]);
+    }
+}
@@ -0,0 +1,714 @@
+//! A small KDL reader covering what `luxnulla.kdl` needs: nodes with
+//! arguments, `key=value` properties, children blocks, strings (plain and
+//! raw), numbers, booleans, null, comments and `/-` slashdash.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KdlValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+}
+
+impl KdlValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KdlValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            KdlValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            KdlValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KdlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KdlValue::String(s) => write!(f, "{:?}", s),
+            KdlValue::Int(i) => write!(f, "{}", i),
+            KdlValue::Float(x) => write!(f, "{}", x),
+            KdlValue::Bool(b) => write!(f, "{}", b),
+            KdlValue::Null => write!(f, "null"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KdlNode {
+    pub name: String,
+    pub args: Vec<KdlValue>,
+    pub props: Vec<(String, KdlValue)>,
+    pub children: Vec<KdlNode>,
+    /// 1-based line the node starts on, for error messages.
+    pub line: usize,
+}
+
+impl KdlNode {
+    pub fn arg(&self, index: usize) -> Option<&KdlValue> {
+        self.args.get(index)
+    }
+
+    /// Returns the last value of property `key`, as KDL says later ones win.
+    pub fn prop(&self, key: &str) -> Option<&KdlValue> {
+        self.props
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&KdlNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KdlNode> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KdlError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for KdlError {}
+
+pub fn parse(input: &str) -> Result<Vec<KdlNode>, KdlError> {
+    let mut parser = KdlParser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let nodes = parser.nodes()?;
+
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected '}'"));
+    }
+
+    Ok(nodes)
+}
+
+struct KdlParser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl KdlParser {
+    fn error(&self, message: impl Into<String>) -> KdlError {
+        KdlError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    /// Skips spaces and comments on the current line, including `\`
+    /// line continuations. Returns whether anything was skipped.
+    fn inline_space(&mut self) -> Result<bool, KdlError> {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(c) if c != '\n' && c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('\\') => {
+                    self.bump();
+                    self.inline_space()?;
+                    if self.starts_with("//") {
+                        self.line_comment();
+                    }
+                    match self.peek() {
+                        Some('\n') => {
+                            self.bump();
+                        }
+                        None => {}
+                        _ => return Err(self.error("expected newline after '\\'")),
+                    }
+                }
+                Some('/') if self.peek_at(1) == Some('*') => self.block_comment()?,
+                _ => break,
+            }
+        }
+        Ok(self.pos > start)
+    }
+
+    fn line_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    fn block_comment(&mut self) -> Result<(), KdlError> {
+        let line = self.line;
+        self.pos += 2;
+        let mut depth = 1;
+        while depth > 0 {
+            if self.starts_with("/*") {
+                self.pos += 2;
+                depth += 1;
+            } else if self.starts_with("*/") {
+                self.pos += 2;
+                depth -= 1;
+            } else if self.bump().is_none() {
+                return Err(KdlError {
+                    line,
+                    message: "unterminated block comment".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips whitespace, newlines, `;` and comments between nodes.
+    fn node_space(&mut self) -> Result<(), KdlError> {
+        loop {
+            self.inline_space()?;
+            match self.peek() {
+                Some('\n') | Some(';') => {
+                    self.bump();
+                }
+                Some('/') if self.peek_at(1) == Some('/') => self.line_comment(),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn nodes(&mut self) -> Result<Vec<KdlNode>, KdlError> {
+        let mut nodes = Vec::new();
+        loop {
+            self.node_space()?;
+            match self.peek() {
+                None | Some('}') => return Ok(nodes),
+                _ => {
+                    let skip = self.slashdash()?;
+                    let node = self.node()?;
+                    if !skip {
+                        nodes.push(node);
+                    }
+                }
+            }
+        }
+    }
+
+    fn slashdash(&mut self) -> Result<bool, KdlError> {
+        if self.starts_with("/-") {
+            self.pos += 2;
+            self.node_space()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn node(&mut self) -> Result<KdlNode, KdlError> {
+        let line = self.line;
+        self.type_annotation()?;
+        let name = self.string_or_identifier()?;
+
+        let mut node = KdlNode {
+            name,
+            args: Vec::new(),
+            props: Vec::new(),
+            children: Vec::new(),
+            line,
+        };
+
+        loop {
+            let spaced = self.inline_space()?;
+            match self.peek() {
+                None | Some('\n') | Some(';') | Some('}') => break,
+                Some('/') if self.peek_at(1) == Some('/') => {
+                    self.line_comment();
+                    break;
+                }
+                _ => {}
+            }
+
+            let skip = self.slashdash()?;
+
+            if self.peek() == Some('{') {
+                self.bump();
+                let children = self.nodes()?;
+                if self.bump() != Some('}') {
+                    return Err(KdlError {
+                        line,
+                        message: format!("unclosed children block of '{}'", node.name),
+                    });
+                }
+                if !skip {
+                    node.children = children;
+                }
+                continue;
+            }
+
+            if !spaced && !skip {
+                return Err(self.error("expected whitespace between node entries"));
+            }
+
+            self.type_annotation()?;
+            let value = self.value()?;
+
+            if self.peek() == Some('=') {
+                let key = match value {
+                    Entry::Key(key) => key,
+                    Entry::Value(KdlValue::String(key)) => key,
+                    _ => return Err(self.error("property key must be a string")),
+                };
+                self.bump();
+                self.type_annotation()?;
+                let value = match self.value()? {
+                    Entry::Value(value) => value,
+                    Entry::Key(ident) => {
+                        return Err(self.error(format!("bare identifier '{}' as value", ident)));
+                    }
+                };
+                if !skip {
+                    node.props.push((key, value));
+                }
+            } else {
+                let value = match value {
+                    Entry::Value(value) => value,
+                    Entry::Key(ident) => {
+                        return Err(self.error(format!("bare identifier '{}' as value", ident)));
+                    }
+                };
+                if !skip {
+                    node.args.push(value);
+                }
+            }
+        }
+
+        Ok(node)
+    }
+
+    fn type_annotation(&mut self) -> Result<(), KdlError> {
+        if self.peek() == Some('(') {
+            self.bump();
+            self.string_or_identifier()?;
+            if self.bump() != Some(')') {
+                return Err(self.error("expected ')' after type annotation"));
+            }
+        }
+        Ok(())
+    }
+
+    fn string_or_identifier(&mut self) -> Result<String, KdlError> {
+        match self.peek() {
+            Some('"') => self.string(),
+            Some('r') if matches!(self.peek_at(1), Some('"') | Some('#')) => self.raw_string(),
+            _ => {
+                let ident = self.identifier();
+                if ident.is_empty() {
+                    Err(self.error("expected a node name"))
+                } else {
+                    Ok(ident)
+                }
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "\\/(){}<>;[]=,\"".contains(c) {
+                break;
+            }
+            out.push(c);
+            self.bump();
+        }
+        out
+    }
+
+    fn value(&mut self) -> Result<Entry, KdlError> {
+        match self.peek() {
+            Some('"') => Ok(Entry::Value(KdlValue::String(self.string()?))),
+            Some('r') if matches!(self.peek_at(1), Some('"') | Some('#')) => {
+                Ok(Entry::Value(KdlValue::String(self.raw_string()?)))
+            }
+            Some(c)
+                if c.is_ascii_digit()
+                    || ((c == '-' || c == '+')
+                        && self.peek_at(1).is_some_and(|d| d.is_ascii_digit())) =>
+            {
+                self.number().map(Entry::Value)
+            }
+            _ => {
+                let ident = self.identifier();
+                match ident.trim_start_matches('#') {
+                    "" => Err(self.error("expected a value")),
+                    "true" => Ok(Entry::Value(KdlValue::Bool(true))),
+                    "false" => Ok(Entry::Value(KdlValue::Bool(false))),
+                    "null" => Ok(Entry::Value(KdlValue::Null)),
+                    _ => Ok(Entry::Key(ident)),
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, KdlError> {
+        let line = self.line;
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(KdlError {
+                        line,
+                        message: "unterminated string".to_string(),
+                    });
+                }
+                Some('"') => return Ok(out),
+                Some('\\') => match self.bump() {
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('"') => out.push('"'),
+                    Some('u') => out.push(self.unicode_escape()?),
+                    other => {
+                        return Err(
+                            self.error(format!("invalid escape: \\{}", other.unwrap_or(' ')))
+                        );
+                    }
+                },
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, KdlError> {
+        if self.bump() != Some('{') {
+            return Err(self.error("expected '{' in unicode escape"));
+        }
+        let mut hex = String::new();
+        loop {
+            match self.bump() {
+                Some('}') => break,
+                Some(c) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+                _ => return Err(self.error("invalid unicode escape")),
+            }
+        }
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn raw_string(&mut self) -> Result<String, KdlError> {
+        let line = self.line;
+        self.bump();
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            self.bump();
+            hashes += 1;
+        }
+        if self.bump() != Some('"') {
+            return Err(self.error("expected '\"' in raw string"));
+        }
+
+        let terminator: String = std::iter::once('"')
+            .chain(std::iter::repeat_n('#', hashes))
+            .collect();
+        let mut out = String::new();
+        loop {
+            if self.starts_with(&terminator) {
+                self.pos += terminator.chars().count();
+                return Ok(out);
+            }
+            match self.bump() {
+                Some(c) => out.push(c),
+                None => {
+                    return Err(KdlError {
+                        line,
+                        message: "unterminated raw string".to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<KdlValue, KdlError> {
+        let mut raw = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "\\/(){}<>;[]=,\"".contains(c) {
+                break;
+            }
+            raw.push(c);
+            self.bump();
+        }
+
+        let cleaned = raw.replace('_', "");
+        let (negative, digits) = match cleaned.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, cleaned.trim_start_matches('+')),
+        };
+
+        let radix = match digits.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+
+        let parsed = match radix {
+            Some(radix) => i64::from_str_radix(&digits[2..], radix)
+                .ok()
+                .map(|i| KdlValue::Int(if negative { -i } else { i })),
+            None if digits.contains(['.', 'e', 'E']) => {
+                cleaned.parse::<f64>().ok().map(KdlValue::Float)
+            }
+            None => cleaned.parse::<i64>().ok().map(KdlValue::Int),
+        };
+
+        parsed.ok_or_else(|| self.error(format!("invalid number: {}", raw)))
+    }
+}
+
+enum Entry {
+    /// A bare identifier, only valid as a property key.
+    Key(String),
+    Value(KdlValue),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn one(input: &str) -> KdlNode {
+        let mut nodes = parse(input).unwrap();
+        assert_eq!(nodes.len(), 1, "{:?}", nodes);
+        nodes.remove(0)
+    }
+
+    fn error(input: &str) -> KdlError {
+        parse(input).unwrap_err()
+    }
+
+    fn string(value: &str) -> KdlValue {
+        KdlValue::String(value.to_string())
+    }
+
+    #[test]
+    fn arguments_and_properties() {
+        let node = one(r#"inbound "socks" port=1080 listen="127.0.0.1" port=1081"#);
+        assert_eq!(node.name, "inbound");
+        assert_eq!(node.args, vec![string("socks")]);
+        assert_eq!(node.prop("listen"), Some(&string("127.0.0.1")));
+        // Later properties win
+        assert_eq!(node.prop("port"), Some(&KdlValue::Int(1081)));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let node = one(r#"s "a\"b" "tab\there" "line\n" "back\\slash" "\/" "\u{1F1E9}\u{e9}""#);
+        assert_eq!(
+            node.args,
+            vec![
+                string("a\"b"),
+                string("tab\there"),
+                string("line\n"),
+                string("back\\slash"),
+                string("/"),
+                string("🇩é"),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_strings() {
+        let node = one(r####"s r"C:\path" r#"say "hi""# r##"a "# b"##"####);
+        assert_eq!(
+            node.args,
+            vec![
+                string(r"C:\path"),
+                string(r#"say "hi""#),
+                string(r##"a "# b"##)
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_names_and_type_annotations() {
+        let node = one(r#"(tag)"node name" (u16)8080 key=(str)"v""#);
+        assert_eq!(node.name, "node name");
+        assert_eq!(node.args, vec![KdlValue::Int(8080)]);
+        assert_eq!(node.prop("key"), Some(&string("v")));
+    }
+
+    #[test]
+    fn numbers() {
+        let node = one("n 42 -7 +3 1_000 0xff 0o17 0b101 -0x10 1.5 -2.5e3 1e2");
+        assert_eq!(
+            node.args,
+            vec![
+                KdlValue::Int(42),
+                KdlValue::Int(-7),
+                KdlValue::Int(3),
+                KdlValue::Int(1000),
+                KdlValue::Int(255),
+                KdlValue::Int(15),
+                KdlValue::Int(5),
+                KdlValue::Int(-16),
+                KdlValue::Float(1.5),
+                KdlValue::Float(-2500.0),
+                KdlValue::Float(100.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn bools_and_null() {
+        let node = one("b true false null #true #false #null");
+        assert_eq!(
+            node.args,
+            vec![
+                KdlValue::Bool(true),
+                KdlValue::Bool(false),
+                KdlValue::Null,
+                KdlValue::Bool(true),
+                KdlValue::Bool(false),
+                KdlValue::Null,
+            ]
+        );
+    }
+
+    #[test]
+    fn children() {
+        let nodes = parse(
+            "routing {\n    rule \"direct\" {\n        domain \"lan\"\n    }\n    rule \"proxy\"\n}\nafter; last\n",
+        )
+        .unwrap();
+        assert_eq!(
+            nodes.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(),
+            ["routing", "after", "last"]
+        );
+        let routing = &nodes[0];
+        assert_eq!(routing.children_named("rule").count(), 2);
+        let rule = routing.child("rule").unwrap();
+        assert_eq!(rule.line, 2);
+        assert_eq!(rule.child("domain").unwrap().args, vec![string("lan")]);
+        assert_eq!(rule.child("domain").unwrap().line, 3);
+        assert_eq!(nodes[1].line, 7);
+        assert!(parse("empty {}").unwrap()[0].children.is_empty());
+        assert_eq!(one("a { b; c }").children.len(), 2);
+    }
+
+    #[test]
+    fn comments() {
+        let nodes = parse(
+            "// leading\na 1 // trailing\n/* block\n /* nested */ still */ b /* inline */ 2\nc \"//not a comment\"\n",
+        )
+        .unwrap();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(nodes[0].args, vec![KdlValue::Int(1)]);
+        assert_eq!(nodes[1].name, "b");
+        assert_eq!(nodes[1].args, vec![KdlValue::Int(2)]);
+        assert_eq!(nodes[1].line, 4);
+        assert_eq!(nodes[2].args, vec![string("//not a comment")]);
+    }
+
+    #[test]
+    fn slashdash() {
+        let nodes = parse(
+            "/-skipped 1 { child }\nkept /-2 3 /-key=4 {\n  /-gone\n  here\n}\nlast /-{ x }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            nodes.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(),
+            ["kept", "last"]
+        );
+        assert_eq!(nodes[0].args, vec![KdlValue::Int(3)]);
+        assert_eq!(nodes[0].prop("key"), None);
+        assert_eq!(
+            nodes[0]
+                .children
+                .iter()
+                .map(|n| n.name.as_str())
+                .collect::<Vec<_>>(),
+            ["here"]
+        );
+        assert!(nodes[1].children.is_empty());
+    }
+
+    #[test]
+    fn line_continuation() {
+        let node = one("a 1 \\ // more\n  2");
+        assert_eq!(node.args, vec![KdlValue::Int(1), KdlValue::Int(2)]);
+    }
+
+    #[test]
+    fn error_positions() {
+        let cases = [
+            ("a 1\nb \"open\n", 2, "unterminated string"),
+            ("a\n\nb {\n  c\n", 3, "unclosed children block of 'b'"),
+            ("a\n}\n", 2, "unexpected '}'"),
+            ("a\nb \"\\q\"\n", 2, "invalid escape: \\q"),
+            ("a\n\nb value\n", 3, "bare identifier 'value' as value"),
+            ("a 1\n/* open\n\n", 2, "unterminated block comment"),
+            ("a r#\"open\n", 1, "unterminated raw string"),
+            ("a\nb 0xzz\n", 2, "invalid number: 0xzz"),
+            (
+                "a\nb \"x\"\"y\"\n",
+                2,
+                "expected whitespace between node entries",
+            ),
+            ("a 1 \\ 2\n", 1, "expected newline after '\\'"),
+        ];
+        for (input, line, message) in cases {
+            let e = error(input);
+            assert_eq!((e.line, e.message.as_str()), (line, message), "{:?}", input);
+        }
+        assert_eq!(error("a\n}").to_string(), "line 2: unexpected '}'");
+    }
+}
@@ -1,12 +1,20 @@
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub mod config;
+pub mod kdl;
 
 pub const CONFIG_DIR: &str = "luxnulla";
 
 pub const LUXNULLA_CONFIG_FILE: &str = "luxnulla.kdl";
 pub const XRAY_CONFIG_FILE: &str = "xray.json";
+pub const XRAY_LOG_FILE: &str = "xray.log";
+pub const STATE_FILE: &str = "state.json";
+pub const NODES_FILE: &str = "nodes.json";
 
 pub const SOCKET_NAME: &str = "luxnulla-core.sock";
 pub const EDITOR_NAME: &str = "zeditor";
+pub const XRAY_BINARY: &str = "xray";
 
 #[derive(Deserialize, Serialize)]
 pub enum CommandRequest {
@@ -15,6 +23,80 @@ pub enum CommandRequest {
     Start,
     Status,
     Restart,
+    Refresh,
+    Logs {
+        follow: bool,
+        lines: usize,
+        level: Option<LogLevel>,
+    },
+    ListNodes,
+    SelectNode {
+        id: String,
+    },
+    SetFavourite {
+        id: String,
+        favourite: bool,
+    },
+    /// Adds (`present`) or removes a user tag, see `tag` in group definitions.
+    SetTag {
+        id: String,
+        tag: String,
+        present: bool,
+    },
+    /// Routes traffic through an xray balancer over the members of the
+    /// `group` named `name` in luxnulla.kdl.
+    SelectGroup {
+        name: String,
+    },
+    /// Sends proxied traffic through the `chain` named `name`.
+    SelectChain {
+        name: String,
+    },
+    /// Replaces the routing presets of luxnulla.kdl until the next call;
+    /// `None` goes back to the ones in the file.
+    SetRoutingPreset {
+        presets: Option<Vec<config::RoutingPreset>>,
+    },
+    /// Installs or removes the nftables rules of the `tproxy` section.
+    Tproxy {
+        action: TproxyAction,
+    },
+    /// Measures TCP connect and TLS handshake time. Empty `ids` probes
+    /// every node.
+    Probe {
+        ids: Vec<String>,
+    },
+    /// Requests the configured test URL through each node using a temporary
+    /// xray instance. Empty `ids` tests every node; `chain:<name>` tests a
+    /// whole chain.
+    UrlTest {
+        ids: Vec<String>,
+    },
+    /// Keeps the connection open and streams every `DaemonEvent`.
+    Events,
+    /// Every parsed field, the share link and the latency history of a node.
+    NodeDetails {
+        id: String,
+    },
+    /// Every subscription of luxnulla.kdl with what its last refresh found.
+    ListSubscriptions,
+    /// Appends a `subscription` to luxnulla.kdl. Without a `name` the daemon
+    /// derives one from the URL host, like the config parser does.
+    AddSubscription {
+        url: String,
+        name: Option<String>,
+    },
+    /// Deletes the `subscription` named `name` from luxnulla.kdl.
+    RemoveSubscription {
+        name: String,
+    },
+    /// Routing rules of the running xray config.
+    Routing,
+    /// Traffic totals and recent rates from xray's stats API. Asking keeps
+    /// the daemon sampling for a while.
+    Traffic,
+    /// Keys and colours from the `tui` section of luxnulla.kdl.
+    TuiConfig,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -27,6 +109,18 @@ pub enum CommandResponse {
 pub enum OkCommandResponse {
     Message(String),
     GetSubs(Vec<String>),
+    Log(LogLine),
+    Nodes(Vec<NodeInfo>),
+    Refreshed(RefreshReport),
+    Probe { id: String, result: ProbeResult },
+    UrlTest { id: String, result: UrlTestResult },
+    Event(DaemonEvent),
+    NodeDetails(NodeDetails),
+    Subscriptions(Vec<SubscriptionInfo>),
+    Status(Box<DaemonStatus>),
+    Routing(RoutingInfo),
+    Traffic(TrafficStats),
+    TuiConfig(config::TuiConfig),
 }
 
 #[derive(Deserialize, Serialize)]
@@ -34,3 +128,369 @@ pub enum ErrorCommandResponse {
     Message(String),
     GetSubs(String),
 }
+
+/// Things the daemon did on its own that clients may want to show.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum DaemonEvent {
+    HealthCheckFailed {
+        id: String,
+        failures: u32,
+        error: String,
+    },
+    NodeSwitched {
+        from: Option<String>,
+        to: String,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for DaemonEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonEvent::HealthCheckFailed {
+                id,
+                failures,
+                error,
+            } => write!(
+                f,
+                "health check of {} failed ({} in a row): {}",
+                id, failures, error
+            ),
+            DaemonEvent::NodeSwitched { from, to, reason } => match from {
+                Some(from) => write!(f, "switched from {} to {}: {}", from, to, reason),
+                None => write!(f, "switched to {}: {}", to, reason),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TproxyAction {
+    Enable,
+    Disable,
+    /// Returns the ruleset and routing commands without applying them.
+    DryRun,
+}
+
+impl FromStr for TproxyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "on" | "enable" => Ok(TproxyAction::Enable),
+            "off" | "disable" => Ok(TproxyAction::Disable),
+            "dry-run" => Ok(TproxyAction::DryRun),
+            _ => Err(format!(
+                "unknown tproxy action: {}. Expected on, off or dry-run.",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl LogLevel {
+    /// Extracts the level from an xray log line such as
+    /// `2025/01/01 12:00:00 [Warning] core: ...`.
+    pub fn from_xray_line(line: &str) -> Option<Self> {
+        let start = line.find('[')?;
+        let end = start + line[start..].find(']')?;
+        line[start + 1..end].parse().ok()
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warning" | "warn" => Ok(LogLevel::Warning),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!(
+                "unknown log level: {}. Expected debug, info, warning or error.",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub level: LogLevel,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NodeInfo {
+    /// Stable fingerprint of the node, see `ProxyConfig::fingerprint`.
+    pub id: String,
+    pub protocol: String,
+    pub address: String,
+    pub port: u16,
+    pub name: String,
+    pub active: bool,
+    pub favourite: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Names of the subscriptions that list this node.
+    pub subscriptions: Vec<String>,
+    /// Latency of the most recent probe, `None` if untested or unreachable.
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NodeDetails {
+    pub info: NodeInfo,
+    /// Parsed fields of the share link as label and value, in display order.
+    pub fields: Vec<(String, String)>,
+    /// The share link the node was last seen with.
+    pub uri: String,
+    /// Oldest first.
+    pub latency_history: Vec<LatencySample>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LatencySample {
+    pub at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub kind: SampleKind,
+    /// `None` when the node did not answer.
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SampleKind {
+    /// TCP connect plus TLS handshake, see `Probe`.
+    #[default]
+    Connect,
+    /// HTTP request through the node, see `UrlTest`.
+    Url,
+}
+
+impl std::fmt::Display for SampleKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            SampleKind::Connect => "connect",
+            SampleKind::Url => "url",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProbeResult {
+    pub tcp_ms: Option<u32>,
+    /// Only measured for TLS and REALITY nodes.
+    pub tls_ms: Option<u32>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UrlTestResult {
+    /// Time until response headers arrived, `None` if the request failed.
+    pub latency_ms: Option<u32>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    /// Time until the node was ready to carry traffic, `None` if it failed.
+    pub fn latency_ms(&self) -> Option<u32> {
+        if self.error.is_some() {
+            return None;
+        }
+        Some(self.tcp_ms? + self.tls_ms.unwrap_or(0))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshReport {
+    pub subscriptions: Vec<SubscriptionReport>,
+    /// Unique nodes after deduplication.
+    pub nodes: usize,
+    /// Entries dropped because an identical node was already listed.
+    pub duplicates: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubscriptionReport {
+    pub name: String,
+    /// Nodes parsed from this subscription before deduplication.
+    pub nodes: usize,
+    pub error: Option<String>,
+}
+
+impl std::fmt::Display for RefreshReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for sub in &self.subscriptions {
+            match &sub.error {
+                Some(e) => writeln!(f, "{}: error: {}", sub.name, e)?,
+                None => writeln!(f, "{}: {} nodes", sub.name, sub.nodes)?,
+            }
+        }
+        write!(
+            f,
+            "{} unique nodes, {} duplicates collapsed",
+            self.nodes, self.duplicates
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubscriptionInfo {
+    pub name: String,
+    pub url: String,
+    /// Unique nodes currently listed by this subscription.
+    pub nodes: usize,
+    pub usage: Option<SubscriptionUsage>,
+    /// Last time the subscription was downloaded successfully.
+    pub updated: Option<chrono::DateTime<chrono::Utc>>,
+    /// Why the last refresh failed, `None` if it succeeded.
+    pub error: Option<String>,
+}
+
+/// Traffic and expiry the provider reports in the `subscription-userinfo`
+/// header. Byte counts; `total` is 0 for unlimited plans.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SubscriptionUsage {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    pub expire: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SubscriptionUsage {
+    /// Parses a header value such as
+    /// `upload=455727941; download=6174315083; total=1073741824000; expire=1671815872`.
+    pub fn from_header(value: &str) -> Option<Self> {
+        let mut usage = SubscriptionUsage::default();
+        let mut found = false;
+        for pair in value.split(';') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            // Some providers send floats or leave fields empty.
+            let Ok(number) = value.trim().parse::<f64>() else {
+                continue;
+            };
+            let number = number.max(0.0) as u64;
+            match key.trim() {
+                "upload" => usage.upload = number,
+                "download" => usage.download = number,
+                "total" => usage.total = number,
+                "expire" if number > 0 => {
+                    usage.expire = chrono::DateTime::from_timestamp(number as i64, 0)
+                }
+                _ => continue,
+            }
+            found = true;
+        }
+        found.then_some(usage)
+    }
+
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DaemonStatus {
+    /// Pid of the xray the daemon runs, `None` if it is not running.
+    pub xray_pid: Option<u32>,
+    pub selected_node: Option<NodeInfo>,
+    pub selected_group: Option<String>,
+    pub selected_chain: Option<String>,
+    pub nodes: usize,
+    pub subscriptions: usize,
+    /// Presets in effect, including an override from `SetRoutingPreset`.
+    pub routing_presets: Vec<config::RoutingPreset>,
+    pub tproxy: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoutingInfo {
+    pub domain_strategy: Option<String>,
+    /// In the order xray matches them.
+    pub rules: Vec<RuleInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RuleInfo {
+    /// `ruleTag` of the rule, if any.
+    pub tag: Option<String>,
+    /// Outbound tag, or `balancer:<tag>` for balancer rules.
+    pub target: String,
+    /// Match conditions such as `domain: geosite:cn`, one per field.
+    pub conditions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TrafficStats {
+    /// Time between two rate samples.
+    pub interval_ms: u64,
+    /// Everything sent through the selected node, group or chain.
+    pub proxy: TrafficSeries,
+    /// One per inbound, by tag.
+    pub inbounds: Vec<TrafficSeries>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TrafficSeries {
+    pub tag: String,
+    /// Bytes since xray started.
+    pub uplink: u64,
+    pub downlink: u64,
+    /// Bytes per second, oldest first.
+    pub uplink_rates: Vec<u64>,
+    pub downlink_rates: Vec<u64>,
+}
+
+impl TrafficSeries {
+    /// The latest (uplink, downlink) rate, `None` before the second sample.
+    pub fn rate(&self) -> Option<(u64, u64)> {
+        Some((*self.uplink_rates.last()?, *self.downlink_rates.last()?))
+    }
+}